@@ -0,0 +1,24 @@
+//! Ready-to-use `ServiceBroker` implementations
+
+use std::io;
+
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+
+pub mod exec;
+pub mod directory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Random alphanumeric string, for generated tokens and passwords
+pub(crate) fn generate_secret(length: usize) -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(length).collect()
+}
+
+/// Removal of an already missing entry is considered done
+pub(crate) fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result                                                => result,
+    }
+}
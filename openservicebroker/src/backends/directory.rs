@@ -0,0 +1,117 @@
+//! Backend serving directories of the local filesystem.
+//!
+//! Each instance owns `<root>/<instance_id>`, holding its `data` directory and a `tokens`
+//! directory with one access token file per binding. Bindings get credentials with the data
+//! `path`, the `token_file` path and the `token` itself. All operations are synchronous.
+
+use crate::{model, store};
+use crate::service::{Completion, ServiceBroker, ServiceError};
+use super::{generate_secret, ignore_missing};
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use anyhow::Context;
+use async_trait::async_trait;
+use serde_json::json;
+
+const TOKEN_LENGTH: usize = 32;
+
+/// Backend provisioning instances as directories under a root directory
+#[derive(Debug, Clone)]
+pub struct DirectoryBackend {
+    root: PathBuf,
+}
+
+impl DirectoryBackend {
+    /// Opens a root directory, creating it if missing
+    pub fn open<P: Into<PathBuf>>(root: P) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root).with_context(|| format!("Can't create root directory '{}'", root.display()))?;
+        Ok(DirectoryBackend { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn instance_path(&self, instance_id: &model::InstanceId) -> PathBuf {
+        self.root.join(instance_id.as_str())
+    }
+
+    /// Directory handed to applications
+    pub fn data_path(&self, instance_id: &model::InstanceId) -> PathBuf {
+        self.instance_path(instance_id).join("data")
+    }
+
+    pub fn token_path(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> PathBuf {
+        self.instance_path(instance_id).join("tokens").join(binding_id.as_str())
+    }
+}
+
+/// Writes a secret into a file only readable by its owner
+fn write_secret(path: &Path, secret: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(secret.as_bytes())
+}
+
+#[async_trait(?Send)]
+impl ServiceBroker for DirectoryBackend {
+    async fn provision(&self, instance_id: &model::InstanceId, _request: &model::ProvisionRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let path = self.instance_path(instance_id);
+        fs::create_dir_all(path.join("data"))
+            .and_then(|_| fs::create_dir_all(path.join("tokens")))
+            .with_context(|| format!("Can't create directory of instance '{}'", instance_id))?;
+        Ok(Completion::Sync(model::ProvisionResponse::new()))
+    }
+
+    async fn update(&self, instance: &store::InstanceRecord, _request: &model::UpdateRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        if !self.instance_path(instance.instance_id()).is_dir() {
+            return Err(ServiceError::UnprocessableEntity(format!("Directory of instance '{}' is missing", instance.instance_id())).into());
+        }
+        Ok(Completion::Sync(model::ProvisionResponse::new()))
+    }
+
+    async fn deprovision(&self, instance: &store::InstanceRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        ignore_missing(fs::remove_dir_all(self.instance_path(instance.instance_id())))
+            .with_context(|| format!("Can't remove directory of instance '{}'", instance.instance_id()))?;
+        Ok(Completion::Sync(model::OperationResponse::new()))
+    }
+
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        Ok(instance.to_instance_response())
+    }
+
+    async fn bind(&self, instance: &store::InstanceRecord, binding_id: &model::BindingId, _request: &model::BindRequest, _accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        let data_path = self.data_path(instance.instance_id());
+        if !data_path.is_dir() {
+            return Err(ServiceError::UnprocessableEntity(format!("Directory of instance '{}' is missing", instance.instance_id())).into());
+        }
+        let token_path = self.token_path(instance.instance_id(), binding_id);
+        let token = generate_secret(TOKEN_LENGTH);
+        write_secret(&token_path, &token).with_context(|| format!("Can't write token of binding '{}'", binding_id))?;
+
+        let mut response = model::BindingResponse::new();
+        *response.credentials_mut() = Some(json!({
+            "path": data_path,
+            "token_file": token_path,
+            "token": token,
+        }));
+        Ok(Completion::Sync(response))
+    }
+
+    async fn unbind(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        ignore_missing(fs::remove_file(self.token_path(instance.instance_id(), binding.binding_id())))
+            .with_context(|| format!("Can't remove token of binding '{}'", binding.binding_id()))?;
+        Ok(Completion::Sync(model::OperationResponse::new()))
+    }
+
+    async fn fetch_binding(&self, _instance: &store::InstanceRecord, binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        Ok(binding.to_binding_response())
+    }
+}
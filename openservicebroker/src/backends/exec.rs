@@ -0,0 +1,411 @@
+//! Backend delegating operations to a local executable.
+//!
+//! For each operation, the executable is run with the operation name as first argument
+//! (`provision`, `update`, `deprovision`, `bind`, `unbind` or `status`), the request JSON on
+//! stdin, and identifiers as `OSB_*` environment variables. It writes the response JSON on
+//! stdout (empty meaning `{}`) and reports its outcome through its exit code:
+//!
+//! | Code | Outcome                                                          |
+//! |------|------------------------------------------------------------------|
+//! | 0    | Operation has completed                                          |
+//! | 10   | Operation is running, to be polled with `status`                 |
+//! | 2    | `400 Bad Request`, stderr being the description                  |
+//! | 3    | `404 Not Found`                                                  |
+//! | 4    | `409 Conflict`                                                   |
+//! | 5    | `410 Gone`                                                       |
+//! | 6    | `422 AsyncRequired`                                              |
+//! | 7    | `422 ConcurrencyError`                                           |
+//! | 8    | `422 Unprocessable Entity`, stderr being the description         |
+//! | 9    | `501 Not Implemented`, for operations the script doesn't support |
+//! | *    | `500 Internal Server Error`                                      |
+//!
+//! `status` receives `OSB_LAST_OPERATION` and writes a `last_operation` response
+//! (e.g. `{"state": "in progress"}`), `OSB_BINDING_ID` being set when polling a binding.
+//!
+//! Executables running longer than the backend timeout are killed, the operation failing with
+//! `500 Internal Server Error`.
+
+use crate::{model, store};
+use crate::service::{Completion, ServiceBroker, ServiceError};
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix_web::error::BlockingError;
+use actix_web::web;
+use anyhow::Result;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+pub const EXIT_ASYNC: i32 = 10;
+
+/// Raw outcome of an executable run
+struct Output {
+    code: Option<i32>,
+    stdout: Vec<u8>,
+    stderr: String,
+}
+
+impl Output {
+    fn description(&self, default: &str) -> String {
+        match self.stderr.trim() {
+            ""          => default.to_owned(),
+            description => description.to_owned(),
+        }
+    }
+
+    fn error(&self, operation: &str) -> anyhow::Error {
+        match self.code {
+            Some(2) => ServiceError::BadRequest(self.description("Invalid request")).into(),
+            Some(3) => ServiceError::NotFound(self.description("Resource does not exist")).into(),
+            Some(4) => ServiceError::Conflict(self.description("Resource already exists")).into(),
+            Some(5) => ServiceError::Gone.into(),
+            Some(6) => ServiceError::AsyncRequired.into(),
+            Some(7) => ServiceError::ConcurrencyError.into(),
+            Some(8) => ServiceError::UnprocessableEntity(self.description("Request can't be processed")).into(),
+            Some(9) => ServiceError::NotSupported(self.description(&format!("Operation '{}' is not supported", operation))).into(),
+            code    => anyhow!("Script '{}' has failed with code {:?}: {}", operation, code, self.stderr.trim()),
+        }
+    }
+
+    fn response<T: DeserializeOwned>(&self, operation: &str) -> Result<T> {
+        let stdout = String::from_utf8_lossy(&self.stdout);
+        let stdout = match stdout.trim() {
+            ""     => "{}",
+            stdout => stdout,
+        };
+        serde_json::from_str(stdout).with_context(|| format!("Script '{}' has written an invalid response", operation))
+    }
+
+    fn completion<T: DeserializeOwned>(&self, operation: &str) -> Result<Completion<T>> {
+        match self.code {
+            Some(0)          => Ok(Completion::Sync(self.response(operation)?)),
+            Some(EXIT_ASYNC) => Ok(Completion::Async(self.response(operation)?)),
+            _                => Err(self.error(operation)),
+        }
+    }
+
+    fn last_operation(&self) -> Result<model::LastOperation> {
+        match self.code {
+            Some(0) => self.response("status"),
+            _       => Err(self.error("status")),
+        }
+    }
+}
+
+/// Backend running a local executable for each operation
+#[derive(Debug, Clone)]
+pub struct ExecBackend {
+    program: PathBuf,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+/// Reads a child output to its end, in the background
+fn read_to_end<R: Read + Send + 'static>(output: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut content = Vec::new();
+        if let Some(mut output) = output {
+            let _ = output.read_to_end(&mut content);
+        }
+        content
+    })
+}
+
+/// Waits for a child, killing it once timeout has elapsed
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+impl ExecBackend {
+    pub fn new<P: Into<PathBuf>>(program: P) -> Self {
+        ExecBackend {
+            program: program.into(),
+            args: Vec::new(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    pub fn program(&self) -> &PathBuf {
+        &self.program
+    }
+    pub fn program_mut(&mut self) -> &mut PathBuf {
+        &mut self.program
+    }
+
+    /// Arguments passed before the operation name
+    pub fn args(&self) -> &Vec<String> {
+        &self.args
+    }
+    pub fn args_mut(&mut self) -> &mut Vec<String> {
+        &mut self.args
+    }
+
+    /// Maximum duration of a run, 60 seconds by default
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    pub fn timeout_mut(&mut self) -> &mut Duration {
+        &mut self.timeout
+    }
+
+    async fn run(&self, operation: &'static str, env: Vec<(&'static str, String)>, input: Value) -> Result<Output> {
+        let program = self.program.clone();
+        let args = self.args.clone();
+        let timeout = self.timeout;
+        web::block(move || -> Result<Output> {
+            let mut child = Command::new(&program)
+                                    .args(&args)
+                                    .arg(operation)
+                                    .envs(env)
+                                    .stdin(Stdio::piped())
+                                    .stdout(Stdio::piped())
+                                    .stderr(Stdio::piped())
+                                    .spawn()
+                                    .with_context(|| format!("Can't run script '{}'", program.display()))?;
+            // Input is written while outputs are read, as scripts may write before reading
+            let stdin = child.stdin.take();
+            thread::spawn(move || {
+                if let Some(mut stdin) = stdin {
+                    // Scripts may not read their input at all
+                    let _ = stdin.write_all(input.to_string().as_bytes());
+                }
+            });
+            let stdout = read_to_end(child.stdout.take());
+            let stderr = read_to_end(child.stderr.take());
+            // Outputs of a killed script may be held open by its own children, they are left behind
+            let status = wait_timeout(&mut child, timeout)?
+                             .ok_or_else(|| anyhow!("Script '{}' has timed out after {:?}", operation, timeout))?;
+            Ok(Output {
+                code: status.code(),
+                stdout: stdout.join().map_err(|_| anyhow!("Can't read output of script '{}'", operation))?,
+                stderr: String::from_utf8_lossy(&stderr.join().map_err(|_| anyhow!("Can't read errors of script '{}'", operation))?).into_owned(),
+            })
+        }).await.map_err(|error| match error {
+            BlockingError::Error(error) => error,
+            BlockingError::Canceled     => anyhow!("Script '{}' has been canceled", operation),
+        })
+    }
+}
+
+fn instance_env(instance: &store::InstanceRecord) -> Vec<(&'static str, String)> {
+    vec![
+        ("OSB_INSTANCE_ID", instance.instance_id().to_string()),
+        ("OSB_SERVICE_ID", instance.service_id().to_string()),
+        ("OSB_PLAN_ID", instance.plan_id().to_string()),
+    ]
+}
+
+#[async_trait(?Send)]
+impl ServiceBroker for ExecBackend {
+    async fn provision(&self, instance_id: &model::InstanceId, request: &model::ProvisionRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let env = vec![
+            ("OSB_INSTANCE_ID", instance_id.to_string()),
+            ("OSB_SERVICE_ID", request.service_id().to_string()),
+            ("OSB_PLAN_ID", request.plan_id().to_string()),
+            ("OSB_ACCEPTS_INCOMPLETE", accepts_incomplete.to_string()),
+        ];
+        self.run("provision", env, serde_json::to_value(request)?).await?.completion("provision")
+    }
+
+    async fn update(&self, instance: &store::InstanceRecord, request: &model::UpdateRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let mut env = instance_env(instance);
+        env.push(("OSB_ACCEPTS_INCOMPLETE", accepts_incomplete.to_string()));
+        self.run("update", env, serde_json::to_value(request)?).await?.completion("update")
+    }
+
+    async fn deprovision(&self, instance: &store::InstanceRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        let mut env = instance_env(instance);
+        env.push(("OSB_ACCEPTS_INCOMPLETE", accepts_incomplete.to_string()));
+        let input = json!({ "service_id": instance.service_id(), "plan_id": instance.plan_id() });
+        self.run("deprovision", env, input).await?.completion("deprovision")
+    }
+
+    async fn last_operation(&self, instance: &store::InstanceRecord, operation: Option<&str>) -> Result<model::LastOperation> {
+        let mut env = instance_env(instance);
+        env.push(("OSB_LAST_OPERATION", operation.unwrap_or_default().to_owned()));
+        self.run("status", env, json!({ "operation": operation })).await?.last_operation()
+    }
+
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        Ok(instance.to_instance_response())
+    }
+
+    async fn bind(&self, instance: &store::InstanceRecord, binding_id: &model::BindingId, request: &model::BindRequest, accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        let mut env = instance_env(instance);
+        env.push(("OSB_BINDING_ID", binding_id.to_string()));
+        env.push(("OSB_ACCEPTS_INCOMPLETE", accepts_incomplete.to_string()));
+        self.run("bind", env, serde_json::to_value(request)?).await?.completion("bind")
+    }
+
+    async fn unbind(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        let mut env = instance_env(instance);
+        env.push(("OSB_BINDING_ID", binding.binding_id().to_string()));
+        env.push(("OSB_ACCEPTS_INCOMPLETE", accepts_incomplete.to_string()));
+        let input = json!({ "service_id": binding.service_id(), "plan_id": binding.plan_id() });
+        self.run("unbind", env, input).await?.completion("unbind")
+    }
+
+    async fn last_binding_operation(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, operation: Option<&str>) -> Result<model::LastOperation> {
+        let mut env = instance_env(instance);
+        env.push(("OSB_BINDING_ID", binding.binding_id().to_string()));
+        env.push(("OSB_LAST_OPERATION", operation.unwrap_or_default().to_owned()));
+        self.run("status", env, json!({ "operation": operation })).await?.last_operation()
+    }
+
+    async fn fetch_binding(&self, _instance: &store::InstanceRecord, binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        Ok(binding.to_binding_response())
+    }
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{model, store, ExecBackend};
+    use crate::service::{Completion, ServiceBroker, ServiceError};
+
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    /// Script provisioning asynchronously when asked to, tracking operations in a state file
+    const SCRIPT: &str = r#"#!/bin/sh
+state="$(dirname "$0")/state"
+case "$1" in
+  provision)
+    input="$(cat)"
+    case "$input" in *'"fail"'*) echo "plan is full" >&2; exit 8 ;; esac
+    if [ "$OSB_ACCEPTS_INCOMPLETE" = "true" ]; then
+      echo "in progress" > "$state"
+      echo '{"operation": "op-'"$OSB_INSTANCE_ID"'"}'
+      exit 10
+    fi
+    echo '{"dashboard_url": "http://dashboard/'"$OSB_INSTANCE_ID"'"}'
+    ;;
+  status)
+    [ "$OSB_LAST_OPERATION" = "op-$OSB_INSTANCE_ID" ] || exit 3
+    echo '{"state": "'"$(cat "$state")"'"}'
+    echo "succeeded" > "$state"
+    ;;
+  bind)
+    echo '{"credentials": {"instance": "'"$OSB_INSTANCE_ID"'", "binding": "'"$OSB_BINDING_ID"'"}}'
+    ;;
+  update)
+    # Writes more than a pipe holds before reading its input
+    printf '{"dashboard_url": "http://dashboard/%s"}' "$(head -c 200000 /dev/zero | tr '\0' x)"
+    input="$(cat)"
+    case "$input" in *'"sleep"'*) sleep 5 ;; esac
+    ;;
+  deprovision)
+    ;;
+  *)
+    exit 9
+    ;;
+esac
+"#;
+
+    fn backend(dir: &Path) -> ExecBackend {
+        let path = dir.join("broker.sh");
+        std::fs::write(&path, SCRIPT).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        ExecBackend::new(path)
+    }
+
+    fn instance() -> store::InstanceRecord {
+        store::InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap())
+    }
+
+    fn provision_request() -> model::ProvisionRequest {
+        model::ProvisionRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap())
+    }
+
+    fn service_error(error: anyhow::Error) -> ServiceError {
+        error.downcast().expect("MUST be a service error")
+    }
+
+    #[actix_rt::test]
+    async fn exec_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(dir.path());
+
+        match backend.provision(&"i1".parse().unwrap(), &provision_request(), false).await.unwrap() {
+            Completion::Sync(response) => assert_eq!(Some("http://dashboard/i1"), response.dashboard_url(), "provision.dashboard_url"),
+            Completion::Async(_)       => panic!("provision MUST be synchronous"),
+        }
+
+        let bind = backend.bind(&instance(), &"b1".parse().unwrap(), &model::BindRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap()), false).await.unwrap();
+        assert_eq!(Some(&serde_json::json!({ "instance": "i1", "binding": "b1" })), bind.into_inner().credentials(), "bind.credentials");
+
+        // Large input and output are exchanged without blocking
+        let mut request = model::UpdateRequest::new("mysql".parse().unwrap());
+        *request.parameters_mut() = Some(serde_json::json!({ "data": "x".repeat(200_000) }));
+        let update = backend.update(&instance(), &request, false).await.unwrap();
+        assert_eq!(Some(200_000 + "http://dashboard/".len()), update.into_inner().dashboard_url().map(str::len), "update.dashboard_url");
+
+        let deprovision = backend.deprovision(&instance(), false).await.unwrap();
+        assert!(!deprovision.is_async(), "deprovision.is_async");
+    }
+
+    #[actix_rt::test]
+    async fn exec_async() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(dir.path());
+
+        let operation = match backend.provision(&"i1".parse().unwrap(), &provision_request(), true).await.unwrap() {
+            Completion::Async(response) => response.operation().map(str::to_owned),
+            Completion::Sync(_)         => panic!("provision MUST be asynchronous"),
+        };
+        assert_eq!(Some("op-i1"), operation.as_deref(), "operation");
+
+        let last = backend.last_operation(&instance(), operation.as_deref()).await.unwrap();
+        assert_eq!(model::LastOperationState::InProgress, last.state(), "[Running] state");
+        let last = backend.last_operation(&instance(), operation.as_deref()).await.unwrap();
+        assert_eq!(model::LastOperationState::Succeeded, last.state(), "[Done] state");
+
+        let error = backend.last_operation(&instance(), Some("other")).await.expect_err("unknown operation");
+        assert!(matches!(service_error(error), ServiceError::NotFound(_)), "[Unknown] error");
+    }
+
+    #[actix_rt::test]
+    async fn exec_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(dir.path());
+
+        let mut request = provision_request();
+        *request.parameters_mut() = Some(serde_json::json!({ "fail": true }));
+        let error = backend.provision(&"i1".parse().unwrap(), &request, false).await.expect_err("failing provision");
+        assert_eq!(ServiceError::UnprocessableEntity("plan is full".to_owned()), service_error(error), "exit 8");
+
+        let error = backend.unbind(&instance(), &store::BindingRecord::new("i1".parse().unwrap(), "b1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap()), false).await.expect_err("unsupported unbind");
+        assert_eq!(ServiceError::NotSupported("Operation 'unbind' is not supported".to_owned()), service_error(error), "exit 9");
+
+        let mut request = model::UpdateRequest::new("mysql".parse().unwrap());
+        *request.parameters_mut() = Some(serde_json::json!({ "sleep": true }));
+        let mut slow = backend.clone();
+        *slow.timeout_mut() = std::time::Duration::from_millis(200);
+        let started = std::time::Instant::now();
+        let error = slow.update(&instance(), &request, false).await.expect_err("slow update");
+        assert!(started.elapsed() < std::time::Duration::from_secs(4), "slow script MUST be killed");
+        assert!(error.downcast_ref::<ServiceError>().is_none(), "timeout MUST be an internal error");
+        assert_eq!("Script 'update' has timed out after 200ms", error.to_string(), "timeout");
+
+        let missing = ExecBackend::new(dir.path().join("missing.sh"));
+        let error = missing.deprovision(&instance(), false).await.expect_err("missing script");
+        assert!(error.downcast_ref::<ServiceError>().is_none(), "missing script MUST be an internal error");
+    }
+}
@@ -0,0 +1,284 @@
+//! Backend provisioning each instance as a SQLite database file.
+//!
+//! Instance `<instance_id>` is stored into `<root>/<instance_id>.db`. Each binding gets a
+//! generated user, recorded into the `osb_users` table of the `<root>/<instance_id>.auth.db`
+//! sidecar database rather than the one handed to applications, and credentials made of a
+//! connection `uri`, the database `path`, `username` and `password`.
+//!
+//! Plans may set a size quota, in megabytes, with their `max_size_mb` metadata. SQLite can't
+//! persist such a limit, so it is handed to applications as `max_size_mb` credential (to be
+//! applied with `PRAGMA max_page_count`), and enforced by refusing bindings of oversized
+//! databases as well as updates to plans they don't fit in.
+
+use crate::{model, store};
+use crate::service::{Completion, ServiceBroker, ServiceError};
+use super::{generate_secret, ignore_missing};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use serde_json::json;
+
+/// Plan metadata holding its size quota, in megabytes
+pub const MAX_SIZE_METADATA: &str = "max_size_mb";
+
+const USERNAME_LENGTH: usize = 8;
+const PASSWORD_LENGTH: usize = 24;
+
+const USERS_TABLE: &str = "CREATE TABLE IF NOT EXISTS osb_users (
+    binding_id TEXT NOT NULL PRIMARY KEY,
+    username   TEXT NOT NULL UNIQUE,
+    password   TEXT NOT NULL
+)";
+
+/// Backend provisioning instances as SQLite databases under a root directory
+#[derive(Debug, Clone)]
+pub struct SqliteBackend {
+    root: PathBuf,
+    quotas: HashMap<model::PlanId, u64>,
+}
+
+impl SqliteBackend {
+    /// Opens a root directory, creating it if missing, and reads plan quotas from the catalog
+    pub fn open<P: Into<PathBuf>>(root: P, catalog: &model::Catalog) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root).with_context(|| format!("Can't create root directory '{}'", root.display()))?;
+        let quotas = Self::read_quotas(catalog)?;
+        Ok(SqliteBackend { root, quotas })
+    }
+
+    /// Reads plan quotas from the catalog, failing on invalid ones
+    pub fn read_quotas(catalog: &model::Catalog) -> Result<HashMap<model::PlanId, u64>> {
+        let mut quotas = HashMap::new();
+        for plan in catalog.services().iter().flat_map(|service| service.plans()) {
+            if let Some(max_size) = plan.metadata().get(MAX_SIZE_METADATA) {
+                let invalid = || format!("Invalid {} of plan '{}': {}", MAX_SIZE_METADATA, plan.id(), max_size);
+                let max_size: u64 = max_size.parse().with_context(invalid)?;
+                // Quota is compared in bytes
+                if max_size.checked_mul(1024 * 1024).is_none() {
+                    return Err(anyhow!(invalid()));
+                }
+                quotas.insert(plan.id().clone(), max_size);
+            }
+        }
+        Ok(quotas)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Size quotas, in megabytes, by plan
+    pub fn quotas(&self) -> &HashMap<model::PlanId, u64> {
+        &self.quotas
+    }
+
+    pub fn database_path(&self, instance_id: &model::InstanceId) -> PathBuf {
+        self.root.join(format!("{}.db", instance_id))
+    }
+
+    /// Sidecar database holding the users of an instance
+    pub fn auth_path(&self, instance_id: &model::InstanceId) -> PathBuf {
+        self.root.join(format!("{}.auth.db", instance_id))
+    }
+
+    fn connect_auth(&self, instance_id: &model::InstanceId) -> Result<Connection> {
+        let path = self.auth_path(instance_id);
+        let connection = Connection::open(&path).with_context(|| format!("Can't open database '{}'", path.display()))?;
+        connection.execute(USERS_TABLE, [])?;
+        Ok(connection)
+    }
+
+    fn connect(&self, instance_id: &model::InstanceId) -> Result<Connection> {
+        let path = self.database_path(instance_id);
+        if !path.is_file() {
+            return Err(ServiceError::UnprocessableEntity(format!("Database of instance '{}' is missing", instance_id)).into());
+        }
+        Connection::open(&path).with_context(|| format!("Can't open database '{}'", path.display()))
+    }
+
+    /// Fails if database doesn't fit into plan quota
+    fn check_quota(&self, connection: &Connection, plan_id: &model::PlanId) -> Result<()> {
+        let max_size = match self.quotas.get(plan_id) {
+            Some(max_size) => *max_size,
+            None           => return Ok(()),
+        };
+        let size: i64 = connection.query_row("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()", [], |row| row.get(0))?;
+        if size as u64 > max_size * 1024 * 1024 {
+            return Err(ServiceError::UnprocessableEntity(format!("Database size ({} bytes) exceeds quota of plan '{}' ({} MB)", size, plan_id, max_size)).into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ServiceBroker for SqliteBackend {
+    async fn provision(&self, instance_id: &model::InstanceId, _request: &model::ProvisionRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let path = self.database_path(instance_id);
+        Connection::open(&path).with_context(|| format!("Can't create database '{}'", path.display()))?;
+        self.connect_auth(instance_id)?;
+        Ok(Completion::Sync(model::ProvisionResponse::new()))
+    }
+
+    async fn update(&self, instance: &store::InstanceRecord, request: &model::UpdateRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let connection = self.connect(instance.instance_id())?;
+        self.check_quota(&connection, request.plan_id().unwrap_or_else(|| instance.plan_id()))?;
+        Ok(Completion::Sync(model::ProvisionResponse::new()))
+    }
+
+    async fn deprovision(&self, instance: &store::InstanceRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        for path in &[self.database_path(instance.instance_id()), self.auth_path(instance.instance_id())] {
+            for suffix in &["", "-journal", "-wal", "-shm"] {
+                let mut file = path.clone().into_os_string();
+                file.push(suffix);
+                ignore_missing(fs::remove_file(&file))
+                    .with_context(|| format!("Can't remove database file '{}'", Path::new(&file).display()))?;
+            }
+        }
+        Ok(Completion::Sync(model::OperationResponse::new()))
+    }
+
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        Ok(instance.to_instance_response())
+    }
+
+    async fn bind(&self, instance: &store::InstanceRecord, binding_id: &model::BindingId, _request: &model::BindRequest, _accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        let connection = self.connect(instance.instance_id())?;
+        self.check_quota(&connection, instance.plan_id())?;
+        let username = format!("u{}", generate_secret(USERNAME_LENGTH).to_lowercase());
+        let password = generate_secret(PASSWORD_LENGTH);
+        self.connect_auth(instance.instance_id())?.execute("INSERT OR REPLACE INTO osb_users (binding_id, username, password) VALUES (?1, ?2, ?3)",
+                           params![binding_id.as_str(), username, password])?;
+
+        let path = self.database_path(instance.instance_id());
+        let path = path.to_str().ok_or_else(|| anyhow!("Database path '{}' is not valid UTF-8", path.display()))?;
+        let mut response = model::BindingResponse::new();
+        *response.credentials_mut() = Some(json!({
+            "uri": format!("file:{}?mode=rw", path),
+            "path": path,
+            "username": username,
+            "password": password,
+            "max_size_mb": self.quotas.get(instance.plan_id()),
+        }));
+        Ok(Completion::Sync(response))
+    }
+
+    async fn unbind(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        // Users are gone along with their database
+        if self.auth_path(instance.instance_id()).is_file() {
+            self.connect_auth(instance.instance_id())?
+                .execute("DELETE FROM osb_users WHERE binding_id = ?1", params![binding.binding_id().as_str()])?;
+        }
+        Ok(Completion::Sync(model::OperationResponse::new()))
+    }
+
+    async fn fetch_binding(&self, _instance: &store::InstanceRecord, binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        Ok(binding.to_binding_response())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{model, store, SqliteBackend, MAX_SIZE_METADATA};
+    use crate::service::{ServiceBroker, ServiceError};
+
+    use rusqlite::Connection;
+
+    fn catalog() -> model::Catalog {
+        let mut catalog: model::Catalog = serde_json::from_str(&std::fs::read_to_string("tests/default_catalog.json").unwrap()).unwrap();
+        for plan in catalog.services_mut().iter_mut().flat_map(|service| service.plans_mut()) {
+            if plan.id().as_str() == "mysql_free" {
+                plan.metadata_mut().insert(MAX_SIZE_METADATA.to_owned(), "1".to_owned());
+            }
+        }
+        catalog
+    }
+
+    fn instance(plan_id: &str) -> store::InstanceRecord {
+        store::InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), plan_id.parse().unwrap())
+    }
+
+    fn binding() -> store::BindingRecord {
+        store::BindingRecord::new("i1".parse().unwrap(), "b1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap())
+    }
+
+    fn bind_request() -> model::BindRequest {
+        model::BindRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap())
+    }
+
+    fn users(connection: &Connection) -> i64 {
+        connection.query_row("SELECT COUNT(*) FROM osb_users", [], |row| row.get(0)).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn sqlite_lifecycle() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = SqliteBackend::open(root.path(), &catalog()).unwrap();
+        let request = model::ProvisionRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+
+        backend.provision(&"i1".parse().unwrap(), &request, false).await.unwrap();
+        let path = backend.database_path(&"i1".parse().unwrap());
+        assert!(path.is_file(), "[Provision] database");
+
+        let response = backend.bind(&instance("mysql_free"), &"b1".parse().unwrap(), &bind_request(), false).await.unwrap().into_inner();
+        let credentials = response.credentials().expect("[Bind] credentials");
+        assert_eq!(serde_json::json!(path), credentials["path"], "[Bind] path");
+        assert_eq!(serde_json::json!(1), credentials["max_size_mb"], "[Bind] max_size_mb");
+        let tables: i64 = Connection::open(&path).unwrap().query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'osb_users'", [], |row| row.get(0)).unwrap();
+        assert_eq!(0, tables, "[Bind] users MUST NOT be readable by applications");
+        let connection = Connection::open(backend.auth_path(&"i1".parse().unwrap())).unwrap();
+        let password: String = connection.query_row("SELECT password FROM osb_users WHERE username = ?1", [credentials["username"].as_str().unwrap()], |row| row.get(0)).unwrap();
+        assert_eq!(credentials["password"].as_str(), Some(password.as_str()), "[Bind] password");
+
+        backend.unbind(&instance("mysql_free"), &binding(), false).await.unwrap();
+        assert_eq!(0, users(&connection), "[Unbind] users");
+        drop(connection);
+
+        backend.deprovision(&instance("mysql_free"), false).await.unwrap();
+        assert!(!path.exists(), "[Deprovision] database");
+        assert!(!backend.auth_path(&"i1".parse().unwrap()).exists(), "[Deprovision] users");
+        backend.unbind(&instance("mysql_free"), &binding(), false).await.expect("[Unbind deprovisioned] MUST succeed");
+    }
+
+    #[actix_rt::test]
+    async fn sqlite_quota() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = SqliteBackend::open(root.path(), &catalog()).unwrap();
+        let request = model::ProvisionRequest::new("mysql".parse().unwrap(), "mysql_small".parse().unwrap());
+        backend.provision(&"i1".parse().unwrap(), &request, false).await.unwrap();
+
+        let connection = Connection::open(backend.database_path(&"i1".parse().unwrap())).unwrap();
+        connection.execute_batch("CREATE TABLE blobs (data BLOB); INSERT INTO blobs VALUES (zeroblob(2 * 1024 * 1024));").unwrap();
+
+        let mut update = model::UpdateRequest::new("mysql".parse().unwrap());
+        *update.plan_id_mut() = Some("mysql_free".parse().unwrap());
+        let error = backend.update(&instance("mysql_small"), &update, false).await.expect_err("[Downgrade] MUST be refused");
+        assert!(matches!(error.downcast::<ServiceError>(), Ok(ServiceError::UnprocessableEntity(_))), "[Downgrade] error");
+
+        let error = backend.bind(&instance("mysql_free"), &"b1".parse().unwrap(), &bind_request(), false).await.expect_err("[Bind oversized] MUST be refused");
+        assert!(matches!(error.downcast::<ServiceError>(), Ok(ServiceError::UnprocessableEntity(_))), "[Bind oversized] error");
+
+        backend.bind(&instance("mysql_small"), &"b1".parse().unwrap(), &bind_request(), false).await.expect("[Bind unlimited] MUST succeed");
+        let auth = Connection::open(backend.auth_path(&"i1".parse().unwrap())).unwrap();
+        assert_eq!(1, users(&auth), "[Bind unlimited] users");
+    }
+
+    #[test]
+    fn sqlite_invalid_quota() {
+        let mut catalog = catalog();
+        catalog.services_mut()[0].plans_mut()[1].metadata_mut().insert(MAX_SIZE_METADATA.to_owned(), "large".to_owned());
+        let root = tempfile::tempdir().unwrap();
+        let error = SqliteBackend::open(root.path(), &catalog).expect_err("invalid quota MUST be refused");
+        assert_eq!("Invalid max_size_mb of plan 'mysql_small': large", error.to_string());
+
+        catalog.services_mut()[0].plans_mut()[1].metadata_mut().insert(MAX_SIZE_METADATA.to_owned(), u64::MAX.to_string());
+        let error = SqliteBackend::open(root.path(), &catalog).expect_err("overflowing quota MUST be refused");
+        assert_eq!(format!("Invalid max_size_mb of plan 'mysql_small': {}", u64::MAX), error.to_string());
+    }
+}
@@ -0,0 +1,93 @@
+//! Command line parsing shared by the binaries.
+//!
+//! Options are given as `--name value` or `--name=value` and may be repeated, flags take no
+//! value, unknown ones being rejected. Binaries extend `Args` with their own accessors.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use anyhow::{anyhow, bail, Context};
+
+/// Parsed command line
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    command: Option<String>,
+    options: HashMap<String, Vec<String>>,
+    flags: HashSet<String>,
+}
+
+impl Args {
+    /// Parses arguments, `flags` being the options taking no value and `options` the ones
+    /// taking one. A single positional argument is accepted as command, if `with_command` is set.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I, flags: &[&str], options: &[&str], with_command: bool) -> Result<Self> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if flags.contains(&name) => {
+                    parsed.flags.insert(name.to_owned());
+                },
+                Some(option) => {
+                    let (name, value) = match option.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_owned())),
+                        None                => (option, None),
+                    };
+                    if flags.contains(&name) {
+                        bail!("Option '--{}' takes no value", name);
+                    }
+                    if !options.contains(&name) {
+                        bail!("Unknown option '--{}'", name);
+                    }
+                    let value = match value {
+                        Some(value) => value,
+                        None        => args.next().ok_or_else(|| anyhow!("Missing value of option '--{}'", name))?,
+                    };
+                    parsed.options.entry(name.to_owned()).or_default().push(value);
+                },
+                None if with_command && parsed.command.is_none() => parsed.command = Some(arg),
+                None                                             => bail!("Unexpected argument '{}'", arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    #[allow(dead_code)]
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// Last value of an option
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|values| values.last()).map(String::as_str)
+    }
+
+    /// All values of a repeated option
+    #[allow(dead_code)]
+    pub fn options(&self, name: &str) -> Option<&Vec<String>> {
+        self.options.get(name)
+    }
+
+    /// Replaces the values of an option
+    #[allow(dead_code)]
+    pub fn set_option(&mut self, name: &str, value: String) {
+        self.options.insert(name.to_owned(), vec![value]);
+    }
+
+    #[allow(dead_code)]
+    pub fn id<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>>
+    where T::Err: std::error::Error + Send + Sync + 'static {
+        self.option(name)
+            .map(|id| id.parse().with_context(|| format!("Invalid option '--{}'", name)))
+            .transpose()
+    }
+
+    #[allow(dead_code)]
+    pub fn required_id<T: std::str::FromStr>(&self, name: &str) -> Result<T>
+    where T::Err: std::error::Error + Send + Sync + 'static {
+        self.id(name)?.ok_or_else(|| anyhow!("Missing option '--{}'", name))
+    }
+}
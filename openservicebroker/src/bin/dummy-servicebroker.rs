@@ -1,23 +1,249 @@
+mod args;
+
 use openservicebroker as osb;
-use osb::service::CatalogProvider;
+use osb::server::{BackendConfig, CatalogSource, Credentials, LogFormat, ServerConfig, StoreConfig, TlsConfig};
+use args::Args;
+
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::{App, HttpServer};
-use actix_rt;
 
 use anyhow::Result;
-use anyhow::Context;
+use anyhow::{anyhow, bail};
 
-#[actix_rt::main]
-async fn main() -> Result<()> {
-    let catalog = osb::service::providers::catalog::file_json("tests/default_catalog.json")
-                                                   .to_single()
-                                                   .with_context(|| "Error on loading default catalog")?;
-    HttpServer::new(move || {
+const USAGE: &str = "Usage: dummy-servicebroker [--config FILE] [OPTIONS]
+
+Options:
+  --bind ADDRESS                  Listening address, may be repeated (127.0.0.1:8080)
+  --catalog FILE                  Catalog JSON file (tests/default_catalog.json)
+  --user USER --password PASSWORD Required basic authentication credentials
+  --min-api-version VERSION       Oldest accepted X-Broker-API-Version
+  --log-level LEVEL               off, error, warn, info, debug or trace (info)
+  --log-format FORMAT             text or json (text)
+  --backend TYPE --backend-path PATH
+                                  directory (root), sqlite (root) or exec (program)
+  --store TYPE [--store-path PATH]
+                                  memory, file (root) or sqlite (database)
+  --tls-certificate FILE --tls-private-key FILE [--tls-client-ca FILE]
+                                  Serve HTTPS, authenticating clients signed by CA
+  --shutdown-timeout SECONDS      Delay for running requests to complete on shutdown (30)
+  --check-config                  Validate configuration and exit
+
+Options override the configuration file ones.";
+
+/// Options taking no value
+const FLAGS: &[&str] = &["check-config", "help"];
+/// Options taking a value
+const OPTIONS: &[&str] = &["config", "bind", "catalog", "user", "password", "min-api-version", "log-level", "log-format",
+                           "backend", "backend-path", "store", "store-path", "tls-certificate", "tls-private-key", "tls-client-ca",
+                           "shutdown-timeout"];
+
+impl Args {
+    /// Loads configuration file, if any, then applies options
+    fn config(&self) -> Result<ServerConfig> {
+        let mut config = match self.option("config") {
+            Some(path) => ServerConfig::load(path)?,
+            None       => ServerConfig::new(),
+        };
+        if let Some(bind) = self.options("bind") {
+            *config.bind_mut() = bind.clone();
+        }
+        if let Some(path) = self.option("catalog") {
+            *config.catalog_mut() = CatalogSource::File { path: path.into(), reload: false };
+        }
+        match (self.option("user"), self.option("password")) {
+            (Some(user), password) => *config.credentials_mut() = Some(Credentials::new(user.to_owned(), password.unwrap_or_default().to_owned())),
+            (None, Some(_))        => bail!("Option '--password' requires '--user'"),
+            (None, None)           => (),
+        }
+        if let Some(version) = self.option("min-api-version") {
+            *config.min_api_version_mut() = Some(version.to_owned());
+        }
+        if let Some(level) = self.option("log-level") {
+            *config.log_level_mut() = level.to_owned();
+        }
+        if let Some(format) = self.option("log-format") {
+            *config.log_format_mut() = match format {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _      => bail!("Unknown log format '{}'", format),
+            };
+        }
+        if let Some(backend) = self.option("backend") {
+            let path = self.option("backend-path").ok_or_else(|| anyhow!("Option '--backend' requires '--backend-path'"))?;
+            *config.backend_mut() = match backend {
+                "directory" => BackendConfig::Directory { root: path.into() },
+                "sqlite"    => BackendConfig::Sqlite { root: path.into() },
+                "exec"      => BackendConfig::Exec { program: path.into(), args: Vec::new(), timeout: None },
+                _           => bail!("Unknown backend '{}'", backend),
+            };
+        }
+        if let Some(store) = self.option("store") {
+            let path = || self.option("store-path").ok_or_else(|| anyhow!("Option '--store {}' requires '--store-path'", store));
+            *config.store_mut() = match store {
+                "memory" => StoreConfig::Memory,
+                "file"   => StoreConfig::File { root: path()?.into() },
+                "sqlite" => StoreConfig::Sqlite { path: path()?.into() },
+                _        => bail!("Unknown store '{}'", store),
+            };
+        }
+        match (self.option("tls-certificate"), self.option("tls-private-key")) {
+            (Some(certificate), Some(private_key)) => match config.tls_mut() {
+                Some(tls) => {
+                    *tls.certificate_mut() = certificate.into();
+                    *tls.private_key_mut() = private_key.into();
+                },
+                None => *config.tls_mut() = Some(TlsConfig::new(certificate.into(), private_key.into())),
+            },
+            (None, None) => (),
+            _            => bail!("Options '--tls-certificate' and '--tls-private-key' go together"),
+        }
+        if let Some(client_ca) = self.option("tls-client-ca") {
+            let tls = config.tls_mut().as_mut().ok_or_else(|| anyhow!("Option '--tls-client-ca' requires TLS"))?;
+            *tls.client_ca_mut() = Some(client_ca.into());
+        }
+        if let Some(timeout) = self.option("shutdown-timeout") {
+            *config.shutdown_timeout_mut() = timeout.parse().map_err(|_| anyhow!("Invalid shutdown timeout '{}'", timeout))?;
+        }
+        Ok(config)
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let config = args.config()?;
+    let catalog = config.check()?;
+    if args.flag("check-config") {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    let logger = tracing_subscriber::fmt().with_env_filter(tracing_subscriber::EnvFilter::try_new(config.log_level())?);
+    match config.log_format() {
+        LogFormat::Text => logger.init(),
+        LogFormat::Json => logger.json().flatten_event(true).with_current_span(true).with_span_list(false).init(),
+    }
+
+    let broker = config.open_broker(&catalog)?;
+    let recovery_broker = broker.clone();
+    let drain = Arc::new(osb::shutdown::Drain::new());
+    let server_drain = drain.clone();
+    let config = Arc::new(config);
+    let server_config = config.clone();
+    let factory = move || {
+        let config = server_config.clone();
+        let drain = server_drain.clone();
+        let catalog = config.catalog_provider(&catalog);
         App::new()
-            .service(osb::new_scope("", Box::new(catalog.clone())))
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await?;
+            .wrap_fn(move |req, srv| drain.guard(req, srv))
+            .wrap_fn(move |req, srv| config.authorize(req, srv))
+            .wrap_fn(osb::trace::instrument)
+            .service(osb::new_broker_scope("", catalog, broker.clone()))
+    };
+    let server = match config.tls() {
+        #[cfg(feature = "tls")]
+        Some(tls) => {
+            let tls = osb::tls::server_config(tls)?;
+            let mut server = actix_server::Server::build()
+                                              .disable_signals()
+                                              .shutdown_timeout(config.shutdown_timeout());
+            for address in config.bind() {
+                server = osb::tls::listen(server, std::net::TcpListener::bind(address)?, factory.clone(), tls.clone())?;
+                tracing::info!("Listening on https://{}", address);
+            }
+            server.run()
+        },
+        #[cfg(not(feature = "tls"))]
+        Some(_) => bail!("TLS requires 'tls' feature"),
+        None => {
+            let mut server = HttpServer::new(factory)
+                                        .disable_signals()
+                                        .shutdown_timeout(config.shutdown_timeout());
+            for address in config.bind() {
+                server = server.bind(address)?;
+                tracing::info!("Listening on http://{}", address);
+            }
+            server.run()
+        },
+    };
+
+    osb::shutdown::OperationRecovery::new(recovery_broker.clone()).start(|report| tracing::info!("{}", report));
+
+    let timeout = Duration::from_secs(config.shutdown_timeout());
+    let stopping = server.clone();
+    actix_rt::spawn(async move {
+        if let Err(error) = osb::shutdown::signal().await {
+            tracing::error!(%error, "Can't handle signals");
+            return;
+        }
+        tracing::info!("Shutting down, refusing new mutating requests");
+        drain.start();
+        if !drain.wait(timeout).await {
+            tracing::warn!("{} request(s) still running after {:?}", drain.in_flight(), timeout);
+        }
+        stopping.stop(true).await;
+    });
+    server.await?;
+
+    let running = osb::shutdown::running_operations(&recovery_broker)?;
+    if !running.is_empty() {
+        match config.store() {
+            StoreConfig::Memory => tracing::warn!("{} running operation(s) are lost with the memory store: {}", running.len(), running.join(", ")),
+            _                   => tracing::info!("{} running operation(s) will resume on next startup: {}", running.len(), running.join(", ")),
+        }
+    }
     Ok(())
 }
+
+#[actix_rt::main]
+async fn main() {
+    let result = match Args::parse(std::env::args().skip(1), FLAGS, OPTIONS, false) {
+        Ok(args)   => run(args).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        eprintln!("ERROR: {:#}", error);
+        std::process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+    use openservicebroker::server::{BackendConfig, LogFormat, StoreConfig};
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()), super::FLAGS, super::OPTIONS, false)
+    }
+
+    #[test]
+    fn args_config() {
+        let args = parse(&["--bind", "127.0.0.1:8080", "--bind=[::1]:8080", "--user", "admin", "--password", "secret",
+                           "--min-api-version", "2.13", "--log-format", "json", "--backend", "exec", "--backend-path", "/usr/bin/broker",
+                           "--store", "file", "--store-path", "/var/lib/broker", "--shutdown-timeout", "5", "--check-config"]).unwrap();
+        assert!(args.flag("check-config"), "check-config");
+        let config = args.config().unwrap();
+        assert_eq!(&vec!["127.0.0.1:8080".to_owned(), "[::1]:8080".to_owned()], config.bind(), "bind");
+        assert_eq!(Some("admin"), config.credentials().map(|credentials| credentials.username()), "user");
+        assert_eq!(Some("2.13"), config.min_api_version(), "min_api_version");
+        assert_eq!(LogFormat::Json, config.log_format(), "log_format");
+        assert_eq!(&BackendConfig::Exec { program: "/usr/bin/broker".into(), args: Vec::new(), timeout: None }, config.backend(), "backend");
+        assert_eq!(&StoreConfig::File { root: "/var/lib/broker".into() }, config.store(), "store");
+        assert_eq!(5, config.shutdown_timeout(), "shutdown_timeout");
+    }
+
+    #[test]
+    fn args_errors() {
+        assert_eq!("Unexpected argument 'serve'", parse(&["serve"]).unwrap_err().to_string());
+        assert_eq!("Unknown option '--check-confg'", parse(&["--check-confg"]).unwrap_err().to_string());
+        assert_eq!("Option '--check-config' takes no value", parse(&["--check-config=true"]).unwrap_err().to_string());
+        assert_eq!("Option '--password' requires '--user'", parse(&["--password", "secret"]).unwrap().config().unwrap_err().to_string());
+        assert_eq!("Unknown store 'redis'", parse(&["--store", "redis"]).unwrap().config().unwrap_err().to_string());
+        assert_eq!("Option '--store sqlite' requires '--store-path'", parse(&["--store", "sqlite"]).unwrap().config().unwrap_err().to_string());
+        assert_eq!("Invalid shutdown timeout 'soon'", parse(&["--shutdown-timeout", "soon"]).unwrap().config().unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,284 @@
+mod args;
+
+use openservicebroker as osb;
+use osb::client::Client;
+use osb::conformance::Conformance;
+use osb::model;
+use osb::service::Completion;
+use args::Args;
+
+use anyhow::Result;
+use anyhow::{anyhow, bail, Context};
+use serde::Serialize;
+use serde_json::Value;
+
+const USAGE: &str = "Usage: osb [--url URL] [--user USER --password PASSWORD] [--json] COMMAND
+
+Commands:
+  catalog
+  provision   [--instance ID] --service ID --plan ID [--params JSON] [--wait]
+  update      --instance ID --service ID [--plan ID] [--params JSON] [--wait]
+  deprovision --instance ID --service ID --plan ID [--wait]
+  bind        --instance ID [--binding ID] --service ID --plan ID [--params JSON] [--wait]
+  unbind      --instance ID --binding ID --service ID --plan ID [--wait]
+  last-op     --instance ID [--binding ID] [--operation OPERATION]
+  conformance [--service ID] [--plan ID]
+
+Options default to OSB_URL (http://127.0.0.1:8080), OSB_USER and OSB_PASSWORD variables.
+Missing instance and binding IDs are generated.";
+
+/// Options taking no value
+const FLAGS: &[&str] = &["json", "wait", "help"];
+/// Options taking a value
+const OPTIONS: &[&str] = &["url", "user", "password", "instance", "binding", "service", "plan", "params", "operation"];
+
+impl Args {
+    fn params(&self) -> Result<Option<Value>> {
+        self.option("params")
+            .map(|params| serde_json::from_str(params).with_context(|| "Invalid option '--params'"))
+            .transpose()
+    }
+
+    fn client(&self) -> Client {
+        let url = self.option("url").map(str::to_owned)
+                      .or_else(|| std::env::var("OSB_URL").ok())
+                      .unwrap_or_else(|| "http://127.0.0.1:8080".to_owned());
+        let mut client = Client::new(url);
+        let user = self.option("user").map(str::to_owned).or_else(|| std::env::var("OSB_USER").ok());
+        let password = self.option("password").map(str::to_owned).or_else(|| std::env::var("OSB_PASSWORD").ok());
+        if let Some(user) = user {
+            *client.basic_auth_mut() = Some((user, password.unwrap_or_default()));
+        }
+        client
+    }
+}
+
+/// Command output, printed as JSON or as aligned columns
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn print<T: Serialize>(&self, value: &T, fields: &[(&str, Option<String>)]) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(value)?);
+        } else {
+            let rows: Vec<Vec<String>> = fields.iter()
+                                               .filter_map(|(name, value)| value.as_ref().map(|value| vec![format!("{}:", name), value.clone()]))
+                                               .collect();
+            print_table(&[], &rows);
+        }
+        Ok(())
+    }
+
+    fn print_catalog(&self, catalog: &model::Catalog) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(catalog)?);
+            return Ok(());
+        }
+        let rows: Vec<Vec<String>> = catalog.services().iter()
+            .flat_map(|service| service.plans().iter().map(move |plan| vec![
+                service.id().to_string(),
+                plan.id().to_string(),
+                plan.name().to_owned(),
+                plan.effective_bindable(service).to_string(),
+                plan.free().unwrap_or(true).to_string(),
+            ]))
+            .collect();
+        print_table(&["SERVICE", "PLAN", "NAME", "BINDABLE", "FREE"], &rows);
+        Ok(())
+    }
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let columns = rows.iter().map(Vec::len).chain(std::iter::once(headers.len())).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns).map(|column| {
+        rows.iter().filter_map(|row| row.get(column)).map(String::len)
+            .chain(headers.get(column).map(|header| header.len()))
+            .max()
+            .unwrap_or(0)
+    }).collect();
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    if !headers.is_empty() {
+        print_row(headers.to_vec());
+    }
+    for row in rows {
+        print_row(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn to_string(value: Option<&Value>) -> Option<String> {
+    value.map(Value::to_string)
+}
+
+/// Waits for an asynchronous operation if asked, failing if it didn't succeed
+async fn complete<T>(args: &Args, client: &Client, completion: Completion<T>, operation: impl Fn(&T) -> Option<String>, binding_id: Option<&model::BindingId>, deleting: bool) -> Result<(T, Option<model::LastOperation>)> {
+    let response = match completion {
+        Completion::Async(response) if args.flag("wait") => response,
+        completion                                       => return Ok((completion.into_inner(), None)),
+    };
+    let instance_id: model::InstanceId = args.required_id("instance")?;
+    let operation = operation(&response);
+    let last = match (binding_id, deleting) {
+        (Some(binding_id), false) => client.wait_binding(&instance_id, binding_id, operation.as_deref()).await?,
+        (Some(binding_id), true)  => client.wait_unbind(&instance_id, binding_id, operation.as_deref()).await?,
+        (None, false)             => client.wait_instance(&instance_id, operation.as_deref()).await?,
+        (None, true)              => client.wait_deprovision(&instance_id, operation.as_deref()).await?,
+    };
+    if last.state() != model::LastOperationState::Succeeded {
+        bail!("Operation has failed: {}", last.description().unwrap_or("no description"));
+    }
+    Ok((response, Some(last)))
+}
+
+async fn run(mut args: Args) -> Result<()> {
+    let client = args.client();
+    let output = Output { json: args.flag("json") };
+    let command = match args.command().map(str::to_owned) {
+        Some(command) if !args.flag("help") => command,
+        _                                   => {
+            println!("{}", USAGE);
+            return Ok(());
+        },
+    };
+    match command.as_str() {
+        "catalog" => output.print_catalog(&client.catalog().await?),
+        "provision" => {
+            let instance_id = args.id("instance")?.unwrap_or_else(model::InstanceId::generate);
+            args.set_option("instance", instance_id.to_string());
+            let mut request = model::ProvisionRequest::new(args.required_id("service")?, args.required_id("plan")?);
+            *request.parameters_mut() = args.params()?;
+            let completion = client.provision(&instance_id, &request, true).await?;
+            let (response, last) = complete(&args, &client, completion, |response| response.operation().map(str::to_owned), None, false).await?;
+            output.print(&response, &[
+                ("instance", Some(instance_id.to_string())),
+                ("dashboard_url", response.dashboard_url().map(str::to_owned)),
+                ("operation", response.operation().map(str::to_owned)),
+                ("state", last.map(|last| format!("{:?}", last.state()))),
+            ])
+        },
+        "update" => {
+            let instance_id: model::InstanceId = args.required_id("instance")?;
+            let mut request = model::UpdateRequest::new(args.required_id("service")?);
+            *request.plan_id_mut() = args.id("plan")?;
+            *request.parameters_mut() = args.params()?;
+            let completion = client.update(&instance_id, &request, true).await?;
+            let (response, last) = complete(&args, &client, completion, |response| response.operation().map(str::to_owned), None, false).await?;
+            output.print(&response, &[
+                ("instance", Some(instance_id.to_string())),
+                ("dashboard_url", response.dashboard_url().map(str::to_owned)),
+                ("operation", response.operation().map(str::to_owned)),
+                ("state", last.map(|last| format!("{:?}", last.state()))),
+            ])
+        },
+        "deprovision" => {
+            let instance_id: model::InstanceId = args.required_id("instance")?;
+            let completion = client.deprovision(&instance_id, &args.required_id("service")?, &args.required_id("plan")?, true).await?;
+            let (response, last) = complete(&args, &client, completion, |response| response.operation().map(str::to_owned), None, true).await?;
+            output.print(&response, &[
+                ("instance", Some(instance_id.to_string())),
+                ("operation", response.operation().map(str::to_owned)),
+                ("state", last.map(|last| format!("{:?}", last.state()))),
+            ])
+        },
+        "bind" => {
+            let instance_id: model::InstanceId = args.required_id("instance")?;
+            let binding_id = args.id("binding")?.unwrap_or_else(model::BindingId::generate);
+            let mut request = model::BindRequest::new(args.required_id("service")?, args.required_id("plan")?);
+            *request.parameters_mut() = args.params()?;
+            let completion = client.bind(&instance_id, &binding_id, &request, true).await?;
+            let (mut response, last) = complete(&args, &client, completion, |response| response.operation().map(str::to_owned), Some(&binding_id), false).await?;
+            // Credentials of asynchronous bindings are only available once created
+            if last.is_some() {
+                response = client.fetch_binding(&instance_id, &binding_id).await?;
+            }
+            output.print(&response, &[
+                ("binding", Some(binding_id.to_string())),
+                ("credentials", to_string(response.credentials())),
+                ("operation", response.operation().map(str::to_owned)),
+                ("state", last.map(|last| format!("{:?}", last.state()))),
+            ])
+        },
+        "unbind" => {
+            let instance_id: model::InstanceId = args.required_id("instance")?;
+            let binding_id: model::BindingId = args.required_id("binding")?;
+            let completion = client.unbind(&instance_id, &binding_id, &args.required_id("service")?, &args.required_id("plan")?, true).await?;
+            let (response, last) = complete(&args, &client, completion, |response| response.operation().map(str::to_owned), Some(&binding_id), true).await?;
+            output.print(&response, &[
+                ("binding", Some(binding_id.to_string())),
+                ("operation", response.operation().map(str::to_owned)),
+                ("state", last.map(|last| format!("{:?}", last.state()))),
+            ])
+        },
+        "last-op" => {
+            let instance_id: model::InstanceId = args.required_id("instance")?;
+            let last = match args.id::<model::BindingId>("binding")? {
+                Some(binding_id) => client.last_binding_operation(&instance_id, &binding_id, args.option("operation")).await?,
+                None             => client.last_operation(&instance_id, args.option("operation")).await?,
+            };
+            output.print(&last, &[
+                ("state", Some(format!("{:?}", last.state()))),
+                ("description", last.description().map(str::to_owned)),
+            ])
+        },
+        "conformance" => {
+            let mut conformance = Conformance::new(client);
+            *conformance.service_id_mut() = args.id("service")?;
+            *conformance.plan_id_mut() = args.id("plan")?;
+            let report = conformance.run().await;
+            println!("{}", report);
+            if !report.is_success() {
+                bail!("Broker has failed conformance checks");
+            }
+            Ok(())
+        },
+        command => Err(anyhow!("Unknown command '{}'\n\n{}", command, USAGE)),
+    }
+}
+
+#[actix_rt::main]
+async fn main() {
+    let result = match Args::parse(std::env::args().skip(1), FLAGS, OPTIONS, true) {
+        Ok(args)   => run(args).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        eprintln!("ERROR: {:#}", error);
+        std::process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()), super::FLAGS, super::OPTIONS, true)
+    }
+
+    #[test]
+    fn args_parse() {
+        let args = parse(&["--url=http://broker", "provision", "--service", "mysql", "--plan", "mysql_free", "--params", "{\"size\": 1}", "--wait"]).unwrap();
+        assert_eq!(Some("provision"), args.command(), "command");
+        assert_eq!(Some("http://broker"), args.option("url"), "url");
+        assert_eq!(Some("mysql"), args.option("service"), "service");
+        assert_eq!(Some(serde_json::json!({ "size": 1 })), args.params().unwrap(), "params");
+        assert!(args.flag("wait"), "wait");
+        assert!(!args.flag("json"), "json");
+    }
+
+    #[test]
+    fn args_errors() {
+        assert_eq!("Missing value of option '--plan'", parse(&["provision", "--plan"]).unwrap_err().to_string());
+        assert_eq!("Unexpected argument 'mysql'", parse(&["provision", "mysql"]).unwrap_err().to_string());
+        // Typo MUST not consume the next argument
+        assert_eq!("Unknown option '--param'", parse(&["provision", "--param", "{}"]).unwrap_err().to_string());
+        let args = parse(&["bind", "--instance", "i/1"]).unwrap();
+        assert!(args.required_id::<openservicebroker::model::InstanceId>("instance").is_err(), "invalid instance");
+        assert_eq!("Missing option '--service'", args.required_id::<openservicebroker::model::ServiceId>("service").unwrap_err().to_string());
+    }
+}
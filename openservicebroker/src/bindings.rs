@@ -0,0 +1,253 @@
+use super::{model, store};
+use super::broker::{Broker, async_required, find_plan, error_response, start_operation, check_no_operation};
+use super::idempotency::{self, Idempotency};
+use super::instances::{AsyncQuery, DeleteQuery, LastOperationQuery};
+use super::orphans::OrphanGuard;
+use super::service::{CatalogProvider, Completion, ServiceError};
+use super::{server, trace};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Result;
+use anyhow::anyhow;
+use serde_json::Value;
+
+/// Cloud Foundry space of a context
+fn space_guid(context: Option<&Value>) -> Option<&str> {
+    context?.get("space_guid")?.as_str()
+}
+
+/// Binding from another space than the instance's one requires a shareable service, a space
+/// missing on either side being another one
+fn check_space(service: &model::Service, instance: &store::InstanceRecord, request: &model::BindRequest) -> Result<()> {
+    let shareable = service.shareable().unwrap_or(false);
+    match (space_guid(instance.context()), space_guid(request.context())) {
+        (Some(origin), Some(space)) if origin == space => Ok(()),
+        (None, None)                                   => Ok(()),
+        _ if shareable                                 => Ok(()),
+        (_, Some(space)) => Err(ServiceError::UnprocessableEntity(format!("Instance '{}' can't be shared with space '{}'", instance.instance_id(), space)).into()),
+        (_, None)        => Err(ServiceError::UnprocessableEntity(format!("Instance '{}' can't be bound outside of its space", instance.instance_id())).into()),
+    }
+}
+
+pub async fn bind(req: HttpRequest,
+                  path: web::Path<(String, String)>,
+                  query: web::Query<AsyncQuery>,
+                  body: web::Json<model::BindRequest>,
+                  catalog: web::Data<Box<dyn CatalogProvider>>,
+                  broker: web::Data<Broker>) -> HttpResponse {
+    trace::record_plan(body.service_id(), Some(body.plan_id()));
+    if let Err(error) = server::check_identity(&req, body.service_id()) {
+        return error_response(error);
+    }
+    try_bind(&path.0, &path.1, query.accepts_incomplete(), body.into_inner(), catalog.get_ref().as_ref(), &broker).await
+            .unwrap_or_else(error_response)
+}
+
+async fn try_bind(instance_id: &str, binding_id: &str, accepts_incomplete: bool, request: model::BindRequest, catalog: &dyn CatalogProvider, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let binding_id = broker.binding_id(binding_id)?;
+    let catalog = catalog.get_catalog()?;
+    let (service, plan) = find_plan(&catalog, request.service_id(), request.plan_id())?;
+    if !plan.effective_bindable(service) {
+        return Err(ServiceError::BadRequest(format!("Plan '{}' of service '{}' is not bindable", plan.id(), service.id())).into());
+    }
+    // Instance is read under the lock, so a concurrent deprovisioning has either completed or not started
+    let _lock = broker.lock_binding(&instance_id, &binding_id)?;
+    let instance = broker.store().get_instance(&instance_id)?
+                         .ok_or_else(|| ServiceError::NotFound(format!("Instance '{}' does not exist", instance_id)))?;
+    if instance.service_id() != request.service_id() || instance.plan_id() != request.plan_id() {
+        return Err(ServiceError::BadRequest(format!("Instance '{}' is of plan '{}' of service '{}'", instance_id, instance.plan_id(), instance.service_id())).into());
+    }
+    // Neither orphaned nor failed instances are known to exist in the backend
+    if instance.orphaned_at().is_some() {
+        return Err(ServiceError::NotFound(format!("Instance '{}' provisioning outcome is unknown", instance_id)).into());
+    }
+    if instance.operation().is_some_and(|operation| operation.kind() == store::OperationKind::Provision && operation.state() == model::LastOperationState::Failed) {
+        return Err(ServiceError::UnprocessableEntity(format!("Instance '{}' provisioning has failed", instance_id)).into());
+    }
+
+    check_no_operation(instance.operation(), None)?;
+    check_space(service, &instance, &request)?;
+
+    if let Some(existing) = broker.store().get_binding(&instance_id, &binding_id)? {
+        check_no_operation(existing.operation(), Some(store::OperationKind::Bind))?;
+        match idempotency::check_bind(Some(&existing), &request) {
+            Idempotency::Create     => (),
+            Idempotency::Conflict   => return Err(ServiceError::Conflict(format!("Binding '{}' already exists with different attributes", binding_id)).into()),
+            Idempotency::Identical  => return Ok(HttpResponse::Ok().json(existing.to_binding_response())),
+            Idempotency::InProgress => {
+                let mut response = model::BindingResponse::new();
+                *response.operation_mut() = existing.operation().and_then(store::Operation::operation).map(str::to_owned);
+                return Ok(HttpResponse::Accepted().json(response));
+            },
+        }
+    }
+
+    let mut record = store::BindingRecord::new(instance_id, binding_id.clone(), request.service_id().clone(), request.plan_id().clone());
+    *record.parameters_mut() = request.parameters().cloned();
+    *record.context_mut() = request.context().cloned();
+    *record.bind_resource_mut() = request.bind_resource().cloned();
+
+    let mut orphan = OrphanGuard::binding(broker, record.clone());
+    let completion = orphan.check(broker.bindings().bind(&instance, &binding_id, &request, accepts_incomplete).await)?;
+    if completion.is_async() && !accepts_incomplete {
+        // Unknown to the platform, so left to orphan cleanup
+        orphan.orphan()?;
+        return Err(async_required(store::OperationKind::Bind));
+    }
+    let is_async = completion.is_async();
+    let response = completion.into_inner();
+    if let Err(requirement) = response.check_requires(service) {
+        // Backend contract violation: the binding exists, but can't be handed to the platform
+        orphan.orphan()?;
+        return Err(anyhow!("Binding '{}' returned fields requiring '{}', not declared by service '{}'", binding_id, requirement, service.id()));
+    }
+
+    *record.credentials_mut() = response.credentials().cloned();
+    *record.syslog_drain_url_mut() = response.syslog_drain_url().map(str::to_owned);
+    *record.route_service_url_mut() = response.route_service_url().map(str::to_owned);
+    *record.volume_mounts_mut() = response.volume_mounts().cloned();
+    *record.endpoints_mut() = response.endpoints().cloned();
+    *record.metadata_mut() = response.metadata().cloned();
+    if is_async {
+        *record.operation_mut() = Some(start_operation(store::OperationKind::Bind, response.operation()));
+    }
+    broker.store().put_binding(record)?;
+    orphan.disarm();
+
+    Ok(if is_async {
+        HttpResponse::Accepted().json(response)
+    } else {
+        HttpResponse::Created().json(response)
+    })
+}
+
+pub async fn unbind(path: web::Path<(String, String)>,
+                    query: web::Query<DeleteQuery>,
+                    broker: web::Data<Broker>) -> HttpResponse {
+    try_unbind(&path.0, &path.1, &query, &broker).await
+              .unwrap_or_else(error_response)
+}
+
+async fn try_unbind(instance_id: &str, binding_id: &str, query: &DeleteQuery, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let binding_id = broker.binding_id(binding_id)?;
+    let _lock = broker.lock_binding(&instance_id, &binding_id)?;
+    let instance = broker.store().get_instance(&instance_id)?
+                         .ok_or(ServiceError::Gone)?;
+    let existing = broker.store().get_binding(&instance_id, &binding_id)?
+                         .ok_or(ServiceError::Gone)?;
+    if existing.service_id() != query.service_id() || existing.plan_id() != query.plan_id() {
+        return Err(ServiceError::BadRequest(format!("Binding '{}' belongs to service '{}' and plan '{}'", binding_id, existing.service_id(), existing.plan_id())).into());
+    }
+    check_no_operation(instance.operation(), None)?;
+    check_no_operation(existing.operation(), Some(store::OperationKind::Unbind))?;
+    if let Some(running) = existing.operation().filter(|operation| operation.in_progress()) {
+        let mut response = model::OperationResponse::new();
+        *response.operation_mut() = running.operation().map(str::to_owned);
+        return Ok(HttpResponse::Accepted().json(response));
+    }
+
+    match broker.bindings().unbind(&instance, &existing, query.accepts_incomplete()).await? {
+        Completion::Async(response) => {
+            broker.store().update_binding(&instance_id, &binding_id, &mut |record| {
+                *record.operation_mut() = Some(start_operation(store::OperationKind::Unbind, response.operation()));
+            })?;
+            if !query.accepts_incomplete() {
+                return Err(async_required(store::OperationKind::Unbind));
+            }
+            Ok(HttpResponse::Accepted().json(response))
+        },
+        Completion::Sync(response) => {
+            broker.store().delete_binding(&instance_id, &binding_id)?;
+            Ok(HttpResponse::Ok().json(response))
+        },
+    }
+}
+
+pub async fn last_operation(path: web::Path<(String, String)>,
+                            query: web::Query<LastOperationQuery>,
+                            broker: web::Data<Broker>) -> HttpResponse {
+    try_last_operation(&path.0, &path.1, query.operation(), &broker).await
+                      .unwrap_or_else(error_response)
+}
+
+async fn try_last_operation(instance_id: &str, binding_id: &str, operation: Option<&str>, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let binding_id = broker.binding_id(binding_id)?;
+    let instance = broker.store().get_instance(&instance_id)?
+                         .ok_or(ServiceError::Gone)?;
+    let existing = broker.store().get_binding(&instance_id, &binding_id)?
+                         .ok_or(ServiceError::Gone)?;
+    let current = match existing.operation() {
+        Some(current) => current,
+        None          => return Ok(HttpResponse::Ok().json(model::LastOperation::new(model::LastOperationState::Succeeded))),
+    };
+    if !current.in_progress() {
+        let mut last = model::LastOperation::new(current.state());
+        *last.description_mut() = current.description().map(str::to_owned);
+        return Ok(HttpResponse::Ok().json(last));
+    }
+
+    let last = poll_operation(broker, &instance, &existing, current, operation).await?;
+    Ok(HttpResponse::Ok().json(last))
+}
+
+/// Polls the provider about a running operation, recording its outcome
+pub(crate) async fn poll_operation(broker: &Broker, instance: &store::InstanceRecord, existing: &store::BindingRecord, current: &store::Operation, operation: Option<&str>) -> Result<model::LastOperation> {
+    let instance_id = existing.instance_id();
+    let binding_id = existing.binding_id();
+    let operation = operation.or_else(|| current.operation());
+    let last = broker.bindings().last_binding_operation(instance, existing, operation).await?;
+    if current.kind() == store::OperationKind::Unbind && last.state() == model::LastOperationState::Succeeded {
+        broker.store().delete_binding(instance_id, binding_id)?;
+    } else {
+        broker.store().update_binding(instance_id, binding_id, &mut |record| {
+            if let Some(current) = record.operation_mut() {
+                *current.state_mut() = last.state();
+                *current.description_mut() = last.description().map(str::to_owned);
+            }
+        })?;
+    }
+    Ok(last)
+}
+
+pub async fn fetch(path: web::Path<(String, String)>,
+                   catalog: web::Data<Box<dyn CatalogProvider>>,
+                   broker: web::Data<Broker>) -> HttpResponse {
+    try_fetch(&path.0, &path.1, catalog.get_ref().as_ref(), &broker).await
+             .unwrap_or_else(error_response)
+}
+
+/// Bindings being created are reported as missing, while other running operations
+/// lead to `422 ConcurrencyError`.
+async fn try_fetch(instance_id: &str, binding_id: &str, catalog: &dyn CatalogProvider, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let binding_id = broker.binding_id(binding_id)?;
+    let existing = broker.store().get_binding(&instance_id, &binding_id)?
+                         .ok_or_else(|| ServiceError::NotFound(format!("Binding '{}' does not exist", binding_id)))?;
+    if existing.orphaned_at().is_some() {
+        return Err(ServiceError::NotFound(format!("Binding '{}' creation outcome is unknown", binding_id)).into());
+    }
+
+    let catalog = catalog.get_catalog()?;
+    let service = catalog.find_service(existing.service_id())
+                         .ok_or_else(|| ServiceError::BadRequest(format!("Unknown service '{}'", existing.service_id())))?;
+    if service.bindings_retrievable() != Some(true) {
+        return Err(ServiceError::NotFound(format!("Service '{}' does not support fetching bindings", service.id())).into());
+    }
+
+    match existing.operation() {
+        Some(operation) if operation.in_progress() && operation.kind() == store::OperationKind::Bind
+            => return Err(ServiceError::NotFound(format!("Binding '{}' is being created", binding_id)).into()),
+        Some(operation) if operation.in_progress()
+            => return Err(ServiceError::ConcurrencyError.into()),
+        _   => (),
+    }
+
+    let instance = broker.store().get_instance(&instance_id)?
+                         .ok_or_else(|| ServiceError::NotFound(format!("Instance '{}' does not exist", instance_id)))?;
+    let mut response = broker.bindings().fetch_binding(&instance, &existing).await?;
+    *response.parameters_mut() = existing.parameters().cloned();
+    Ok(HttpResponse::Ok().json(response))
+}
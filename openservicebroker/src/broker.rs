@@ -0,0 +1,134 @@
+use super::{model, service, store};
+use super::locks::{Locks, LockGuard};
+
+use std::sync::Arc;
+
+use actix_web::HttpResponse;
+use anyhow::Result;
+
+/// Shared state of service instances and bindings endpoints.
+///
+/// It is built once and cloned into each server worker, so they all share the same store
+/// and providers.
+#[derive(Clone)]
+pub struct Broker {
+    store: Arc<dyn store::Store>,
+    instances: Arc<dyn service::InstanceProvider>,
+    bindings: Arc<dyn service::BindingProvider>,
+    id_format: model::IdFormat,
+    locks: Arc<Locks>,
+}
+
+impl Broker {
+    /// Creates a broker keeping its state in memory
+    pub fn new(instances: Arc<dyn service::InstanceProvider>, bindings: Arc<dyn service::BindingProvider>) -> Self {
+        Broker {
+            store: Arc::new(store::InMemoryStore::new()),
+            instances,
+            bindings,
+            id_format: model::IdFormat::default(),
+            locks: Arc::new(Locks::new()),
+        }
+    }
+
+    /// Creates a broker, keeping its state in memory, with a single backend handling all operations
+    pub fn from_service_broker(service_broker: Arc<dyn service::ServiceBroker>) -> Self {
+        let provider = Arc::new(service::ServiceBrokerProvider::new(service_broker));
+        Self::new(provider.clone(), provider)
+    }
+
+    pub fn store(&self) -> &dyn store::Store {
+        self.store.as_ref()
+    }
+    pub fn store_mut(&mut self) -> &mut Arc<dyn store::Store> {
+        &mut self.store
+    }
+
+    pub fn instances(&self) -> &dyn service::InstanceProvider {
+        self.instances.as_ref()
+    }
+    pub fn instances_mut(&mut self) -> &mut Arc<dyn service::InstanceProvider> {
+        &mut self.instances
+    }
+
+    pub fn bindings(&self) -> &dyn service::BindingProvider {
+        self.bindings.as_ref()
+    }
+    pub fn bindings_mut(&mut self) -> &mut Arc<dyn service::BindingProvider> {
+        &mut self.bindings
+    }
+
+    /// Format required for instance and binding IDs chosen by platforms
+    pub fn id_format(&self) -> model::IdFormat {
+        self.id_format
+    }
+    pub fn id_format_mut(&mut self) -> &mut model::IdFormat {
+        &mut self.id_format
+    }
+
+    /// Resources currently handled by a request
+    pub fn locks(&self) -> &Locks {
+        &self.locks
+    }
+
+    pub(crate) fn lock_instance(&self, instance_id: &model::InstanceId) -> Result<LockGuard> {
+        Ok(self.locks.lock_instance(instance_id)?)
+    }
+
+    pub(crate) fn lock_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<LockGuard> {
+        Ok(self.locks.lock_binding(instance_id, binding_id)?)
+    }
+
+    pub(crate) fn instance_id(&self, id: &str) -> Result<model::InstanceId> {
+        model::InstanceId::with_format(id, self.id_format)
+                          .map_err(|error| service::ServiceError::BadRequest(format!("Invalid instance ID: {}", error)).into())
+    }
+
+    pub(crate) fn binding_id(&self, id: &str) -> Result<model::BindingId> {
+        model::BindingId::with_format(id, self.id_format)
+                         .map_err(|error| service::ServiceError::BadRequest(format!("Invalid binding ID: {}", error)).into())
+    }
+}
+
+/// Looks for a plan of a service, failing with `400 Bad Request` if it isn't part of the catalog
+pub(crate) fn find_plan<'a>(catalog: &'a model::Catalog, service_id: &model::ServiceId, plan_id: &model::PlanId) -> Result<(&'a model::Service, &'a model::ServicePlan)> {
+    let service = catalog.find_service(service_id)
+                         .ok_or_else(|| service::ServiceError::BadRequest(format!("Unknown service '{}'", service_id)))?;
+    let plan = catalog.find_plan(service_id, plan_id)
+                      .ok_or_else(|| service::ServiceError::BadRequest(format!("Unknown plan '{}' for service '{}'", plan_id, service_id)))?;
+    Ok((service, plan))
+}
+
+/// Operation accepted by a provider, to be polled using `last_operation`
+pub(crate) fn start_operation(kind: store::OperationKind, operation: Option<&str>) -> store::Operation {
+    let mut started = store::Operation::new(kind, model::LastOperationState::InProgress);
+    *started.operation_mut() = operation.map(str::to_owned);
+    started
+}
+
+/// Refuses an asynchronous operation the platform doesn't accept, once the caller has recorded
+/// what the backend has started despite `accepts_incomplete` being false
+pub(crate) fn async_required(kind: store::OperationKind) -> anyhow::Error {
+    tracing::error!(operation = ?kind, "Backend has started an asynchronous operation, although not accepted by the platform");
+    service::ServiceError::AsyncRequired.into()
+}
+
+/// Fails with `ConcurrencyError` if an asynchronous operation is still running, unless it is
+/// of the given kind
+pub(crate) fn check_no_operation(operation: Option<&store::Operation>, allowed: Option<store::OperationKind>) -> Result<()> {
+    match operation {
+        Some(operation) if operation.in_progress() && Some(operation.kind()) != allowed => Err(service::ServiceError::ConcurrencyError.into()),
+        _                                                                               => Ok(()),
+    }
+}
+
+/// Converts an error to the response sent to the platform
+pub(crate) fn error_response(error: anyhow::Error) -> HttpResponse {
+    match error.downcast_ref::<service::ServiceError>() {
+        Some(error) => HttpResponse::build(error.status()).json(error.to_response()),
+        None        => {
+            tracing::error!(error = %super::trace::ErrorChain(&error), "Request has failed");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
@@ -0,0 +1,312 @@
+//! Client calling any Open Service Broker, for platform-side tooling.
+//!
+//! Spec errors answered by brokers are returned as `ServiceError` (inside `anyhow::Error`),
+//! other failures as `ClientError` or transport errors.
+
+use super::model;
+use super::service::{Completion, ServiceError};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use anyhow::Result;
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Specification version sent by default
+pub const API_VERSION: &str = "2.16";
+
+/// Failure not covered by `ServiceError`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    /// Broker answered with a status the specification doesn't define for this request
+    UnexpectedStatus(StatusCode, String),
+    /// Asynchronous operation was still running once polling timeout has elapsed
+    Timeout(Duration),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::UnexpectedStatus(status, body) => write!(f, "Unexpected response {}: {}", status, body),
+            ClientError::Timeout(timeout)               => write!(f, "Operation is still in progress after {}s", timeout.as_secs()),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Response read from a broker
+pub(crate) struct Answer {
+    pub(crate) status: StatusCode,
+    pub(crate) retry_after: Option<Duration>,
+    pub(crate) body: Bytes,
+}
+
+impl Answer {
+    /// Fails with the answered error, unless status is expected
+    pub(crate) fn expect(self, expected: &[StatusCode]) -> Result<Self> {
+        if expected.contains(&self.status) {
+            return Ok(self);
+        }
+        let response = serde_json::from_slice(&self.body).unwrap_or_default();
+        match ServiceError::from_response(self.status, &response) {
+            Some(error) => Err(error.into()),
+            None        => Err(ClientError::UnexpectedStatus(self.status, String::from_utf8_lossy(&self.body).into_owned()).into()),
+        }
+    }
+
+    /// Reads JSON body, an empty one being read as `{}`
+    pub(crate) fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        let body: &[u8] = match self.body.as_ref() {
+            b"" => b"{}",
+            body => body,
+        };
+        serde_json::from_slice(body).with_context(|| format!("Invalid response body: {}", String::from_utf8_lossy(&self.body)))
+    }
+
+    fn completion<T: DeserializeOwned>(self) -> Result<Completion<T>> {
+        let answer = self.expect(&[StatusCode::OK, StatusCode::CREATED, StatusCode::ACCEPTED])?;
+        match answer.status {
+            StatusCode::ACCEPTED => Ok(Completion::Async(answer.json()?)),
+            _                    => Ok(Completion::Sync(answer.json()?)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AsyncQuery {
+    accepts_incomplete: bool,
+}
+
+#[derive(Serialize)]
+struct DeleteQuery<'a> {
+    service_id: &'a model::ServiceId,
+    plan_id: &'a model::PlanId,
+    accepts_incomplete: bool,
+}
+
+#[derive(Serialize)]
+struct LastOperationQuery<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation: Option<&'a str>,
+}
+
+/// Client of a broker, identified by its base URL
+#[derive(Clone)]
+pub struct Client {
+    url: String,
+    api_version: String,
+    basic_auth: Option<(String, String)>,
+    originating_identity: Option<(String, Value)>,
+    poll_interval: Duration,
+    max_poll_interval: Duration,
+    poll_timeout: Duration,
+    client: awc::Client,
+}
+
+impl Client {
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        Client {
+            url: url.into().trim_end_matches('/').to_owned(),
+            api_version: API_VERSION.to_owned(),
+            basic_auth: None,
+            originating_identity: None,
+            poll_interval: Duration::from_secs(1),
+            max_poll_interval: Duration::from_secs(30),
+            poll_timeout: Duration::from_secs(3600),
+            client: awc::Client::default(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Value of `X-Broker-API-Version` header, omitted if empty
+    pub fn api_version(&self) -> &str {
+        &self.api_version
+    }
+    pub fn api_version_mut(&mut self) -> &mut String {
+        &mut self.api_version
+    }
+
+    /// Username and password for Basic authentication
+    pub fn basic_auth(&self) -> Option<&(String, String)> {
+        self.basic_auth.as_ref()
+    }
+    pub fn basic_auth_mut(&mut self) -> &mut Option<(String, String)> {
+        &mut self.basic_auth
+    }
+
+    /// Platform and user properties sent as `X-Broker-API-Originating-Identity` header
+    pub fn originating_identity(&self) -> Option<&(String, Value)> {
+        self.originating_identity.as_ref()
+    }
+    pub fn originating_identity_mut(&mut self) -> &mut Option<(String, Value)> {
+        &mut self.originating_identity
+    }
+
+    /// First delay between `last_operation` polls, doubled after each one unless broker
+    /// sends `Retry-After`
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+    pub fn poll_interval_mut(&mut self) -> &mut Duration {
+        &mut self.poll_interval
+    }
+
+    pub fn max_poll_interval(&self) -> Duration {
+        self.max_poll_interval
+    }
+    pub fn max_poll_interval_mut(&mut self) -> &mut Duration {
+        &mut self.max_poll_interval
+    }
+
+    pub fn poll_timeout(&self) -> Duration {
+        self.poll_timeout
+    }
+    pub fn poll_timeout_mut(&mut self) -> &mut Duration {
+        &mut self.poll_timeout
+    }
+
+    fn originating_identity_header(&self) -> Option<String> {
+        self.originating_identity.as_ref()
+            .map(|(platform, identity)| format!("{} {}", platform, base64::encode(&identity.to_string())))
+    }
+
+    pub(crate) async fn send<Q: Serialize, B: Serialize>(&self, method: awc::http::Method, path: &str, query: Option<&Q>, body: Option<&B>) -> Result<Answer> {
+        let url = format!("{}{}", self.url, path);
+        let mut request = self.client.request(method, &url);
+        if !self.api_version.is_empty() {
+            request = request.header(model::API_VERSION_HEADER, self.api_version.as_str());
+        }
+        if let Some((username, password)) = &self.basic_auth {
+            request = request.basic_auth(username, Some(password));
+        }
+        if let Some(identity) = self.originating_identity_header() {
+            request = request.header(model::ORIGINATING_IDENTITY_HEADER, identity);
+        }
+        if let Some(query) = query {
+            request = request.query(query)?;
+        }
+        let sent = match body {
+            Some(body) => request.send_json(body).await,
+            None       => request.send().await,
+        };
+        let mut response = sent.map_err(|error| anyhow!("Can't call '{}': {}", url, error))?;
+        let body = response.body().limit(1024 * 1024).await
+                           .map_err(|error| anyhow!("Can't read response of '{}': {}", url, error))?;
+        let retry_after = response.headers()
+                                  .get(awc::http::header::RETRY_AFTER)
+                                  .and_then(|value| value.to_str().ok())
+                                  .and_then(|value| value.parse().ok())
+                                  .map(Duration::from_secs);
+        Ok(Answer { status: response.status(), retry_after, body })
+    }
+
+    async fn get<Q: Serialize>(&self, path: &str, query: Option<&Q>) -> Result<Answer> {
+        self.send(awc::http::Method::GET, path, query, None::<&()>).await
+    }
+
+    pub async fn catalog(&self) -> Result<model::Catalog> {
+        self.get("/v2/catalog", None::<&()>).await?.expect(&[StatusCode::OK])?.json()
+    }
+
+    pub async fn provision(&self, instance_id: &model::InstanceId, request: &model::ProvisionRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let path = format!("/v2/service_instances/{}", instance_id);
+        self.send(awc::http::Method::PUT, &path, Some(&AsyncQuery { accepts_incomplete }), Some(request)).await?.completion()
+    }
+
+    pub async fn update(&self, instance_id: &model::InstanceId, request: &model::UpdateRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let path = format!("/v2/service_instances/{}", instance_id);
+        self.send(awc::http::Method::PATCH, &path, Some(&AsyncQuery { accepts_incomplete }), Some(request)).await?.completion()
+    }
+
+    pub async fn deprovision(&self, instance_id: &model::InstanceId, service_id: &model::ServiceId, plan_id: &model::PlanId, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        let path = format!("/v2/service_instances/{}", instance_id);
+        let query = DeleteQuery { service_id, plan_id, accepts_incomplete };
+        self.send(awc::http::Method::DELETE, &path, Some(&query), None::<&()>).await?.completion()
+    }
+
+    pub async fn fetch_instance(&self, instance_id: &model::InstanceId) -> Result<model::InstanceResponse> {
+        let path = format!("/v2/service_instances/{}", instance_id);
+        self.get(&path, None::<&()>).await?.expect(&[StatusCode::OK])?.json()
+    }
+
+    pub async fn last_operation(&self, instance_id: &model::InstanceId, operation: Option<&str>) -> Result<model::LastOperation> {
+        Ok(self.poll(&format!("/v2/service_instances/{}/last_operation", instance_id), operation).await?.0)
+    }
+
+    pub async fn bind(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, request: &model::BindRequest, accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        let path = format!("/v2/service_instances/{}/service_bindings/{}", instance_id, binding_id);
+        self.send(awc::http::Method::PUT, &path, Some(&AsyncQuery { accepts_incomplete }), Some(request)).await?.completion()
+    }
+
+    pub async fn unbind(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, service_id: &model::ServiceId, plan_id: &model::PlanId, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        let path = format!("/v2/service_instances/{}/service_bindings/{}", instance_id, binding_id);
+        let query = DeleteQuery { service_id, plan_id, accepts_incomplete };
+        self.send(awc::http::Method::DELETE, &path, Some(&query), None::<&()>).await?.completion()
+    }
+
+    pub async fn fetch_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<model::BindingResponse> {
+        let path = format!("/v2/service_instances/{}/service_bindings/{}", instance_id, binding_id);
+        self.get(&path, None::<&()>).await?.expect(&[StatusCode::OK])?.json()
+    }
+
+    pub async fn last_binding_operation(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, operation: Option<&str>) -> Result<model::LastOperation> {
+        Ok(self.poll(&format!("/v2/service_instances/{}/service_bindings/{}/last_operation", instance_id, binding_id), operation).await?.0)
+    }
+
+    /// Polls an instance operation until it is over
+    pub async fn wait_instance(&self, instance_id: &model::InstanceId, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.wait(&format!("/v2/service_instances/{}/last_operation", instance_id), operation, false).await
+    }
+
+    /// Polls a deprovisioning until it is over. Deleted instance (`410 Gone`) means it has
+    /// succeeded.
+    pub async fn wait_deprovision(&self, instance_id: &model::InstanceId, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.wait(&format!("/v2/service_instances/{}/last_operation", instance_id), operation, true).await
+    }
+
+    /// Polls a binding operation until it is over
+    pub async fn wait_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.wait(&format!("/v2/service_instances/{}/service_bindings/{}/last_operation", instance_id, binding_id), operation, false).await
+    }
+
+    /// Polls an unbinding until it is over. Deleted binding (`410 Gone`) means it has
+    /// succeeded.
+    pub async fn wait_unbind(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.wait(&format!("/v2/service_instances/{}/service_bindings/{}/last_operation", instance_id, binding_id), operation, true).await
+    }
+
+    async fn poll(&self, path: &str, operation: Option<&str>) -> Result<(model::LastOperation, Option<Duration>)> {
+        let answer = self.get(path, Some(&LastOperationQuery { operation })).await?.expect(&[StatusCode::OK])?;
+        Ok((answer.json()?, answer.retry_after))
+    }
+
+    async fn wait(&self, path: &str, operation: Option<&str>, deleting: bool) -> Result<model::LastOperation> {
+        let started = Instant::now();
+        let mut interval = self.poll_interval;
+        loop {
+            let retry_after = match self.poll(path, operation).await {
+                Ok((last, _)) if last.state().is_terminal() => return Ok(last),
+                Ok((_, retry_after))                        => retry_after,
+                Err(error) => match error.downcast_ref::<ServiceError>() {
+                    Some(ServiceError::Gone) if deleting => return Ok(model::LastOperation::new(model::LastOperationState::Succeeded)),
+                    _                                    => return Err(error),
+                },
+            };
+            let delay = retry_after.unwrap_or(interval);
+            if started.elapsed() + delay > self.poll_timeout {
+                return Err(ClientError::Timeout(self.poll_timeout).into());
+            }
+            actix_rt::time::delay_for(delay).await;
+            interval = std::cmp::min(interval * 2, self.max_poll_interval);
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! Conformance suite checking a running broker against the Open Service Broker specification.
+//!
+//! It drives a broker through its catalog, provisioning, binding and deprovisioning, using a
+//! `Client`. In-process apps can be checked by serving them with `actix_web::test::start`.
+
+use super::model;
+use super::client::{Answer, Client};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Instant;
+
+use actix_web::http::{Method, StatusCode};
+use anyhow::Result;
+use anyhow::{anyhow, bail};
+use serde_json::{json, Value};
+
+/// Specification requirement level of a scenario
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Must,
+    Should,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioResult {
+    name: &'static str,
+    level: Level,
+    outcome: Outcome,
+}
+
+impl ScenarioResult {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn outcome(&self) -> &Outcome {
+        &self.outcome
+    }
+}
+
+/// Outcome of all scenarios, in run order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    results: Vec<ScenarioResult>,
+}
+
+impl ConformanceReport {
+    pub fn results(&self) -> &[ScenarioResult] {
+        &self.results
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ScenarioResult> {
+        self.results.iter().find(|result| result.name == name)
+    }
+
+    /// Whether no `Must` scenario has failed
+    pub fn is_success(&self) -> bool {
+        !self.results.iter().any(|result| result.level == Level::Must && matches!(result.outcome, Outcome::Failed(_)))
+    }
+
+    /// Records a scenario outcome, returning whether it hasn't failed
+    fn record(&mut self, name: &'static str, level: Level, result: Result<Outcome>) -> bool {
+        let outcome = result.unwrap_or_else(|error| Outcome::Failed(format!("{:#}", error)));
+        let failed = matches!(outcome, Outcome::Failed(_));
+        self.results.push(ScenarioResult { name, level, outcome });
+        !failed
+    }
+
+    fn skip(&mut self, names: &[&'static str], reason: &str) {
+        for name in names {
+            self.record(name, Level::Must, Ok(Outcome::Skipped(reason.to_owned())));
+        }
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match (&result.outcome, result.level) {
+                (Outcome::Passed, _)                     => writeln!(f, "[PASS] {}", result.name)?,
+                (Outcome::Failed(reason), Level::Must)   => writeln!(f, "[FAIL] {}: {}", result.name, reason)?,
+                (Outcome::Failed(reason), Level::Should) => writeln!(f, "[WARN] {}: {}", result.name, reason)?,
+                (Outcome::Skipped(reason), _)            => writeln!(f, "[SKIP] {}: {}", result.name, reason)?,
+            }
+        }
+        write!(f, "{}", if self.is_success() { "Broker conforms" } else { "Broker does NOT conform" })
+    }
+}
+
+fn expect_status(answer: &Answer, expected: &[StatusCode]) -> Result<()> {
+    if !expected.contains(&answer.status) {
+        bail!("expected {:?}, got {} ({})", expected.iter().map(StatusCode::as_u16).collect::<Vec<_>>(), answer.status, String::from_utf8_lossy(&answer.body));
+    }
+    Ok(())
+}
+
+/// Checks required fields and uniqueness of identifiers and names
+fn check_catalog(catalog: &model::Catalog) -> Result<Outcome> {
+    if catalog.services().is_empty() {
+        bail!("catalog has no service");
+    }
+    let mut service_ids = HashSet::new();
+    let mut service_names = HashSet::new();
+    let mut plan_ids = HashSet::new();
+    for service in catalog.services() {
+        if !service_ids.insert(service.id()) {
+            bail!("service '{}' is duplicated", service.id());
+        }
+        if service.name().is_empty() || !service_names.insert(service.name()) {
+            bail!("service '{}' has an empty or duplicated name", service.id());
+        }
+        if service.plans().is_empty() {
+            bail!("service '{}' has no plan", service.id());
+        }
+        let mut plan_names = HashSet::new();
+        for plan in service.plans() {
+            if !plan_ids.insert(plan.id()) {
+                bail!("plan '{}' is duplicated", plan.id());
+            }
+            if plan.name().is_empty() || !plan_names.insert(plan.name()) {
+                bail!("plan '{}' has an empty or duplicated name", plan.id());
+            }
+        }
+    }
+    Ok(Outcome::Passed)
+}
+
+const BINDING_SCENARIOS: &[&str] = &["bind", "bind idempotency", "bind conflict", "unbind", "unbind missing"];
+const INSTANCE_SCENARIOS: &[&str] = &["provision idempotency", "provision conflict", "deprovision", "deprovision missing"];
+
+/// Scenarios run against a broker
+pub struct Conformance {
+    client: Client,
+    service_id: Option<model::ServiceId>,
+    plan_id: Option<model::PlanId>,
+}
+
+impl Conformance {
+    pub fn new(client: Client) -> Self {
+        Conformance {
+            client,
+            service_id: None,
+            plan_id: None,
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Service to provision, defaults to the first one having a bindable plan
+    pub fn service_id(&self) -> Option<&model::ServiceId> {
+        self.service_id.as_ref()
+    }
+    pub fn service_id_mut(&mut self) -> &mut Option<model::ServiceId> {
+        &mut self.service_id
+    }
+
+    /// Plan to provision, defaults to the first bindable one of the service
+    pub fn plan_id(&self) -> Option<&model::PlanId> {
+        self.plan_id.as_ref()
+    }
+    pub fn plan_id_mut(&mut self) -> &mut Option<model::PlanId> {
+        &mut self.plan_id
+    }
+
+    pub async fn run(&self) -> ConformanceReport {
+        let mut report = ConformanceReport::default();
+        let catalog = match self.client.catalog().await {
+            Ok(catalog) => catalog,
+            Err(error)  => {
+                report.record("catalog", Level::Must, Err(error));
+                return report;
+            },
+        };
+        report.record("catalog", Level::Must, check_catalog(&catalog));
+        report.record("version header", Level::Should, self.check_version().await);
+        report.record("authentication", Level::Must, self.check_authentication().await);
+
+        let (service, plan) = match self.select_plan(&catalog) {
+            Ok(selected) => selected,
+            Err(error)   => {
+                report.record("provision", Level::Must, Err(error));
+                return report;
+            },
+        };
+        report.record("bind missing instance", Level::Must, self.check_bind_missing_instance(service.id(), plan.id()).await);
+        let instance_id = model::InstanceId::generate();
+        let mut request = model::ProvisionRequest::new(service.id().clone(), plan.id().clone());
+        *request.parameters_mut() = Some(json!({ "conformance": true }));
+
+        let path = format!("/v2/service_instances/{}", instance_id);
+        let provisioning = async {
+            let answer = self.client.send(Method::PUT, &path, Some(&[("accepts_incomplete", "true")]), Some(&request)).await?;
+            expect_status(&answer, &[StatusCode::CREATED, StatusCode::ACCEPTED])?;
+            Ok(answer)
+        }.await;
+        let answer = match provisioning {
+            Ok(answer) => {
+                report.record("provision", Level::Must, Ok(Outcome::Passed));
+                answer
+            },
+            Err(error) => {
+                report.record("provision", Level::Must, Err(error));
+                report.skip(&["last_operation"], "provisioning has failed");
+                report.skip(BINDING_SCENARIOS, "provisioning has failed");
+                report.skip(INSTANCE_SCENARIOS, "provisioning has failed");
+                self.run_not_bindable(&mut report, &catalog).await;
+                return report;
+            },
+        };
+        if !report.record("last_operation", Level::Must, self.check_last_operation(&instance_id, &answer).await) {
+            report.skip(BINDING_SCENARIOS, "provisioning hasn't succeeded");
+            report.skip(&INSTANCE_SCENARIOS[..2], "provisioning hasn't succeeded");
+        } else {
+            self.run_instance(&mut report, &path, &request, &instance_id, service, plan).await;
+        }
+
+        let query = [("service_id", service.id().as_str()), ("plan_id", plan.id().as_str()), ("accepts_incomplete", "true")];
+        report.record("deprovision", Level::Must, self.delete(&path, &query, &instance_id, None).await);
+        report.record("deprovision missing", Level::Must, self.check_missing(&path, &query).await);
+        self.run_not_bindable(&mut report, &catalog).await;
+        report
+    }
+
+    async fn run_instance(&self, report: &mut ConformanceReport, path: &str, request: &model::ProvisionRequest, instance_id: &model::InstanceId, service: &model::Service, plan: &model::ServicePlan) {
+        report.record("provision idempotency", Level::Must, self.check_idempotency(path, request).await);
+        let mut conflicting = request.clone();
+        *conflicting.parameters_mut() = Some(json!({ "conformance": "conflict" }));
+        report.record("provision conflict", Level::Must, self.check_conflict(path, &conflicting).await);
+
+        if plan.effective_bindable(service) {
+            self.run_binding(report, instance_id, service.id(), plan.id()).await;
+        } else {
+            report.skip(BINDING_SCENARIOS, "plan is not bindable");
+        }
+    }
+
+    /// Binds an instance of a non-bindable plan, if the catalog has one
+    async fn run_not_bindable(&self, report: &mut ConformanceReport, catalog: &model::Catalog) {
+        let selected = catalog.services().iter()
+                              .flat_map(|service| service.plans().iter().map(move |plan| (service, plan)))
+                              .find(|(service, plan)| !plan.effective_bindable(service));
+        let outcome = match selected {
+            Some((service, plan)) => self.check_not_bindable(service.id(), plan.id()).await,
+            None                  => Ok(Outcome::Skipped("catalog has no non-bindable plan".to_owned())),
+        };
+        report.record("bind non-bindable plan", Level::Must, outcome);
+    }
+
+    async fn run_binding(&self, report: &mut ConformanceReport, instance_id: &model::InstanceId, service_id: &model::ServiceId, plan_id: &model::PlanId) {
+        let binding_id = model::BindingId::generate();
+        let mut request = model::BindRequest::new(service_id.clone(), plan_id.clone());
+        *request.parameters_mut() = Some(json!({ "conformance": true }));
+
+        let path = format!("/v2/service_instances/{}/service_bindings/{}", instance_id, binding_id);
+        if !report.record("bind", Level::Must, self.create(&path, &request, instance_id, Some(&binding_id)).await) {
+            report.skip(&BINDING_SCENARIOS[1..], "binding has failed");
+            return;
+        }
+        report.record("bind idempotency", Level::Must, self.check_idempotency(&path, &request).await);
+        let mut conflicting = request.clone();
+        *conflicting.parameters_mut() = Some(json!({ "conformance": "conflict" }));
+        report.record("bind conflict", Level::Must, self.check_conflict(&path, &conflicting).await);
+
+        let query = [("service_id", service_id.as_str()), ("plan_id", plan_id.as_str()), ("accepts_incomplete", "true")];
+        report.record("unbind", Level::Must, self.delete(&path, &query, instance_id, Some(&binding_id)).await);
+        report.record("unbind missing", Level::Must, self.check_missing(&path, &query).await);
+    }
+
+    fn select_plan<'a>(&self, catalog: &'a model::Catalog) -> Result<(&'a model::Service, &'a model::ServicePlan)> {
+        let services: Vec<&model::Service> = match &self.service_id {
+            Some(service_id) => vec![catalog.find_service(service_id).ok_or_else(|| anyhow!("service '{}' is not in catalog", service_id))?],
+            None             => catalog.services().iter().collect(),
+        };
+        let candidates = services.iter().flat_map(|service| service.plans().iter().map(move |plan| (*service, plan)));
+        let selected = match &self.plan_id {
+            Some(plan_id) => candidates.clone().find(|(_, plan)| plan.id() == plan_id),
+            None          => candidates.clone().find(|(service, plan)| plan.effective_bindable(service))
+                                       .or_else(|| candidates.clone().next()),
+        };
+        selected.ok_or_else(|| anyhow!("no plan to provision"))
+    }
+
+    async fn check_version(&self) -> Result<Outcome> {
+        for version in &["", "1.0"] {
+            let mut client = self.client.clone();
+            *client.api_version_mut() = (*version).to_owned();
+            let answer = client.send(Method::GET, "/v2/catalog", None::<&()>, None::<&()>).await?;
+            if answer.status != StatusCode::PRECONDITION_FAILED {
+                bail!("version '{}' got {} instead of 412", version, answer.status);
+            }
+        }
+        Ok(Outcome::Passed)
+    }
+
+    async fn check_authentication(&self) -> Result<Outcome> {
+        let (username, _) = match self.client.basic_auth() {
+            Some(credentials) => credentials,
+            None              => return Ok(Outcome::Skipped("no credentials configured".to_owned())),
+        };
+        let attempts = vec![None, Some((username.clone(), "conformance-invalid-password".to_owned()))];
+        for credentials in attempts {
+            let mut client = self.client.clone();
+            *client.basic_auth_mut() = credentials;
+            let answer = client.send(Method::GET, "/v2/catalog", None::<&()>, None::<&()>).await?;
+            if answer.status != StatusCode::UNAUTHORIZED {
+                bail!("invalid credentials got {} instead of 401", answer.status);
+            }
+        }
+        Ok(Outcome::Passed)
+    }
+
+    /// Creates a resource, waiting for its completion
+    async fn create<B: serde::Serialize>(&self, path: &str, request: &B, instance_id: &model::InstanceId, binding_id: Option<&model::BindingId>) -> Result<Outcome> {
+        let answer = self.client.send(Method::PUT, path, Some(&[("accepts_incomplete", "true")]), Some(request)).await?;
+        expect_status(&answer, &[StatusCode::CREATED, StatusCode::ACCEPTED])?;
+        if answer.status == StatusCode::ACCEPTED {
+            self.wait(&answer, instance_id, binding_id, false).await?;
+        }
+        Ok(Outcome::Passed)
+    }
+
+    async fn delete(&self, path: &str, query: &[(&str, &str)], instance_id: &model::InstanceId, binding_id: Option<&model::BindingId>) -> Result<Outcome> {
+        let answer = self.client.send(Method::DELETE, path, Some(&query), None::<&()>).await?;
+        expect_status(&answer, &[StatusCode::OK, StatusCode::ACCEPTED])?;
+        if answer.status == StatusCode::ACCEPTED {
+            self.wait(&answer, instance_id, binding_id, true).await?;
+        }
+        Ok(Outcome::Passed)
+    }
+
+    async fn wait(&self, answer: &Answer, instance_id: &model::InstanceId, binding_id: Option<&model::BindingId>, deleting: bool) -> Result<()> {
+        let body: Value = answer.json()?;
+        let operation = body.get("operation").and_then(Value::as_str);
+        let last = match (binding_id, deleting) {
+            (Some(binding_id), false) => self.client.wait_binding(instance_id, binding_id, operation).await?,
+            (Some(binding_id), true)  => self.client.wait_unbind(instance_id, binding_id, operation).await?,
+            (None, false)             => self.client.wait_instance(instance_id, operation).await?,
+            (None, true)              => self.client.wait_deprovision(instance_id, operation).await?,
+        };
+        if last.state() != model::LastOperationState::Succeeded {
+            bail!("operation has failed: {}", last.description().unwrap_or("no description"));
+        }
+        Ok(())
+    }
+
+    /// Polls asynchronous provisioning until it is over, checking it only moves from
+    /// `in progress` to `succeeded`, and stays there
+    async fn check_last_operation(&self, instance_id: &model::InstanceId, answer: &Answer) -> Result<Outcome> {
+        // Brokers only have to serve last operation of the ones they've answered `202 Accepted` to
+        if answer.status != StatusCode::ACCEPTED {
+            return Ok(Outcome::Skipped("provisioning was synchronous".to_owned()));
+        }
+        let body: Value = answer.json()?;
+        let operation = body.get("operation").and_then(Value::as_str);
+        let started = Instant::now();
+        loop {
+            let last = self.client.last_operation(instance_id, operation).await?;
+            match last.state() {
+                model::LastOperationState::InProgress => (),
+                model::LastOperationState::Succeeded  => break,
+                model::LastOperationState::Failed     => bail!("provisioning has failed: {}", last.description().unwrap_or("no description")),
+            }
+            if started.elapsed() > self.client.poll_timeout() {
+                bail!("provisioning is still in progress after {:?}", self.client.poll_timeout());
+            }
+            actix_rt::time::delay_for(self.client.poll_interval()).await;
+        }
+        let last = self.client.last_operation(instance_id, operation).await?;
+        if last.state() != model::LastOperationState::Succeeded {
+            bail!("succeeded provisioning is then reported as '{:?}'", last.state());
+        }
+        Ok(Outcome::Passed)
+    }
+
+    async fn check_bind_missing_instance(&self, service_id: &model::ServiceId, plan_id: &model::PlanId) -> Result<Outcome> {
+        let path = format!("/v2/service_instances/{}/service_bindings/{}", model::InstanceId::generate(), model::BindingId::generate());
+        let request = model::BindRequest::new(service_id.clone(), plan_id.clone());
+        let answer = self.client.send(Method::PUT, &path, Some(&[("accepts_incomplete", "true")]), Some(&request)).await?;
+        expect_status(&answer, &[StatusCode::BAD_REQUEST, StatusCode::NOT_FOUND, StatusCode::UNPROCESSABLE_ENTITY])?;
+        Ok(Outcome::Passed)
+    }
+
+    /// Provisions an instance of a non-bindable plan, checking it can't be bound
+    async fn check_not_bindable(&self, service_id: &model::ServiceId, plan_id: &model::PlanId) -> Result<Outcome> {
+        let instance_id = model::InstanceId::generate();
+        let path = format!("/v2/service_instances/{}", instance_id);
+        let request = model::ProvisionRequest::new(service_id.clone(), plan_id.clone());
+        self.create(&path, &request, &instance_id, None).await?;
+
+        let request = model::BindRequest::new(service_id.clone(), plan_id.clone());
+        let binding_path = format!("{}/service_bindings/{}", path, model::BindingId::generate());
+        let binding = self.client.send(Method::PUT, &binding_path, Some(&[("accepts_incomplete", "true")]), Some(&request)).await;
+
+        let query = [("service_id", service_id.as_str()), ("plan_id", plan_id.as_str()), ("accepts_incomplete", "true")];
+        self.delete(&path, &query, &instance_id, None).await?;
+        expect_status(&binding?, &[StatusCode::BAD_REQUEST, StatusCode::UNPROCESSABLE_ENTITY])?;
+        Ok(Outcome::Passed)
+    }
+
+    async fn check_idempotency<B: serde::Serialize>(&self, path: &str, request: &B) -> Result<Outcome> {
+        let answer = self.client.send(Method::PUT, path, Some(&[("accepts_incomplete", "true")]), Some(request)).await?;
+        expect_status(&answer, &[StatusCode::OK])?;
+        Ok(Outcome::Passed)
+    }
+
+    async fn check_conflict<B: serde::Serialize>(&self, path: &str, request: &B) -> Result<Outcome> {
+        let answer = self.client.send(Method::PUT, path, Some(&[("accepts_incomplete", "true")]), Some(request)).await?;
+        expect_status(&answer, &[StatusCode::CONFLICT])?;
+        Ok(Outcome::Passed)
+    }
+
+    async fn check_missing(&self, path: &str, query: &[(&str, &str)]) -> Result<Outcome> {
+        let answer = self.client.send(Method::DELETE, path, Some(&query), None::<&()>).await?;
+        expect_status(&answer, &[StatusCode::GONE])?;
+        Ok(Outcome::Passed)
+    }
+}
@@ -0,0 +1,165 @@
+use super::{model, store};
+
+use serde_json::Value;
+
+/// How a provisioning or binding request relates to the stored resource with the same ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idempotency {
+    /// Resource doesn't exist (or its creation has failed), it has to be created: `201 Created`
+    Create,
+    /// Resource already exists with identical attributes: `200 OK`
+    Identical,
+    /// Resource creation with identical attributes is still running: `202 Accepted`
+    InProgress,
+    /// Resource already exists with different attributes: `409 Conflict`
+    Conflict,
+}
+
+/// Compares optional JSON objects, considering a missing one as empty
+fn same_object(left: Option<&Value>, right: Option<&Value>) -> bool {
+    fn is_empty(value: Option<&Value>) -> bool {
+        match value {
+            None                      => true,
+            Some(Value::Null)         => true,
+            Some(Value::Object(map))  => map.is_empty(),
+            Some(_)                   => false,
+        }
+    }
+    match (is_empty(left), is_empty(right)) {
+        (true, true)   => true,
+        (false, false) => left == right,
+        _              => false,
+    }
+}
+
+fn check(operation: Option<&store::Operation>, creation: store::OperationKind, identical: bool) -> Idempotency {
+    match operation {
+        Some(operation) if operation.kind() == creation && operation.state() == model::LastOperationState::Failed => Idempotency::Create,
+        _ if !identical                                                                                          => Idempotency::Conflict,
+        Some(operation) if operation.kind() == creation && operation.in_progress()                              => Idempotency::InProgress,
+        _                                                                                                        => Idempotency::Identical,
+    }
+}
+
+pub fn check_provision(existing: Option<&store::InstanceRecord>, request: &model::ProvisionRequest) -> Idempotency {
+    let existing = match existing {
+        Some(existing) => existing,
+        None           => return Idempotency::Create,
+    };
+    let identical = existing.service_id() == request.service_id()
+                 && existing.plan_id() == request.plan_id()
+                 && same_object(existing.parameters(), request.parameters())
+                 && same_object(existing.context(), request.context());
+    check(existing.operation(), store::OperationKind::Provision, identical)
+}
+
+pub fn check_bind(existing: Option<&store::BindingRecord>, request: &model::BindRequest) -> Idempotency {
+    let existing = match existing {
+        Some(existing) => existing,
+        None           => return Idempotency::Create,
+    };
+    let identical = existing.service_id() == request.service_id()
+                 && existing.plan_id() == request.plan_id()
+                 && same_object(existing.parameters(), request.parameters())
+                 && same_object(existing.context(), request.context())
+                 && same_object(existing.bind_resource(), request.bind_resource());
+    check(existing.operation(), store::OperationKind::Bind, identical)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{model, store, Idempotency, check_provision, check_bind};
+    use serde_json::json;
+
+    fn instance() -> store::InstanceRecord {
+        let mut record = store::InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        *record.parameters_mut() = Some(json!({ "size": 1 }));
+        *record.context_mut() = Some(json!({ "platform": "cloudfoundry" }));
+        record
+    }
+
+    fn provision_request() -> model::ProvisionRequest {
+        let mut request = model::ProvisionRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        *request.parameters_mut() = Some(json!({ "size": 1 }));
+        *request.context_mut() = Some(json!({ "platform": "cloudfoundry" }));
+        request
+    }
+
+    fn operation(kind: store::OperationKind, state: model::LastOperationState) -> Option<store::Operation> {
+        Some(store::Operation::new(kind, state))
+    }
+
+    #[test]
+    fn provision_create() {
+        assert_eq!(Idempotency::Create, check_provision(None, &provision_request()), "missing");
+
+        let mut failed = instance();
+        *failed.operation_mut() = operation(store::OperationKind::Provision, model::LastOperationState::Failed);
+        assert_eq!(Idempotency::Create, check_provision(Some(&failed), &provision_request()), "failed");
+    }
+
+    #[test]
+    fn provision_identical() {
+        assert_eq!(Idempotency::Identical, check_provision(Some(&instance()), &provision_request()), "same");
+
+        let mut succeeded = instance();
+        *succeeded.operation_mut() = operation(store::OperationKind::Provision, model::LastOperationState::Succeeded);
+        assert_eq!(Idempotency::Identical, check_provision(Some(&succeeded), &provision_request()), "succeeded");
+
+        let mut empty = instance();
+        *empty.parameters_mut() = None;
+        let mut request = provision_request();
+        *request.parameters_mut() = Some(json!({}));
+        assert_eq!(Idempotency::Identical, check_provision(Some(&empty), &request), "empty parameters");
+    }
+
+    #[test]
+    fn provision_in_progress() {
+        let mut running = instance();
+        *running.operation_mut() = operation(store::OperationKind::Provision, model::LastOperationState::InProgress);
+        assert_eq!(Idempotency::InProgress, check_provision(Some(&running), &provision_request()), "same");
+
+        let mut request = provision_request();
+        *request.plan_id_mut() = "mysql_small".parse().unwrap();
+        assert_eq!(Idempotency::Conflict, check_provision(Some(&running), &request), "different");
+    }
+
+    #[test]
+    fn provision_conflict() {
+        let mut request = provision_request();
+        *request.service_id_mut() = "pgsql".parse().unwrap();
+        assert_eq!(Idempotency::Conflict, check_provision(Some(&instance()), &request), "service_id");
+
+        let mut request = provision_request();
+        *request.plan_id_mut() = "mysql_small".parse().unwrap();
+        assert_eq!(Idempotency::Conflict, check_provision(Some(&instance()), &request), "plan_id");
+
+        let mut request = provision_request();
+        *request.parameters_mut() = Some(json!({ "size": 2 }));
+        assert_eq!(Idempotency::Conflict, check_provision(Some(&instance()), &request), "parameters");
+
+        let mut request = provision_request();
+        *request.context_mut() = None;
+        assert_eq!(Idempotency::Conflict, check_provision(Some(&instance()), &request), "context");
+    }
+
+    #[test]
+    fn bind() {
+        let request = model::BindRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        assert_eq!(Idempotency::Create, check_bind(None, &request), "missing");
+
+        let mut binding = store::BindingRecord::new("i1".parse().unwrap(), "b1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        assert_eq!(Idempotency::Identical, check_bind(Some(&binding), &request), "identical");
+
+        *binding.operation_mut() = operation(store::OperationKind::Bind, model::LastOperationState::InProgress);
+        assert_eq!(Idempotency::InProgress, check_bind(Some(&binding), &request), "in progress");
+
+        let mut other = request.clone();
+        *other.bind_resource_mut() = Some(json!({ "app_guid": "app" }));
+        assert_eq!(Idempotency::Conflict, check_bind(Some(&binding), &other), "bind_resource");
+
+        *binding.operation_mut() = operation(store::OperationKind::Bind, model::LastOperationState::Failed);
+        assert_eq!(Idempotency::Create, check_bind(Some(&binding), &other), "failed");
+    }
+}
@@ -0,0 +1,322 @@
+use super::{model, store};
+use super::broker::{Broker, async_required, find_plan, error_response, start_operation, check_no_operation};
+use super::idempotency::{self, Idempotency};
+use super::orphans::OrphanGuard;
+use super::service::{CatalogProvider, Completion, ServiceError};
+use super::{server, trace};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Result;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct AsyncQuery {
+    #[serde(default)]
+    accepts_incomplete: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    service_id: model::ServiceId,
+    plan_id: model::PlanId,
+    #[serde(default)]
+    accepts_incomplete: bool,
+}
+
+#[derive(Deserialize)]
+pub struct LastOperationQuery {
+    operation: Option<String>,
+}
+
+impl AsyncQuery {
+    pub fn accepts_incomplete(&self) -> bool {
+        self.accepts_incomplete
+    }
+}
+
+impl DeleteQuery {
+    pub fn service_id(&self) -> &model::ServiceId {
+        &self.service_id
+    }
+
+    pub fn plan_id(&self) -> &model::PlanId {
+        &self.plan_id
+    }
+
+    pub fn accepts_incomplete(&self) -> bool {
+        self.accepts_incomplete
+    }
+}
+
+impl LastOperationQuery {
+    pub fn operation(&self) -> Option<&str> {
+        self.operation.as_deref()
+    }
+}
+
+pub async fn provision(req: HttpRequest,
+                       path: web::Path<String>,
+                       query: web::Query<AsyncQuery>,
+                       body: web::Json<model::ProvisionRequest>,
+                       catalog: web::Data<Box<dyn CatalogProvider>>,
+                       broker: web::Data<Broker>) -> HttpResponse {
+    trace::record_plan(body.service_id(), Some(body.plan_id()));
+    if let Err(error) = server::check_identity(&req, body.service_id()) {
+        return error_response(error);
+    }
+    try_provision(&path, query.accepts_incomplete, body.into_inner(), catalog.get_ref().as_ref(), &broker).await
+                 .unwrap_or_else(error_response)
+}
+
+async fn try_provision(instance_id: &str, accepts_incomplete: bool, request: model::ProvisionRequest, catalog: &dyn CatalogProvider, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let catalog = catalog.get_catalog()?;
+    find_plan(&catalog, request.service_id(), request.plan_id())?;
+
+    let _lock = broker.lock_instance(&instance_id)?;
+    if let Some(existing) = broker.store().get_instance(&instance_id)? {
+        check_no_operation(existing.operation(), Some(store::OperationKind::Provision))?;
+        match idempotency::check_provision(Some(&existing), &request) {
+            Idempotency::Create     => (),
+            Idempotency::Conflict   => return Err(ServiceError::Conflict(format!("Instance '{}' already exists with different attributes", instance_id)).into()),
+            Idempotency::Identical  => {
+                let mut response = model::ProvisionResponse::new();
+                *response.dashboard_url_mut() = existing.dashboard_url().map(str::to_owned);
+                *response.metadata_mut() = existing.metadata().cloned();
+                return Ok(HttpResponse::Ok().json(response));
+            },
+            Idempotency::InProgress => {
+                let mut response = model::ProvisionResponse::new();
+                *response.dashboard_url_mut() = existing.dashboard_url().map(str::to_owned);
+                *response.operation_mut() = existing.operation().and_then(store::Operation::operation).map(str::to_owned);
+                return Ok(HttpResponse::Accepted().json(response));
+            },
+        }
+    }
+
+    let mut record = store::InstanceRecord::new(instance_id.clone(), request.service_id().clone(), request.plan_id().clone());
+    *record.parameters_mut() = request.parameters().cloned();
+    *record.context_mut() = request.context().cloned();
+
+    let mut orphan = OrphanGuard::instance(broker, record.clone());
+    let completion = orphan.check(broker.instances().provision(&instance_id, &request, accepts_incomplete).await)?;
+    if completion.is_async() && !accepts_incomplete {
+        // Unknown to the platform, so left to orphan cleanup
+        orphan.orphan()?;
+        return Err(async_required(store::OperationKind::Provision));
+    }
+    let is_async = completion.is_async();
+    let response = completion.into_inner();
+
+    *record.dashboard_url_mut() = response.dashboard_url().map(str::to_owned);
+    *record.metadata_mut() = response.metadata().cloned();
+    if is_async {
+        *record.operation_mut() = Some(start_operation(store::OperationKind::Provision, response.operation()));
+    }
+    broker.store().put_instance(record)?;
+    orphan.disarm();
+
+    Ok(if is_async {
+        HttpResponse::Accepted().json(response)
+    } else {
+        HttpResponse::Created().json(response)
+    })
+}
+
+pub async fn update(req: HttpRequest,
+                    path: web::Path<String>,
+                    query: web::Query<AsyncQuery>,
+                    body: web::Json<model::UpdateRequest>,
+                    catalog: web::Data<Box<dyn CatalogProvider>>,
+                    broker: web::Data<Broker>) -> HttpResponse {
+    trace::record_plan(body.service_id(), body.plan_id());
+    if let Err(error) = server::check_identity(&req, body.service_id()) {
+        return error_response(error);
+    }
+    try_update(&path, query.accepts_incomplete, body.into_inner(), catalog.get_ref().as_ref(), &broker).await
+              .unwrap_or_else(error_response)
+}
+
+async fn try_update(instance_id: &str, accepts_incomplete: bool, request: model::UpdateRequest, catalog: &dyn CatalogProvider, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let _lock = broker.lock_instance(&instance_id)?;
+    let existing = broker.store().get_instance(&instance_id)?
+                         .ok_or_else(|| ServiceError::NotFound(format!("Instance '{}' does not exist", instance_id)))?;
+    check_no_operation(existing.operation(), None)?;
+    if existing.service_id() != request.service_id() {
+        return Err(ServiceError::BadRequest(format!("Instance '{}' belongs to service '{}'", instance_id, existing.service_id())).into());
+    }
+
+    let catalog = catalog.get_catalog()?;
+    let (service, plan) = find_plan(&catalog, existing.service_id(), existing.plan_id())?;
+    if let Some(plan_id) = request.plan_id().filter(|plan_id| *plan_id != existing.plan_id()) {
+        find_plan(&catalog, request.service_id(), plan_id)?;
+        if !plan.effective_plan_updateable(service) {
+            return Err(ServiceError::UnprocessableEntity(format!("Plan of instance '{}' can't be updated", instance_id)).into());
+        }
+    }
+
+    let completion = broker.instances().update(&existing, &request, accepts_incomplete).await?;
+    let is_async = completion.is_async();
+    let response = completion.into_inner();
+
+    broker.store().update_instance(&instance_id, &mut |record| {
+        if let Some(plan_id) = request.plan_id() {
+            *record.plan_id_mut() = plan_id.clone();
+        }
+        if let Some(parameters) = request.parameters() {
+            *record.parameters_mut() = Some(parameters.clone());
+        }
+        if let Some(context) = request.context() {
+            *record.context_mut() = Some(context.clone());
+        }
+        if let Some(dashboard_url) = response.dashboard_url() {
+            *record.dashboard_url_mut() = Some(dashboard_url.to_owned());
+        }
+        if let Some(metadata) = response.metadata() {
+            *record.metadata_mut() = Some(metadata.clone());
+        }
+        *record.operation_mut() = if is_async {
+            Some(start_operation(store::OperationKind::Update, response.operation()))
+        } else {
+            None
+        };
+    })?;
+    // Recorded anyway, so that the running update is tracked and later operations wait for it
+    if is_async && !accepts_incomplete {
+        return Err(async_required(store::OperationKind::Update));
+    }
+
+    Ok(if is_async {
+        HttpResponse::Accepted().json(response)
+    } else {
+        HttpResponse::Ok().json(response)
+    })
+}
+
+pub async fn deprovision(path: web::Path<String>,
+                         query: web::Query<DeleteQuery>,
+                         broker: web::Data<Broker>) -> HttpResponse {
+    try_deprovision(&path, &query, &broker).await
+                   .unwrap_or_else(error_response)
+}
+
+async fn try_deprovision(instance_id: &str, query: &DeleteQuery, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let _lock = broker.lock_instance(&instance_id)?;
+    let existing = broker.store().get_instance(&instance_id)?
+                         .ok_or(ServiceError::Gone)?;
+    if existing.service_id() != &query.service_id || existing.plan_id() != &query.plan_id {
+        return Err(ServiceError::BadRequest(format!("Instance '{}' belongs to service '{}' and plan '{}'", instance_id, existing.service_id(), existing.plan_id())).into());
+    }
+    check_no_operation(existing.operation(), Some(store::OperationKind::Deprovision))?;
+    if let Some(running) = existing.operation().filter(|operation| operation.in_progress()) {
+        let mut response = model::OperationResponse::new();
+        *response.operation_mut() = running.operation().map(str::to_owned);
+        return Ok(HttpResponse::Accepted().json(response));
+    }
+
+    match broker.instances().deprovision(&existing, query.accepts_incomplete).await? {
+        Completion::Async(response) => {
+            broker.store().update_instance(&instance_id, &mut |record| {
+                *record.operation_mut() = Some(start_operation(store::OperationKind::Deprovision, response.operation()));
+            })?;
+            if !query.accepts_incomplete {
+                return Err(async_required(store::OperationKind::Deprovision));
+            }
+            Ok(HttpResponse::Accepted().json(response))
+        },
+        Completion::Sync(response) => {
+            delete_instance(broker, &instance_id)?;
+            Ok(HttpResponse::Ok().json(response))
+        },
+    }
+}
+
+/// Removes an instance and its bindings from store
+pub(crate) fn delete_instance(broker: &Broker, instance_id: &model::InstanceId) -> Result<()> {
+    for binding in broker.store().list_bindings(instance_id)? {
+        broker.store().delete_binding(instance_id, binding.binding_id())?;
+    }
+    broker.store().delete_instance(instance_id)?;
+    Ok(())
+}
+
+pub async fn last_operation(path: web::Path<String>,
+                            query: web::Query<LastOperationQuery>,
+                            broker: web::Data<Broker>) -> HttpResponse {
+    try_last_operation(&path, query.operation.as_deref(), &broker).await
+                      .unwrap_or_else(error_response)
+}
+
+async fn try_last_operation(instance_id: &str, operation: Option<&str>, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let existing = broker.store().get_instance(&instance_id)?
+                         .ok_or(ServiceError::Gone)?;
+    let current = match existing.operation() {
+        Some(current) => current,
+        None          => return Ok(HttpResponse::Ok().json(model::LastOperation::new(model::LastOperationState::Succeeded))),
+    };
+    if !current.in_progress() {
+        let mut last = model::LastOperation::new(current.state());
+        *last.description_mut() = current.description().map(str::to_owned);
+        return Ok(HttpResponse::Ok().json(last));
+    }
+
+    let last = poll_operation(broker, &existing, current, operation).await?;
+    Ok(HttpResponse::Ok().json(last))
+}
+
+/// Polls the provider about a running operation, recording its outcome
+pub(crate) async fn poll_operation(broker: &Broker, existing: &store::InstanceRecord, current: &store::Operation, operation: Option<&str>) -> Result<model::LastOperation> {
+    let instance_id = existing.instance_id();
+    let operation = operation.or_else(|| current.operation());
+    let last = broker.instances().last_operation(existing, operation).await?;
+    if current.kind() == store::OperationKind::Deprovision && last.state() == model::LastOperationState::Succeeded {
+        delete_instance(broker, instance_id)?;
+    } else {
+        broker.store().update_instance(instance_id, &mut |record| {
+            if let Some(current) = record.operation_mut() {
+                *current.state_mut() = last.state();
+                *current.description_mut() = last.description().map(str::to_owned);
+            }
+        })?;
+    }
+    Ok(last)
+}
+
+pub async fn fetch(path: web::Path<String>,
+                   catalog: web::Data<Box<dyn CatalogProvider>>,
+                   broker: web::Data<Broker>) -> HttpResponse {
+    try_fetch(&path, catalog.get_ref().as_ref(), &broker).await
+             .unwrap_or_else(error_response)
+}
+
+/// Instances being provisioned are reported as missing, while other running operations
+/// lead to `422 ConcurrencyError`.
+async fn try_fetch(instance_id: &str, catalog: &dyn CatalogProvider, broker: &Broker) -> Result<HttpResponse> {
+    let instance_id = broker.instance_id(instance_id)?;
+    let existing = broker.store().get_instance(&instance_id)?
+                         .ok_or_else(|| ServiceError::NotFound(format!("Instance '{}' does not exist", instance_id)))?;
+    if existing.orphaned_at().is_some() {
+        return Err(ServiceError::NotFound(format!("Instance '{}' provisioning outcome is unknown", instance_id)).into());
+    }
+
+    let catalog = catalog.get_catalog()?;
+    let service = catalog.find_service(existing.service_id())
+                         .ok_or_else(|| ServiceError::BadRequest(format!("Unknown service '{}'", existing.service_id())))?;
+    if service.instances_retrievable() != Some(true) {
+        return Err(ServiceError::NotFound(format!("Service '{}' does not support fetching instances", service.id())).into());
+    }
+
+    match existing.operation() {
+        Some(operation) if operation.in_progress() && operation.kind() == store::OperationKind::Provision
+            => return Err(ServiceError::NotFound(format!("Instance '{}' is being provisioned", instance_id)).into()),
+        Some(operation) if operation.in_progress()
+            => return Err(ServiceError::ConcurrencyError.into()),
+        _   => (),
+    }
+
+    let response = broker.instances().fetch_instance(&existing).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
@@ -2,6 +2,23 @@ use actix_web::{web, HttpRequest, HttpResponse};
 
 pub mod model;
 pub mod service;
+pub mod store;
+pub mod broker;
+pub mod idempotency;
+pub mod locks;
+pub mod orphans;
+pub mod router;
+pub mod backends;
+pub mod client;
+pub mod conformance;
+pub mod platform;
+pub mod server;
+pub mod shutdown;
+pub mod trace;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod instances;
+pub mod bindings;
 
 pub fn new_scope(path: &str, catalog: Box<dyn service::CatalogProvider>) -> actix_web::Scope {
     actix_web::Scope::new(path)
@@ -9,11 +26,46 @@ pub fn new_scope(path: &str, catalog: Box<dyn service::CatalogProvider>) -> acti
                      .route("/v2/catalog", web::get().to(get_catalog))
 }
 
-pub async fn get_catalog(_req: HttpRequest, data: web::Data<Box<dyn service::CatalogProvider>>) -> HttpResponse {
+/// Scope serving the catalog as well as service instances and bindings endpoints
+pub fn new_broker_scope(path: &str, catalog: Box<dyn service::CatalogProvider>, broker: broker::Broker) -> actix_web::Scope {
+    new_scope(path, catalog)
+        .data(broker)
+        .route("/v2/service_instances/{instance_id}", web::get().to(instances::fetch))
+        .route("/v2/service_instances/{instance_id}", web::put().to(instances::provision))
+        .route("/v2/service_instances/{instance_id}", web::patch().to(instances::update))
+        .route("/v2/service_instances/{instance_id}", web::delete().to(instances::deprovision))
+        .route("/v2/service_instances/{instance_id}/last_operation", web::get().to(instances::last_operation))
+        .route("/v2/service_instances/{instance_id}/service_bindings/{binding_id}", web::get().to(bindings::fetch))
+        .route("/v2/service_instances/{instance_id}/service_bindings/{binding_id}", web::put().to(bindings::bind))
+        .route("/v2/service_instances/{instance_id}/service_bindings/{binding_id}", web::delete().to(bindings::unbind))
+        .route("/v2/service_instances/{instance_id}/service_bindings/{binding_id}/last_operation", web::get().to(bindings::last_operation))
+}
+
+/// Scope serving a complete broker, whose operations are all handled by a single backend
+pub fn new_service_broker_scope<B: service::ServiceBroker + 'static>(path: &str, catalog: Box<dyn service::CatalogProvider>, service_broker: B) -> actix_web::Scope {
+    new_broker_scope(path, catalog, broker::Broker::from_service_broker(std::sync::Arc::new(service_broker)))
+}
+
+/// Scope serving a complete broker dispatching operations by service, failing if some catalog
+/// entries have no backend
+pub fn new_router_scope(path: &str, catalog: Box<dyn service::CatalogProvider>, router: router::ServiceRouter) -> anyhow::Result<actix_web::Scope> {
+    router.check(catalog.get_catalog()?.as_ref())?;
+    Ok(new_service_broker_scope(path, catalog, router))
+}
+
+pub async fn get_catalog(req: HttpRequest, data: web::Data<Box<dyn service::CatalogProvider>>) -> HttpResponse {
     match data.get_catalog() {
-        Ok(catalog) => HttpResponse::Ok().json(catalog),
+        // Platforms authenticated by certificate may only see some services
+        Ok(catalog) => match req.extensions().get::<server::BrokerIdentity>() {
+            Some(identity) => {
+                let mut catalog = catalog.into_owned();
+                identity.filter_catalog(&mut catalog);
+                HttpResponse::Ok().json(catalog)
+            },
+            None => HttpResponse::Ok().json(catalog),
+        },
         Err(error)  => {
-            eprintln!("ERROR: {:?}", error);
+            tracing::error!(error = %trace::ErrorChain(&error), "Can't get catalog");
             HttpResponse::InternalServerError().finish()
         },
     }
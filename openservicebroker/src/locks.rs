@@ -0,0 +1,129 @@
+use super::{model, service::ServiceError};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Default)]
+struct InstanceLocks {
+    exclusive: bool,
+    bindings: HashSet<model::BindingId>,
+}
+
+/// Resources currently handled by a request.
+///
+/// Instance operations lock their instance exclusively, while binding operations only lock
+/// their binding but prevent instance operations to run meanwhile. A conflicting request fails
+/// immediately with `ConcurrencyError`, rather than waiting.
+#[derive(Default)]
+pub struct Locks {
+    instances: Mutex<HashMap<model::InstanceId, InstanceLocks>>,
+}
+
+/// Lock held on an instance or a binding, released on drop
+pub struct LockGuard {
+    locks: Arc<Locks>,
+    instance_id: model::InstanceId,
+    binding_id: Option<model::BindingId>,
+}
+
+impl Locks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn instances(&self) -> MutexGuard<'_, HashMap<model::InstanceId, InstanceLocks>> {
+        // State is always left consistent, so poisoning can be ignored
+        self.instances.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn lock_instance(self: &Arc<Self>, instance_id: &model::InstanceId) -> Result<LockGuard, ServiceError> {
+        let mut instances = self.instances();
+        let locks = instances.entry(instance_id.clone()).or_default();
+        if locks.exclusive || !locks.bindings.is_empty() {
+            return Err(ServiceError::ConcurrencyError);
+        }
+        locks.exclusive = true;
+        Ok(LockGuard {
+            locks: self.clone(),
+            instance_id: instance_id.clone(),
+            binding_id: None,
+        })
+    }
+
+    pub fn lock_binding(self: &Arc<Self>, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<LockGuard, ServiceError> {
+        let mut instances = self.instances();
+        let locks = instances.entry(instance_id.clone()).or_default();
+        if locks.exclusive || !locks.bindings.insert(binding_id.clone()) {
+            if !locks.exclusive && locks.bindings.is_empty() {
+                instances.remove(instance_id);
+            }
+            return Err(ServiceError::ConcurrencyError);
+        }
+        Ok(LockGuard {
+            locks: self.clone(),
+            instance_id: instance_id.clone(),
+            binding_id: Some(binding_id.clone()),
+        })
+    }
+
+    pub fn is_locked(&self, instance_id: &model::InstanceId) -> bool {
+        self.instances().contains_key(instance_id)
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let mut instances = self.locks.instances();
+        if let Some(locks) = instances.get_mut(&self.instance_id) {
+            match &self.binding_id {
+                Some(binding_id) => { locks.bindings.remove(binding_id); },
+                None             => locks.exclusive = false,
+            }
+            if !locks.exclusive && locks.bindings.is_empty() {
+                instances.remove(&self.instance_id);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{model, Locks, ServiceError};
+    use std::sync::Arc;
+
+    fn ids() -> (model::InstanceId, model::BindingId, model::BindingId) {
+        ("i1".parse().unwrap(), "b1".parse().unwrap(), "b2".parse().unwrap())
+    }
+
+    #[test]
+    fn lock_instance() {
+        let locks = Arc::new(Locks::new());
+        let (i1, b1, _) = ids();
+
+        let guard = locks.lock_instance(&i1).expect("first lock");
+        assert_eq!(Some(ServiceError::ConcurrencyError), locks.lock_instance(&i1).err(), "instance");
+        assert_eq!(Some(ServiceError::ConcurrencyError), locks.lock_binding(&i1, &b1).err(), "binding");
+        assert!(locks.lock_instance(&"i2".parse().unwrap()).is_ok(), "other instance");
+
+        drop(guard);
+        assert!(!locks.is_locked(&i1), "released");
+        assert!(locks.lock_instance(&i1).is_ok(), "relock");
+    }
+
+    #[test]
+    fn lock_binding() {
+        let locks = Arc::new(Locks::new());
+        let (i1, b1, b2) = ids();
+
+        let guard = locks.lock_binding(&i1, &b1).expect("first lock");
+        assert_eq!(Some(ServiceError::ConcurrencyError), locks.lock_binding(&i1, &b1).err(), "binding");
+        assert_eq!(Some(ServiceError::ConcurrencyError), locks.lock_instance(&i1).err(), "instance");
+        let other = locks.lock_binding(&i1, &b2).expect("other binding");
+
+        drop(guard);
+        assert!(locks.is_locked(&i1), "partially released");
+        drop(other);
+        assert!(!locks.is_locked(&i1), "released");
+    }
+}
@@ -1,28 +1,340 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::OnceLock;
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+/// Validation applied to instance and binding identifiers.
+///
+/// OSB only requires identifiers to be non-empty, but recommends GUIDs. As platform chosen
+/// ones are part of request paths, characters are restricted to URL unreserved ones and
+/// dot segments (`.` and `..`) are rejected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdFormat {
+    #[default]
+    /// Non-empty string made of ASCII alphanumerics, `-`, `_`, `.` or `~`
+    Any,
+    /// Hyphenated UUID (e.g. `0a9f0c86-6b3e-4d6c-9a3b-1f8c7d2e5a41`)
+    Uuid,
+}
+
+impl IdFormat {
+    pub fn validate(self, id: &str) -> Result<(), IdError> {
+        if id.is_empty() {
+            return Err(IdError::Empty);
+        }
+        if id == "." || id == ".." {
+            return Err(IdError::DotSegment(id.to_owned()));
+        }
+        if let Some(c) = id.chars().find(|c| !(c.is_ascii_alphanumeric() || "-_.~".contains(*c))) {
+            return Err(IdError::InvalidCharacter(id.to_owned(), c));
+        }
+        if self == IdFormat::Uuid && !is_uuid(id) {
+            return Err(IdError::NotUuid(id.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Catalog identifiers are chosen by the broker, they only have to be non-empty
+fn validate_catalog_id(id: &str) -> Result<(), IdError> {
+    if id.is_empty() {
+        return Err(IdError::Empty);
+    }
+    Ok(())
+}
+
+fn validate_platform_id(id: &str) -> Result<(), IdError> {
+    IdFormat::Any.validate(id)
+}
+
+fn generate_uuid() -> String {
+    use rand::Rng;
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    // Version 4, variant 1
+    format!("{}-{}-4{}-{:x}{}-{}", &hex[0..8], &hex[8..12], &hex[13..16], 8 | (bytes[8] & 0x3), &hex[17..20], &hex[20..32])
+}
+
+fn is_uuid(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.len() == 5
+        && groups.iter().zip(&[8, 4, 4, 4, 12]).all(|(group, len)| group.len() == *len)
+        && groups.iter().all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdError {
+    Empty,
+    DotSegment(String),
+    InvalidCharacter(String, char),
+    NotUuid(String),
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdError::Empty                     => write!(f, "identifier must not be empty"),
+            IdError::DotSegment(id)            => write!(f, "identifier '{}' is a dot segment", id),
+            IdError::InvalidCharacter(id, c)   => write!(f, "identifier '{}' contains invalid character '{}'", id, c),
+            IdError::NotUuid(id)               => write!(f, "identifier '{}' is not a UUID", id),
+        }
+    }
+}
+
+impl std::error::Error for IdError {}
+
+macro_rules! identifier {
+    ($(#[$meta:meta])* $name:ident, $validate:path) => {
+        $(#[$meta])*
+        ///
+        /// Default value is empty and only stands as a placeholder while building a model,
+        /// it doesn't pass validation.
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new<S: Into<String>>(id: S) -> Result<Self, IdError> {
+                let id = id.into();
+                $validate(&id)?;
+                Ok($name(id))
+            }
+
+            /// Random (version 4) UUID identifier
+            pub fn generate() -> Self {
+                $name(generate_uuid())
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = IdError;
+
+            fn try_from(id: String) -> Result<Self, IdError> {
+                Self::new(id)
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = IdError;
 
-#[derive(Clone, Serialize, Deserialize)]
+            fn from_str(id: &str) -> Result<Self, IdError> {
+                Self::new(id)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> String {
+                id.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::borrow::Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl PartialEq<$name> for str {
+            fn eq(&self, other: &$name) -> bool {
+                self == other.0
+            }
+        }
+
+        impl PartialEq<$name> for &str {
+            fn eq(&self, other: &$name) -> bool {
+                *self == other.0
+            }
+        }
+    };
+}
+
+/// Validation against an [`IdFormat`](enum.IdFormat.html), for platform chosen identifiers
+macro_rules! formatted_identifier {
+    ($name:ident) => {
+        impl $name {
+            pub fn with_format<S: Into<String>>(id: S, format: IdFormat) -> Result<Self, IdError> {
+                let id = id.into();
+                format.validate(&id)?;
+                Ok($name(id))
+            }
+
+            pub fn validate(&self, format: IdFormat) -> Result<(), IdError> {
+                format.validate(&self.0)
+            }
+        }
+    };
+}
+
+identifier!(
+    /// Identifier of a [`Service`](struct.Service.html) offering
+    ServiceId, validate_catalog_id
+);
+identifier!(
+    /// Identifier of a [`ServicePlan`](struct.ServicePlan.html)
+    PlanId, validate_catalog_id
+);
+identifier!(
+    /// Identifier of a service instance, chosen by the platform
+    InstanceId, validate_platform_id
+);
+identifier!(
+    /// Identifier of a service binding, chosen by the platform
+    BindingId, validate_platform_id
+);
+formatted_identifier!(InstanceId);
+formatted_identifier!(BindingId);
+
+#[derive(Clone, Serialize)]
 pub struct Catalog {
     services: Vec<Service>,
+    #[serde(skip)]
+    index: OnceLock<CatalogIndex>,
+}
+
+/// Positions of services and plans, built on deserialization or on first lookup, and dropped on
+/// mutation.
+#[derive(Clone, Default)]
+struct CatalogIndex {
+    services: HashMap<ServiceId, usize>,
+    plans: HashMap<PlanId, (usize, usize)>,
+}
+
+impl CatalogIndex {
+    fn build(services: &[Service]) -> Self {
+        let mut index = CatalogIndex::default();
+        for (s, service) in services.iter().enumerate() {
+            index.services.entry(service.id().clone()).or_insert(s);
+            for (p, plan) in service.plans().iter().enumerate() {
+                index.plans.entry(plan.id().clone()).or_insert((s, p));
+            }
+        }
+        index
+    }
+}
+
+impl<'de> Deserialize<'de> for Catalog {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            services: Vec<Service>,
+        }
+        let Fields { services } = Fields::deserialize(deserializer)?;
+        let catalog = Catalog { services, index: OnceLock::new() };
+        // Served catalogs are looked up on each request
+        catalog.index();
+        Ok(catalog)
+    }
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Catalog {
     pub fn new() -> Catalog {
-        Catalog { services: vec![] }
+        Catalog { services: vec![], index: OnceLock::new() }
     }
 
     pub fn services(&self) -> &Vec<Service> {
         &self.services
     }
     pub fn services_mut(&mut self) -> &mut Vec<Service> {
+        self.index = OnceLock::new();
         &mut self.services
     }
+
+    fn index(&self) -> &CatalogIndex {
+        self.index.get_or_init(|| CatalogIndex::build(&self.services))
+    }
+
+    pub fn find_service(&self, service_id: &ServiceId) -> Option<&Service> {
+        self.index()
+            .services
+            .get(service_id)
+            .map(|s| &self.services[*s])
+    }
+
+    /// Finds a plan, only if it belongs to the given service
+    pub fn find_plan(&self, service_id: &ServiceId, plan_id: &PlanId) -> Option<&ServicePlan> {
+        self.plan_with_service(plan_id)
+            .filter(|(service, _)| service.id() == service_id)
+            .map(|(_, plan)| plan)
+    }
+
+    pub fn plan_with_service(&self, plan_id: &PlanId) -> Option<(&Service, &ServicePlan)> {
+        self.index()
+            .plans
+            .get(plan_id)
+            .map(|(s, p)| {
+                let service = &self.services[*s];
+                (service, &service.plans[*p])
+            })
+    }
+}
+
+/// Specification version requested by the platform
+pub const API_VERSION_HEADER: &str = "X-Broker-API-Version";
+/// Platform and base64 encoded user properties on whose behalf the request is made
+pub const ORIGINATING_IDENTITY_HEADER: &str = "X-Broker-API-Originating-Identity";
+/// Platform chosen identity of a request, for correlating logs
+pub const REQUEST_IDENTITY_HEADER: &str = "X-Broker-API-Request-Identity";
+
+/// `requires` entry allowing bindings to return a `syslog_drain_url`
+pub const REQUIRES_SYSLOG_DRAIN: &str = "syslog_drain";
+/// `requires` entry allowing bindings to return a `route_service_url`
+pub const REQUIRES_ROUTE_FORWARDING: &str = "route_forwarding";
+/// `requires` entry allowing bindings to return `volume_mounts`
+pub const REQUIRES_VOLUME_MOUNT: &str = "volume_mount";
+
+/// Service metadata, `shareable` being typed while other entries are kept as strings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ServiceMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shareable: Option<bool>,
+    #[serde(flatten)]
+    entries: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     name: String,
-    id: String,
+    id: ServiceId,
     description: String,
     tags: Vec<String>,
     requires: Vec<String>,
@@ -30,17 +342,23 @@ pub struct Service {
     instances_retrievable: Option<bool>,
     bindings_retrievable: Option<bool>,
     allow_context_updates: Option<bool>,
-    metadata: HashMap<String, String>,
+    metadata: ServiceMetadata,
     // dashboard_client: Option<DashboardClient>,
     plan_updateable: Option<bool>,
     plans: Vec<ServicePlan>,
 }
 
+impl Default for Service {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Service {
     pub fn new() -> Service {
         Service {
             name: String::new(),
-            id: String::new(),
+            id: ServiceId::default(),
             description: String::new(),
             tags: Vec::new(),
             requires: Vec::new(),
@@ -48,7 +366,7 @@ impl Service {
             instances_retrievable: None,
             bindings_retrievable: None,
             allow_context_updates: None,
-            metadata: HashMap::new(),
+            metadata: ServiceMetadata::default(),
             plan_updateable: None,
             plans: Vec::new(),
         }
@@ -61,10 +379,10 @@ impl Service {
         &mut self.name
     }
 
-    pub fn id(&self) -> &str {
+    pub fn id(&self) -> &ServiceId {
         &self.id
     }
-    pub fn id_mut(&mut self) -> &mut String {
+    pub fn id_mut(&mut self) -> &mut ServiceId {
         &mut self.id
     }
 
@@ -88,6 +406,9 @@ impl Service {
     pub fn requires_mut(&mut self) -> &mut Vec<String> {
         &mut self.requires
     }
+    pub fn has_requirement(&self, requirement: &str) -> bool {
+        self.requires.iter().any(|required| required == requirement)
+    }
 
     pub fn bindable(&self) -> bool {
         self.bindable
@@ -117,11 +438,20 @@ impl Service {
         &mut self.allow_context_updates
     }
 
+    /// Metadata entries, except `shareable`
     pub fn metadata(&self) -> &HashMap<String, String> {
-        &self.metadata
+        &self.metadata.entries
     }
     pub fn metadata_mut(&mut self) -> &mut HashMap<String, String> {
-        &mut self.metadata
+        &mut self.metadata.entries
+    }
+
+    /// Whether instances can be shared across spaces (`shareable` metadata)
+    pub fn shareable(&self) -> Option<bool> {
+        self.metadata.shareable
+    }
+    pub fn shareable_mut(&mut self) -> &mut Option<bool> {
+        &mut self.metadata.shareable
     }
 
     pub fn plan_updateable(&self) -> Option<bool> {
@@ -141,7 +471,7 @@ impl Service {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServicePlan {
-    id: String,
+    id: PlanId,
     name: String,
     description: String,
     metadata: HashMap<String, String>,
@@ -153,10 +483,16 @@ pub struct ServicePlan {
     // maintenance_info: Option<MaintenanceInfo>,
 }
 
+impl Default for ServicePlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ServicePlan {
     pub fn new() -> ServicePlan {
         ServicePlan {
-            id: String::new(),
+            id: PlanId::default(),
             name: String::new(),
             description: String::new(),
             metadata: HashMap::new(),
@@ -167,10 +503,10 @@ impl ServicePlan {
         }
     }
 
-    pub fn id(&self) -> &str {
+    pub fn id(&self) -> &PlanId {
         &self.id
     }
-    pub fn id_mut(&mut self) -> &mut String {
+    pub fn id_mut(&mut self) -> &mut PlanId {
         &mut self.id
     }
 
@@ -222,13 +558,831 @@ impl ServicePlan {
     pub fn maximum_polling_duration_mut(&mut self) -> &mut Option<u64> {
         &mut self.maximum_polling_duration
     }
+
+    /// Plan `bindable` flag, falling back to the service one
+    pub fn effective_bindable(&self, service: &Service) -> bool {
+        self.bindable.unwrap_or_else(|| service.bindable())
+    }
+
+    /// Plan `plan_updateable` flag, falling back to the service one (default: `false`)
+    pub fn effective_plan_updateable(&self, service: &Service) -> bool {
+        self.plan_updateable
+            .or_else(|| service.plan_updateable())
+            .unwrap_or(false)
+    }
+}
+
+/// State of an asynchronous operation, as reported by `last_operation` endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LastOperationState {
+    #[serde(rename = "in progress")]
+    InProgress,
+    #[serde(rename = "succeeded")]
+    Succeeded,
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+impl LastOperationState {
+    pub fn is_terminal(self) -> bool {
+        self != LastOperationState::InProgress
+    }
+}
+
+/// Body of `PUT /v2/service_instances/{instance_id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvisionRequest {
+    service_id: ServiceId,
+    plan_id: PlanId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    organization_guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    space_guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parameters: Option<Value>,
+}
+
+impl ProvisionRequest {
+    pub fn new(service_id: ServiceId, plan_id: PlanId) -> ProvisionRequest {
+        ProvisionRequest {
+            service_id,
+            plan_id,
+            context: None,
+            organization_guid: None,
+            space_guid: None,
+            parameters: None,
+        }
+    }
+
+    pub fn service_id(&self) -> &ServiceId {
+        &self.service_id
+    }
+    pub fn service_id_mut(&mut self) -> &mut ServiceId {
+        &mut self.service_id
+    }
+
+    pub fn plan_id(&self) -> &PlanId {
+        &self.plan_id
+    }
+    pub fn plan_id_mut(&mut self) -> &mut PlanId {
+        &mut self.plan_id
+    }
+
+    pub fn context(&self) -> Option<&Value> {
+        self.context.as_ref()
+    }
+    pub fn context_mut(&mut self) -> &mut Option<Value> {
+        &mut self.context
+    }
+
+    pub fn organization_guid(&self) -> Option<&str> {
+        self.organization_guid.as_deref()
+    }
+    pub fn organization_guid_mut(&mut self) -> &mut Option<String> {
+        &mut self.organization_guid
+    }
+
+    pub fn space_guid(&self) -> Option<&str> {
+        self.space_guid.as_deref()
+    }
+    pub fn space_guid_mut(&mut self) -> &mut Option<String> {
+        &mut self.space_guid
+    }
+
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+}
+
+/// Body of `PATCH /v2/service_instances/{instance_id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateRequest {
+    service_id: ServiceId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plan_id: Option<PlanId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_values: Option<Value>,
+}
+
+impl UpdateRequest {
+    pub fn new(service_id: ServiceId) -> UpdateRequest {
+        UpdateRequest {
+            service_id,
+            plan_id: None,
+            context: None,
+            parameters: None,
+            previous_values: None,
+        }
+    }
+
+    pub fn service_id(&self) -> &ServiceId {
+        &self.service_id
+    }
+    pub fn service_id_mut(&mut self) -> &mut ServiceId {
+        &mut self.service_id
+    }
+
+    pub fn plan_id(&self) -> Option<&PlanId> {
+        self.plan_id.as_ref()
+    }
+    pub fn plan_id_mut(&mut self) -> &mut Option<PlanId> {
+        &mut self.plan_id
+    }
+
+    pub fn context(&self) -> Option<&Value> {
+        self.context.as_ref()
+    }
+    pub fn context_mut(&mut self) -> &mut Option<Value> {
+        &mut self.context
+    }
+
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+
+    pub fn previous_values(&self) -> Option<&Value> {
+        self.previous_values.as_ref()
+    }
+    pub fn previous_values_mut(&mut self) -> &mut Option<Value> {
+        &mut self.previous_values
+    }
+}
+
+/// Body of `PUT /v2/service_instances/{instance_id}/service_bindings/{binding_id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindRequest {
+    service_id: ServiceId,
+    plan_id: PlanId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bind_resource: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parameters: Option<Value>,
+}
+
+impl BindRequest {
+    pub fn new(service_id: ServiceId, plan_id: PlanId) -> BindRequest {
+        BindRequest {
+            service_id,
+            plan_id,
+            context: None,
+            bind_resource: None,
+            parameters: None,
+        }
+    }
+
+    pub fn service_id(&self) -> &ServiceId {
+        &self.service_id
+    }
+    pub fn service_id_mut(&mut self) -> &mut ServiceId {
+        &mut self.service_id
+    }
+
+    pub fn plan_id(&self) -> &PlanId {
+        &self.plan_id
+    }
+    pub fn plan_id_mut(&mut self) -> &mut PlanId {
+        &mut self.plan_id
+    }
+
+    pub fn context(&self) -> Option<&Value> {
+        self.context.as_ref()
+    }
+    pub fn context_mut(&mut self) -> &mut Option<Value> {
+        &mut self.context
+    }
+
+    pub fn bind_resource(&self) -> Option<&Value> {
+        self.bind_resource.as_ref()
+    }
+    pub fn bind_resource_mut(&mut self) -> &mut Option<Value> {
+        &mut self.bind_resource
+    }
+
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+}
+
+/// Response of provisioning and update
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvisionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dashboard_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    operation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Value>,
+}
+
+impl Default for ProvisionResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProvisionResponse {
+    pub fn new() -> ProvisionResponse {
+        ProvisionResponse {
+            dashboard_url: None,
+            operation: None,
+            metadata: None,
+        }
+    }
+
+    pub fn dashboard_url(&self) -> Option<&str> {
+        self.dashboard_url.as_deref()
+    }
+    pub fn dashboard_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.dashboard_url
+    }
+
+    pub fn operation(&self) -> Option<&str> {
+        self.operation.as_deref()
+    }
+    pub fn operation_mut(&mut self) -> &mut Option<String> {
+        &mut self.operation
+    }
+
+    pub fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref()
+    }
+    pub fn metadata_mut(&mut self) -> &mut Option<Value> {
+        &mut self.metadata
+    }
+}
+
+/// Response of `GET /v2/service_instances/{instance_id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstanceResponse {
+    service_id: ServiceId,
+    plan_id: PlanId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dashboard_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Value>,
+}
+
+impl InstanceResponse {
+    pub fn new(service_id: ServiceId, plan_id: PlanId) -> InstanceResponse {
+        InstanceResponse {
+            service_id,
+            plan_id,
+            dashboard_url: None,
+            parameters: None,
+            metadata: None,
+        }
+    }
+
+    pub fn service_id(&self) -> &ServiceId {
+        &self.service_id
+    }
+    pub fn service_id_mut(&mut self) -> &mut ServiceId {
+        &mut self.service_id
+    }
+
+    pub fn plan_id(&self) -> &PlanId {
+        &self.plan_id
+    }
+    pub fn plan_id_mut(&mut self) -> &mut PlanId {
+        &mut self.plan_id
+    }
+
+    pub fn dashboard_url(&self) -> Option<&str> {
+        self.dashboard_url.as_deref()
+    }
+    pub fn dashboard_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.dashboard_url
+    }
+
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+
+    pub fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref()
+    }
+    pub fn metadata_mut(&mut self) -> &mut Option<Value> {
+        &mut self.metadata
+    }
+}
+
+/// Response of deprovisioning and unbinding
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    operation: Option<String>,
+}
+
+impl Default for OperationResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OperationResponse {
+    pub fn new() -> OperationResponse {
+        OperationResponse {
+            operation: None,
+        }
+    }
+
+    pub fn operation(&self) -> Option<&str> {
+        self.operation.as_deref()
+    }
+    pub fn operation_mut(&mut self) -> &mut Option<String> {
+        &mut self.operation
+    }
+}
+
+/// Response of binding and of `GET /v2/service_instances/{instance_id}/service_bindings/{binding_id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindingResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credentials: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    syslog_drain_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route_service_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volume_mounts: Option<Vec<VolumeMount>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    endpoints: Option<Vec<Endpoint>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    operation: Option<String>,
+}
+
+impl Default for BindingResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BindingResponse {
+    pub fn new() -> BindingResponse {
+        BindingResponse {
+            metadata: None,
+            credentials: None,
+            syslog_drain_url: None,
+            route_service_url: None,
+            volume_mounts: None,
+            endpoints: None,
+            parameters: None,
+            operation: None,
+        }
+    }
+
+    pub fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref()
+    }
+    pub fn metadata_mut(&mut self) -> &mut Option<Value> {
+        &mut self.metadata
+    }
+
+    pub fn credentials(&self) -> Option<&Value> {
+        self.credentials.as_ref()
+    }
+    pub fn credentials_mut(&mut self) -> &mut Option<Value> {
+        &mut self.credentials
+    }
+
+    /// Requires service to declare `syslog_drain`
+    pub fn syslog_drain_url(&self) -> Option<&str> {
+        self.syslog_drain_url.as_deref()
+    }
+    pub fn syslog_drain_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.syslog_drain_url
+    }
+
+    /// Requires service to declare `route_forwarding`
+    pub fn route_service_url(&self) -> Option<&str> {
+        self.route_service_url.as_deref()
+    }
+    pub fn route_service_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.route_service_url
+    }
+
+    /// Requires service to declare `volume_mount`
+    pub fn volume_mounts(&self) -> Option<&Vec<VolumeMount>> {
+        self.volume_mounts.as_ref()
+    }
+    pub fn volume_mounts_mut(&mut self) -> &mut Option<Vec<VolumeMount>> {
+        &mut self.volume_mounts
+    }
+
+    pub fn endpoints(&self) -> Option<&Vec<Endpoint>> {
+        self.endpoints.as_ref()
+    }
+    pub fn endpoints_mut(&mut self) -> &mut Option<Vec<Endpoint>> {
+        &mut self.endpoints
+    }
+
+    /// Checks returned fields have been declared in service `requires`, returning first undeclared one
+    pub fn check_requires(&self, service: &Service) -> Result<(), &'static str> {
+        let returned = [
+            (self.syslog_drain_url.is_some(),  REQUIRES_SYSLOG_DRAIN),
+            (self.route_service_url.is_some(), REQUIRES_ROUTE_FORWARDING),
+            (self.volume_mounts.is_some(),     REQUIRES_VOLUME_MOUNT),
+        ];
+        match returned.iter().find(|(is_some, requirement)| *is_some && !service.has_requirement(requirement)) {
+            Some((_, requirement)) => Err(requirement),
+            None                   => Ok(()),
+        }
+    }
+
+    /// Binding parameters, only returned when fetching a binding
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+
+    pub fn operation(&self) -> Option<&str> {
+        self.operation.as_deref()
+    }
+    pub fn operation_mut(&mut self) -> &mut Option<String> {
+        &mut self.operation
+    }
+}
+
+/// Access granted to a volume mount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VolumeMountMode {
+    #[serde(rename = "r")]
+    ReadOnly,
+    #[serde(rename = "rw")]
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceType {
+    #[serde(rename = "shared")]
+    Shared,
+}
+
+/// Volume to be mounted into application containers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeMount {
+    driver: String,
+    container_dir: String,
+    mode: VolumeMountMode,
+    device_type: DeviceType,
+    device: Device,
+}
+
+impl VolumeMount {
+    pub fn new(driver: String, container_dir: String, mode: VolumeMountMode, device: Device) -> VolumeMount {
+        VolumeMount {
+            driver,
+            container_dir,
+            mode,
+            device_type: DeviceType::Shared,
+            device,
+        }
+    }
+
+    /// Name of the volume driver plugin
+    pub fn driver(&self) -> &str {
+        &self.driver
+    }
+    pub fn driver_mut(&mut self) -> &mut String {
+        &mut self.driver
+    }
+
+    /// Path in application containers where the volume is mounted
+    pub fn container_dir(&self) -> &str {
+        &self.container_dir
+    }
+    pub fn container_dir_mut(&mut self) -> &mut String {
+        &mut self.container_dir
+    }
+
+    pub fn mode(&self) -> VolumeMountMode {
+        self.mode
+    }
+    pub fn mode_mut(&mut self) -> &mut VolumeMountMode {
+        &mut self.mode
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+    pub fn device_type_mut(&mut self) -> &mut DeviceType {
+        &mut self.device_type
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+    pub fn device_mut(&mut self) -> &mut Device {
+        &mut self.device
+    }
+}
+
+/// Shared device backing a volume mount
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    volume_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mount_config: Option<Value>,
+}
+
+impl Device {
+    pub fn new(volume_id: String) -> Device {
+        Device {
+            volume_id,
+            mount_config: None,
+        }
+    }
+
+    /// Identifier of the volume, same for all bindings sharing it
+    pub fn volume_id(&self) -> &str {
+        &self.volume_id
+    }
+    pub fn volume_id_mut(&mut self) -> &mut String {
+        &mut self.volume_id
+    }
+
+    /// Driver specific configuration
+    pub fn mount_config(&self) -> Option<&Value> {
+        self.mount_config.as_ref()
+    }
+    pub fn mount_config_mut(&mut self) -> &mut Option<Value> {
+        &mut self.mount_config
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortRangeError {
+    Empty,
+    InvalidPort(String),
+    Reversed(u16, u16),
+}
+
+impl fmt::Display for PortRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortRangeError::Empty              => write!(f, "port range must not be empty"),
+            PortRangeError::InvalidPort(port)  => write!(f, "'{}' is not a port between 1 and 65535", port),
+            PortRangeError::Reversed(from, to) => write!(f, "port range {}-{} is reversed", from, to),
+        }
+    }
+}
+
+impl std::error::Error for PortRangeError {}
+
+/// Single port (`443`) or inclusive range of ports (`9000-9010`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    start: u16,
+    end: u16,
+}
+
+impl PortRange {
+    pub fn new(start: u16, end: u16) -> Result<Self, PortRangeError> {
+        if start == 0 {
+            return Err(PortRangeError::InvalidPort(start.to_string()));
+        }
+        if end == 0 {
+            return Err(PortRangeError::InvalidPort(end.to_string()));
+        }
+        if start > end {
+            return Err(PortRangeError::Reversed(start, end));
+        }
+        Ok(PortRange { start, end })
+    }
+
+    pub fn single(port: u16) -> Result<Self, PortRangeError> {
+        Self::new(port, port)
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn end(&self) -> u16 {
+        self.end
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = PortRangeError;
+
+    fn from_str(range: &str) -> Result<Self, PortRangeError> {
+        fn port(text: &str) -> Result<u16, PortRangeError> {
+            text.trim().parse().map_err(|_| PortRangeError::InvalidPort(text.trim().to_owned()))
+        }
+        let range = range.trim();
+        if range.is_empty() {
+            return Err(PortRangeError::Empty);
+        }
+        match range.split_once('-') {
+            Some((start, end)) => Self::new(port(start)?, port(end)?),
+            None               => Self::single(port(range)?),
+        }
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = PortRangeError;
+
+    fn try_from(range: String) -> Result<Self, PortRangeError> {
+        range.parse()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> String {
+        range.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointProtocol {
+    #[default]
+    Tcp,
+    Udp,
+    All,
+}
+
+/// Network endpoint of a binding, for platforms to open network policies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Endpoint {
+    host: String,
+    ports: Vec<PortRange>,
+    #[serde(default)]
+    protocol: EndpointProtocol,
+}
+
+impl Endpoint {
+    pub fn new(host: String, ports: Vec<PortRange>) -> Endpoint {
+        Endpoint {
+            host,
+            ports,
+            protocol: EndpointProtocol::default(),
+        }
+    }
+
+    /// Host name or IP address
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    pub fn host_mut(&mut self) -> &mut String {
+        &mut self.host
+    }
+
+    pub fn ports(&self) -> &Vec<PortRange> {
+        &self.ports
+    }
+    pub fn ports_mut(&mut self) -> &mut Vec<PortRange> {
+        &mut self.ports
+    }
+
+    pub fn protocol(&self) -> EndpointProtocol {
+        self.protocol
+    }
+    pub fn protocol_mut(&mut self) -> &mut EndpointProtocol {
+        &mut self.protocol
+    }
+}
+
+/// Response of `last_operation` endpoints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastOperation {
+    state: LastOperationState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl LastOperation {
+    pub fn new(state: LastOperationState) -> LastOperation {
+        LastOperation {
+            state,
+            description: None,
+        }
+    }
+
+    pub fn state(&self) -> LastOperationState {
+        self.state
+    }
+    pub fn state_mut(&mut self) -> &mut LastOperationState {
+        &mut self.state
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+    pub fn description_mut(&mut self) -> &mut Option<String> {
+        &mut self.description
+    }
+}
+
+/// Body of error responses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instance_usable: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update_repeatable: Option<bool>,
+}
+
+impl Default for ErrorResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorResponse {
+    pub fn new() -> ErrorResponse {
+        ErrorResponse {
+            error: None,
+            description: None,
+            instance_usable: None,
+            update_repeatable: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+    pub fn error_mut(&mut self) -> &mut Option<String> {
+        &mut self.error
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+    pub fn description_mut(&mut self) -> &mut Option<String> {
+        &mut self.description
+    }
+
+    pub fn instance_usable(&self) -> Option<bool> {
+        self.instance_usable
+    }
+    pub fn instance_usable_mut(&mut self) -> &mut Option<bool> {
+        &mut self.instance_usable
+    }
+
+    pub fn update_repeatable(&self) -> Option<bool> {
+        self.update_repeatable
+    }
+    pub fn update_repeatable_mut(&mut self) -> &mut Option<bool> {
+        &mut self.update_repeatable
+    }
 }
 
 #[cfg(test)]
 mod tests {
     mod catalog {
         use std::collections::HashMap;
-        use super::super::{Catalog, Service, ServicePlan};
+        use serde_json::json;
+        use super::super::{Catalog, Service, ServicePlan, ServiceId, PlanId};
 
         #[test]
         fn catalog_new() {
@@ -291,10 +1445,10 @@ mod tests {
         fn service_id() {
             let mut service = Service::new();
 
-            *service.id_mut() = String::from("0");
+            *service.id_mut() = ServiceId::new("0").unwrap();
             assert_eq!("0", service.id(), "[0]");
 
-            service.id_mut().push_str("1");
+            *service.id_mut() = "01".parse().unwrap();
             assert_eq!("01", service.id(), "[1]");
 
             assert_eq!("", service.name(), "name");
@@ -496,6 +1650,27 @@ mod tests {
             assert_eq!(0, service.plans().len(), "plans.len");
         }
 
+        #[test]
+        fn service_shareable() {
+            let mut service = Service::new();
+            assert_eq!(None, service.shareable(), "[New] shareable");
+
+            *service.shareable_mut() = Some(true);
+            assert_eq!(Some(true), service.shareable(), "[Set] shareable");
+            assert_eq!(json!({ "shareable": true }), serde_json::to_value(&service).unwrap()["metadata"], "[Set] metadata");
+
+            let mut value = serde_json::to_value(&service).unwrap();
+            value["id"] = json!("mysql");
+            value["metadata"] = json!({ "shareable": false, "displayName": "MySQL" });
+            let service: Service = serde_json::from_value(value.clone()).unwrap();
+            assert_eq!(Some(false), service.shareable(), "[Parsed] shareable");
+            assert_eq!(Some(&"MySQL".to_owned()), service.metadata().get("displayName"), "[Parsed] displayName");
+            assert!(!service.metadata().contains_key("shareable"), "[Parsed] metadata");
+
+            value["metadata"] = json!({ "shareable": "yes" });
+            assert!(serde_json::from_value::<Service>(value).is_err(), "[Invalid] shareable");
+        }
+
         #[test]
         fn service_plan_updateable() {
             let mut service = Service::new();
@@ -559,10 +1734,10 @@ mod tests {
         fn serviceplan_id() {
             let mut plan = ServicePlan::new();
 
-            *plan.id_mut() = String::from("0");
+            *plan.id_mut() = PlanId::new("0").unwrap();
             assert_eq!("0", plan.id(), "[0]");
 
-            plan.id_mut().push_str("1");
+            *plan.id_mut() = "01".parse().unwrap();
             assert_eq!("01", plan.id(), "[1]");
 
             assert_eq!("", plan.name(), "name");
@@ -686,4 +1861,230 @@ mod tests {
             assert_eq!(None, plan.bindable(), "bindable");
         }
     }
+
+    mod index {
+        use super::super::{Catalog, Service, ServicePlan, ServiceId, PlanId};
+
+        fn build_catalog(services: usize, plans: usize) -> Catalog {
+            let mut catalog = Catalog::new();
+            for s in 0..services {
+                let mut service = Service::new();
+                *service.id_mut() = ServiceId::new(format!("s{}", s)).unwrap();
+                for p in 0..plans {
+                    let mut plan = ServicePlan::new();
+                    *plan.id_mut() = PlanId::new(format!("s{}p{}", s, p)).unwrap();
+                    service.plans_mut().push(plan);
+                }
+                catalog.services_mut().push(service);
+            }
+            catalog
+        }
+
+        #[test]
+        fn catalog_find_service() {
+            let catalog = build_catalog(3, 2);
+            assert_eq!("s1", catalog.find_service(&"s1".parse().unwrap()).expect("s1").id(), "s1");
+            assert!(catalog.find_service(&"s3".parse().unwrap()).is_none(), "s3");
+        }
+
+        #[test]
+        fn catalog_find_plan() {
+            let catalog = build_catalog(3, 2);
+            let s1: ServiceId = "s1".parse().unwrap();
+            let s2: ServiceId = "s2".parse().unwrap();
+            let s1p1: PlanId = "s1p1".parse().unwrap();
+            assert_eq!("s1p1", catalog.find_plan(&s1, &s1p1).expect("s1p1").id(), "s1.s1p1");
+            assert!(catalog.find_plan(&s2, &s1p1).is_none(), "s2.s1p1");
+            assert!(catalog.find_plan(&s1, &"s1p2".parse().unwrap()).is_none(), "s1.s1p2");
+        }
+
+        #[test]
+        fn catalog_plan_with_service() {
+            let catalog = build_catalog(100, 100);
+            let (service, plan) = catalog.plan_with_service(&"s42p24".parse().unwrap()).expect("s42p24");
+            assert_eq!("s42", service.id(), "service");
+            assert_eq!("s42p24", plan.id(), "plan");
+            assert!(catalog.plan_with_service(&"s42p100".parse().unwrap()).is_none(), "s42p100");
+        }
+
+        #[test]
+        fn catalog_index_mutation() {
+            let mut catalog = build_catalog(1, 1);
+            let s0p0: PlanId = "s0p0".parse().unwrap();
+            assert!(catalog.plan_with_service(&s0p0).is_some(), "[Before] s0p0");
+
+            *catalog.services_mut()[0].plans_mut()[0].id_mut() = "s0p1".parse().unwrap();
+            assert!(catalog.plan_with_service(&s0p0).is_none(), "[After] s0p0");
+            assert!(catalog.plan_with_service(&"s0p1".parse().unwrap()).is_some(), "[After] s0p1");
+        }
+
+        #[test]
+        fn catalog_index_deserialize() {
+            let catalog: Catalog = serde_json::from_str(r#"{"services":[{"name":"","id":"mysql","description":"","tags":[],"requires":[],"bindable":false,"metadata":{},"plans":[{"id":"mysql_free","name":"","description":"","metadata":{}}]}]}"#).unwrap();
+            assert!(catalog.index.get().is_some(), "index MUST be built on deserialization");
+            assert!(catalog.clone().index.get().is_some(), "index MUST be kept by clones");
+            let (service, _) = catalog.plan_with_service(&"mysql_free".parse().unwrap()).expect("mysql_free");
+            assert_eq!("mysql", service.id(), "service");
+        }
+
+        #[test]
+        fn serviceplan_effective_bindable() {
+            let mut service = Service::new();
+            let mut plan = ServicePlan::new();
+            assert!(!plan.effective_bindable(&service), "[None, false]");
+
+            *service.bindable_mut() = true;
+            assert!(plan.effective_bindable(&service), "[None, true]");
+
+            *plan.bindable_mut() = Some(false);
+            assert!(!plan.effective_bindable(&service), "[false, true]");
+
+            *service.bindable_mut() = false;
+            *plan.bindable_mut() = Some(true);
+            assert!(plan.effective_bindable(&service), "[true, false]");
+        }
+
+        #[test]
+        fn serviceplan_effective_plan_updateable() {
+            let mut service = Service::new();
+            let mut plan = ServicePlan::new();
+            assert!(!plan.effective_plan_updateable(&service), "[None, None]");
+
+            *service.plan_updateable_mut() = Some(true);
+            assert!(plan.effective_plan_updateable(&service), "[None, true]");
+
+            *plan.plan_updateable_mut() = Some(false);
+            assert!(!plan.effective_plan_updateable(&service), "[false, true]");
+
+            *service.plan_updateable_mut() = None;
+            *plan.plan_updateable_mut() = Some(true);
+            assert!(plan.effective_plan_updateable(&service), "[true, None]");
+        }
+    }
+
+    mod ids {
+        use super::super::{IdFormat, IdError, ServiceId, PlanId, InstanceId, BindingId, Service};
+
+        #[test]
+        fn id_new() {
+            assert_eq!("mysql_free", PlanId::new("mysql_free").unwrap(), "plan");
+            assert_eq!("a.b~c-d", InstanceId::new("a.b~c-d").unwrap(), "instance");
+            assert_eq!(Err(IdError::Empty), ServiceId::new(""), "empty");
+            assert_eq!(Err(IdError::InvalidCharacter("a/b".to_owned(), '/')), BindingId::new("a/b"), "slash");
+            assert_eq!(Err(IdError::InvalidCharacter("a b".to_owned(), ' ')), BindingId::new("a b"), "space");
+            assert_eq!(Err(IdError::DotSegment("..".to_owned())), BindingId::new(".."), "dot");
+            assert!(BindingId::new("...").is_ok(), "dots");
+            assert_eq!("mysql:5.7 (legacy)", ServiceId::new("mysql:5.7 (legacy)").unwrap(), "service");
+            assert_eq!("free/small", PlanId::new("free/small").unwrap(), "plan.slash");
+            assert_eq!(Err(IdError::Empty), PlanId::new(""), "plan.empty");
+        }
+
+        #[test]
+        fn id_uuid() {
+            let uuid = "0a9f0c86-6b3e-4d6c-9a3b-1f8c7d2e5a41";
+            assert!(InstanceId::with_format(uuid, IdFormat::Uuid).is_ok(), "uuid");
+            assert_eq!(Err(IdError::NotUuid("mysql".to_owned())), InstanceId::with_format("mysql", IdFormat::Uuid), "name");
+            assert!(InstanceId::with_format("0a9f0c86-6b3e-4d6c-9a3b-1f8c7d2e5a4", IdFormat::Uuid).is_err(), "short");
+            assert!(InstanceId::with_format("0a9f0c86-6b3e-4d6c-9a3b-1f8c7d2e5a4g", IdFormat::Uuid).is_err(), "hex");
+
+            let id = InstanceId::new("mysql").unwrap();
+            assert!(id.validate(IdFormat::Any).is_ok(), "validate.any");
+            assert!(id.validate(IdFormat::Uuid).is_err(), "validate.uuid");
+
+            let generated = BindingId::generate();
+            assert!(generated.validate(IdFormat::Uuid).is_ok(), "generate: {}", generated);
+            assert_eq!(Some('4'), generated.as_str().chars().nth(14), "generate.version");
+            assert_ne!(generated, BindingId::generate(), "generate.random");
+        }
+
+        #[test]
+        fn id_serde() {
+            let id: ServiceId = serde_json::from_str("\"mysql\"").unwrap();
+            assert_eq!("mysql", id, "deserialize");
+            assert_eq!("\"mysql\"", serde_json::to_string(&id).unwrap(), "serialize");
+
+            assert!(serde_json::from_str::<ServiceId>("\"\"").is_err(), "empty");
+            assert!(serde_json::from_str::<ServiceId>("\"my sql\"").is_ok(), "service.space");
+            assert!(serde_json::from_str::<InstanceId>("\"my sql\"").is_err(), "instance.space");
+
+            let error = serde_json::from_str::<Service>(r#"{"name":"","id":"","description":"","tags":[],"requires":[],"bindable":false,"metadata":{},"plans":[]}"#)
+                                   .expect_err("empty service id MUST be rejected");
+            assert!(error.to_string().contains("identifier must not be empty"), "{}", error);
+        }
+    }
+
+    mod bindings {
+        use super::super::{BindingResponse, Device, Endpoint, EndpointProtocol, PortRange, PortRangeError, Service, VolumeMount, VolumeMountMode, REQUIRES_SYSLOG_DRAIN, REQUIRES_VOLUME_MOUNT};
+        use serde_json::json;
+
+        #[test]
+        fn volume_mount_serde() {
+            let json = json!({
+                "driver": "nfs",
+                "container_dir": "/data",
+                "mode": "rw",
+                "device_type": "shared",
+                "device": { "volume_id": "v1", "mount_config": { "uid": 1000 } },
+            });
+            let mount: VolumeMount = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!("nfs", mount.driver(), "driver");
+            assert_eq!("/data", mount.container_dir(), "container_dir");
+            assert_eq!(VolumeMountMode::ReadWrite, mount.mode(), "mode");
+            assert_eq!("v1", mount.device().volume_id(), "device.volume_id");
+            assert_eq!(Some(&json!({ "uid": 1000 })), mount.device().mount_config(), "device.mount_config");
+            assert_eq!(json, serde_json::to_value(&mount).unwrap(), "serialize");
+
+            assert!(serde_json::from_value::<VolumeMount>(json!({
+                "driver": "nfs", "container_dir": "/data", "mode": "x", "device_type": "shared", "device": { "volume_id": "v1" },
+            })).is_err(), "invalid mode");
+        }
+
+        #[test]
+        fn port_range() {
+            assert_eq!(Ok(PortRange::single(443).unwrap()), "443".parse(), "single");
+            assert_eq!(Ok(PortRange::new(9000, 9010).unwrap()), " 9000 - 9010 ".parse(), "range");
+            assert_eq!(Err(PortRangeError::Empty), "".parse::<PortRange>(), "empty");
+            assert_eq!(Err(PortRangeError::InvalidPort("0".to_owned())), "0".parse::<PortRange>(), "zero");
+            assert_eq!(Err(PortRangeError::InvalidPort("65536".to_owned())), "1-65536".parse::<PortRange>(), "overflow");
+            assert_eq!(Err(PortRangeError::InvalidPort("http".to_owned())), "http".parse::<PortRange>(), "name");
+            assert_eq!(Err(PortRangeError::Reversed(10, 1)), "10-1".parse::<PortRange>(), "reversed");
+
+            let range = PortRange::new(9000, 9010).unwrap();
+            assert!(range.contains(9005) && !range.contains(9011), "contains");
+            assert_eq!("9000-9010", range.to_string(), "display.range");
+            assert_eq!("443", PortRange::single(443).unwrap().to_string(), "display.single");
+        }
+
+        #[test]
+        fn endpoint_serde() {
+            let endpoint: Endpoint = serde_json::from_value(json!({ "host": "db.local", "ports": ["3306", "9000-9010"] })).unwrap();
+            assert_eq!("db.local", endpoint.host(), "host");
+            assert_eq!(&vec![PortRange::single(3306).unwrap(), PortRange::new(9000, 9010).unwrap()], endpoint.ports(), "ports");
+            assert_eq!(EndpointProtocol::Tcp, endpoint.protocol(), "protocol");
+            assert_eq!(json!({ "host": "db.local", "ports": ["3306", "9000-9010"], "protocol": "tcp" }), serde_json::to_value(&endpoint).unwrap(), "serialize");
+
+            let endpoint: Endpoint = serde_json::from_value(json!({ "host": "dns", "ports": ["53"], "protocol": "all" })).unwrap();
+            assert_eq!(EndpointProtocol::All, endpoint.protocol(), "protocol.all");
+
+            assert!(serde_json::from_value::<Endpoint>(json!({ "host": "db", "ports": ["0-10"] })).is_err(), "invalid port");
+            assert!(serde_json::from_value::<Endpoint>(json!({ "host": "db", "ports": ["80"], "protocol": "icmp" })).is_err(), "invalid protocol");
+        }
+
+        #[test]
+        fn binding_requires() {
+            let mut service = Service::new();
+            let mut response = BindingResponse::new();
+            assert_eq!(Ok(()), response.check_requires(&service), "[Empty]");
+
+            *response.syslog_drain_url_mut() = Some("syslog://logs:514".to_owned());
+            *response.volume_mounts_mut() = Some(vec![VolumeMount::new("nfs".to_owned(), "/data".to_owned(), VolumeMountMode::ReadOnly, Device::new("v1".to_owned()))]);
+            assert_eq!(Err(REQUIRES_SYSLOG_DRAIN), response.check_requires(&service), "[Undeclared]");
+
+            service.requires_mut().push(REQUIRES_SYSLOG_DRAIN.to_owned());
+            assert_eq!(Err(REQUIRES_VOLUME_MOUNT), response.check_requires(&service), "[Partially declared]");
+
+            service.requires_mut().push(REQUIRES_VOLUME_MOUNT.to_owned());
+            assert_eq!(Ok(()), response.check_requires(&service), "[Declared]");
+        }
+    }
 }
@@ -0,0 +1,309 @@
+use super::{model, store};
+use super::broker::{Broker, start_operation};
+use super::instances;
+use super::service::{Completion, ServiceError};
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+enum Creation {
+    Instance(store::InstanceRecord),
+    Binding(store::BindingRecord),
+}
+
+/// Creation whose outcome may not reach the platform.
+///
+/// Unless disarmed, dropping it records the resource as orphaned, so that an orphan mitigation
+/// `DELETE` from the platform reaches the backend instead of being answered `410 Gone`.
+///
+/// Handlers disarm it once the created resource is stored, before the response is written:
+/// a response lost afterwards, such as on a connection reset, leaves the resource recorded as
+/// created rather than orphaned. A mitigation `DELETE` still reaches the backend then, but
+/// `OrphanCleanup` doesn't remove the resource if the platform never sends one.
+pub(crate) struct OrphanGuard<'a> {
+    broker: &'a Broker,
+    creation: Option<Creation>,
+}
+
+impl<'a> OrphanGuard<'a> {
+    pub(crate) fn instance(broker: &'a Broker, record: store::InstanceRecord) -> Self {
+        OrphanGuard { broker, creation: Some(Creation::Instance(record)) }
+    }
+
+    pub(crate) fn binding(broker: &'a Broker, record: store::BindingRecord) -> Self {
+        OrphanGuard { broker, creation: Some(Creation::Binding(record)) }
+    }
+
+    /// Disarms on errors the platform can rely on, as backend explicitly refused the creation
+    pub(crate) fn check<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(error) = &result {
+            if error.is::<ServiceError>() {
+                self.disarm();
+            }
+        }
+        result
+    }
+
+    /// Stops tracking the creation, as its outcome is known to the broker
+    pub(crate) fn disarm(&mut self) {
+        self.creation = None;
+    }
+
+    /// Records the creation as orphaned right away, such as when the backend has created a
+    /// resource the broker can't accept, so that cleanup or mitigation removes it
+    pub(crate) fn orphan(&mut self) -> Result<()> {
+        match self.creation.take() {
+            Some(creation) => self.record(creation),
+            None           => Ok(()),
+        }
+    }
+
+    fn record(&self, creation: Creation) -> Result<()> {
+        let now = Some(SystemTime::now());
+        match creation {
+            Creation::Instance(mut record) => {
+                *record.operation_mut() = Some(store::Operation::new(store::OperationKind::Provision, model::LastOperationState::Failed));
+                *record.orphaned_at_mut() = now;
+                self.broker.store().put_instance(record)
+            },
+            Creation::Binding(mut record) => {
+                *record.operation_mut() = Some(store::Operation::new(store::OperationKind::Bind, model::LastOperationState::Failed));
+                *record.orphaned_at_mut() = now;
+                self.broker.store().put_binding(record)
+            },
+        }
+    }
+}
+
+impl Drop for OrphanGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(creation) = self.creation.take() {
+            if let Err(error) = self.record(creation) {
+                tracing::error!(error = %super::trace::ErrorChain(&error), "Can't record orphan");
+            }
+        }
+    }
+}
+
+/// Progress of an orphan removal by its backend
+enum Removal {
+    Done,
+    Started(model::OperationResponse),
+    Running,
+}
+
+/// Outcome of an orphan cleanup run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CleanupReport {
+    instances: Vec<model::InstanceId>,
+    bindings: Vec<(model::InstanceId, model::BindingId)>,
+    pending: usize,
+    failures: Vec<String>,
+}
+
+impl CleanupReport {
+    /// Deprovisioned instances
+    pub fn instances(&self) -> &[model::InstanceId] {
+        &self.instances
+    }
+
+    /// Unbound bindings
+    pub fn bindings(&self) -> &[(model::InstanceId, model::BindingId)] {
+        &self.bindings
+    }
+
+    /// Orphans whose removal is still running asynchronously
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn failures(&self) -> &[String] {
+        &self.failures
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty() && self.bindings.is_empty() && self.pending == 0 && self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Orphan cleanup: {} instance(s) deprovisioned, {} binding(s) unbound, {} pending, {} failure(s)",
+               self.instances.len(), self.bindings.len(), self.pending, self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "\n - {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes orphans from backends once the platform had time to mitigate them by itself
+pub struct OrphanCleanup {
+    broker: Broker,
+    grace_period: Duration,
+    interval: Duration,
+}
+
+impl OrphanCleanup {
+    pub fn new(broker: Broker) -> Self {
+        OrphanCleanup {
+            broker,
+            grace_period: Duration::from_secs(600),
+            interval: Duration::from_secs(60),
+        }
+    }
+
+    /// Delay left to the platform for its own orphan mitigation
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+    pub fn grace_period_mut(&mut self) -> &mut Duration {
+        &mut self.grace_period
+    }
+
+    /// Delay between background runs
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+    pub fn interval_mut(&mut self) -> &mut Duration {
+        &mut self.interval
+    }
+
+    fn is_expired(&self, orphaned_at: Option<SystemTime>) -> bool {
+        match orphaned_at {
+            Some(orphaned_at) => orphaned_at.elapsed().map(|elapsed| elapsed >= self.grace_period).unwrap_or(false),
+            None              => false,
+        }
+    }
+
+    /// Deprovisions and unbinds orphans whose grace period is over
+    pub async fn run(&self) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+        for instance in self.broker.store().list_instances()? {
+            for binding in self.broker.store().list_bindings(instance.instance_id())? {
+                if self.is_expired(binding.orphaned_at()) {
+                    let id = format!("Binding '{}' of instance '{}'", binding.binding_id(), binding.instance_id());
+                    if let Err(error) = self.clean_binding(&instance, binding, &mut report).await {
+                        report.failures.push(format!("{}: {}", id, error));
+                    }
+                }
+            }
+            if self.is_expired(instance.orphaned_at()) {
+                let id = format!("Instance '{}'", instance.instance_id());
+                if let Err(error) = self.clean_instance(instance, &mut report).await {
+                    report.failures.push(format!("{}: {}", id, error));
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn clean_instance(&self, instance: store::InstanceRecord, report: &mut CleanupReport) -> Result<()> {
+        let instance_id = instance.instance_id();
+        // Busy instances are handled by the platform, or left to next run
+        let _lock = match self.broker.lock_instance(instance_id) {
+            Ok(lock) => lock,
+            Err(_)   => return Ok(()),
+        };
+        let instance = match self.broker.store().get_instance(instance_id)? {
+            Some(instance) if instance.orphaned_at().is_some() => instance,
+            _                                                  => return Ok(()),
+        };
+
+        let removal = match instance.operation().filter(|operation| operation.in_progress()) {
+            Some(operation) => {
+                let last = self.broker.instances().last_operation(&instance, operation.operation()).await?;
+                match last.state() {
+                    model::LastOperationState::InProgress => Removal::Running,
+                    model::LastOperationState::Succeeded  => Removal::Done,
+                    model::LastOperationState::Failed     => {
+                        self.broker.store().update_instance(instance_id, &mut |record| *record.operation_mut() = None)?;
+                        return Err(ServiceError::UnprocessableEntity(last.description().unwrap_or("Deprovisioning has failed").to_owned()).into());
+                    },
+                }
+            },
+            None => match self.broker.instances().deprovision(&instance, true).await? {
+                Completion::Sync(_)         => Removal::Done,
+                Completion::Async(response) => Removal::Started(response),
+            },
+        };
+
+        match removal {
+            Removal::Done => {
+                instances::delete_instance(&self.broker, instance_id)?;
+                report.instances.push(instance_id.clone());
+            },
+            Removal::Started(response) => {
+                self.broker.store().update_instance(instance_id, &mut |record| {
+                    *record.operation_mut() = Some(start_operation(store::OperationKind::Deprovision, response.operation()));
+                })?;
+                report.pending += 1;
+            },
+            Removal::Running => report.pending += 1,
+        }
+        Ok(())
+    }
+
+    async fn clean_binding(&self, instance: &store::InstanceRecord, binding: store::BindingRecord, report: &mut CleanupReport) -> Result<()> {
+        let instance_id = binding.instance_id();
+        let binding_id = binding.binding_id();
+        let _lock = match self.broker.lock_binding(instance_id, binding_id) {
+            Ok(lock) => lock,
+            Err(_)   => return Ok(()),
+        };
+        let binding = match self.broker.store().get_binding(instance_id, binding_id)? {
+            Some(binding) if binding.orphaned_at().is_some() => binding,
+            _                                                => return Ok(()),
+        };
+
+        let removal = match binding.operation().filter(|operation| operation.in_progress()) {
+            Some(operation) => {
+                let last = self.broker.bindings().last_binding_operation(instance, &binding, operation.operation()).await?;
+                match last.state() {
+                    model::LastOperationState::InProgress => Removal::Running,
+                    model::LastOperationState::Succeeded  => Removal::Done,
+                    model::LastOperationState::Failed     => {
+                        self.broker.store().update_binding(instance_id, binding_id, &mut |record| *record.operation_mut() = None)?;
+                        return Err(ServiceError::UnprocessableEntity(last.description().unwrap_or("Unbinding has failed").to_owned()).into());
+                    },
+                }
+            },
+            None => match self.broker.bindings().unbind(instance, &binding, true).await? {
+                Completion::Sync(_)         => Removal::Done,
+                Completion::Async(response) => Removal::Started(response),
+            },
+        };
+
+        match removal {
+            Removal::Done => {
+                self.broker.store().delete_binding(instance_id, binding_id)?;
+                report.bindings.push((instance_id.clone(), binding_id.clone()));
+            },
+            Removal::Started(response) => {
+                self.broker.store().update_binding(instance_id, binding_id, &mut |record| {
+                    *record.operation_mut() = Some(start_operation(store::OperationKind::Unbind, response.operation()));
+                })?;
+                report.pending += 1;
+            },
+            Removal::Running => report.pending += 1,
+        }
+        Ok(())
+    }
+
+    /// Runs cleanup periodically on current actix system, handing non-empty reports to the callback
+    pub fn start<F: FnMut(CleanupReport) + 'static>(self, mut on_report: F) {
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                match self.run().await {
+                    Ok(report) if report.is_empty() => (),
+                    Ok(report)                      => on_report(report),
+                    Err(error)                      => tracing::error!(error = %super::trace::ErrorChain(&error), "Orphan cleanup has failed"),
+                }
+            }
+        });
+    }
+}
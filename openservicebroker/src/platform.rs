@@ -0,0 +1,332 @@
+//! Simulated platform, driving a broker the way Cloud Foundry or Kubernetes Service Catalog
+//! do, for end-to-end tests.
+//!
+//! Requests carry a platform context and an originating identity. Asynchronous operations are
+//! polled no longer than plan `maximum_polling_duration`, and creations whose outcome is
+//! unknown are followed by an orphan mitigation. Unexpected broker behaviors are recorded as
+//! violations, to be checked with `assert_invariants`.
+
+use super::model;
+use super::client::{Client, ClientError};
+use super::service::{Completion, ServiceError};
+
+use std::time::Duration;
+
+use anyhow::Result;
+use anyhow::{anyhow, bail};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlatformKind {
+    CloudFoundry,
+    Kubernetes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlatformInstance {
+    instance_id: model::InstanceId,
+    service_id: model::ServiceId,
+    plan_id: model::PlanId,
+    dashboard_url: Option<String>,
+}
+
+impl PlatformInstance {
+    pub fn instance_id(&self) -> &model::InstanceId {
+        &self.instance_id
+    }
+
+    pub fn service_id(&self) -> &model::ServiceId {
+        &self.service_id
+    }
+
+    pub fn plan_id(&self) -> &model::PlanId {
+        &self.plan_id
+    }
+
+    pub fn dashboard_url(&self) -> Option<&str> {
+        self.dashboard_url.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlatformBinding {
+    instance: PlatformInstance,
+    binding_id: model::BindingId,
+    response: model::BindingResponse,
+}
+
+impl PlatformBinding {
+    pub fn instance(&self) -> &PlatformInstance {
+        &self.instance
+    }
+
+    pub fn binding_id(&self) -> &model::BindingId {
+        &self.binding_id
+    }
+
+    /// Binding as answered by the broker, fetched once created for asynchronous bindings
+    pub fn response(&self) -> &model::BindingResponse {
+        &self.response
+    }
+}
+
+/// Whether the outcome of a creation is unknown, so that the platform must clean it up
+fn requires_mitigation(error: &anyhow::Error) -> bool {
+    if error.is::<ServiceError>() {
+        return false;
+    }
+    match error.downcast_ref::<ClientError>() {
+        Some(ClientError::UnexpectedStatus(status, _)) => status.is_server_error() || status.as_u16() == 408,
+        Some(ClientError::Timeout(_))                  => true,
+        // Transport failures and malformed responses
+        None                                           => true,
+    }
+}
+
+/// Platform calling a broker
+pub struct Platform {
+    kind: PlatformKind,
+    client: Client,
+    catalog: Option<model::Catalog>,
+    mitigations: Vec<String>,
+    violations: Vec<String>,
+}
+
+impl Platform {
+    pub fn new(kind: PlatformKind, mut client: Client) -> Self {
+        *client.originating_identity_mut() = Some(match kind {
+            PlatformKind::CloudFoundry => ("cloudfoundry".to_owned(), json!({ "user_id": "683ea748-3092-4ff4-b656-39cacc4d5360" })),
+            PlatformKind::Kubernetes   => ("kubernetes".to_owned(), json!({ "username": "duke", "uid": "c2dde242-5ce4-11e7-988c-000c2946f14f", "groups": ["admin", "dev"] })),
+        });
+        Platform {
+            kind,
+            client,
+            catalog: None,
+            mitigations: Vec::new(),
+            violations: Vec::new(),
+        }
+    }
+
+    pub fn kind(&self) -> PlatformKind {
+        self.kind
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Resources cleaned up by orphan mitigation
+    pub fn mitigations(&self) -> &[String] {
+        &self.mitigations
+    }
+
+    /// Broker behaviors breaking the specification
+    pub fn violations(&self) -> &[String] {
+        &self.violations
+    }
+
+    /// Panics if the broker has broken the specification
+    pub fn assert_invariants(&self) {
+        assert!(self.violations.is_empty(), "Broker invariants are violated:\n - {}", self.violations.join("\n - "));
+    }
+
+    fn context(&self, instance_name: &str) -> Value {
+        match self.kind {
+            PlatformKind::CloudFoundry => json!({
+                "platform": "cloudfoundry",
+                "organization_guid": "1113aa0-124e-4af2-1526-6bfacf61b111",
+                "organization_name": "system",
+                "space_guid": "aaaa1234-da91-4f12-8ffa-b51d0336aaaa",
+                "space_name": "development",
+                "instance_name": instance_name,
+            }),
+            PlatformKind::Kubernetes => json!({
+                "platform": "kubernetes",
+                "namespace": "development",
+                "clusterid": "8263feba-9b8a-23ae-99ed-abcd1234feda",
+                "instance_name": instance_name,
+            }),
+        }
+    }
+
+    async fn find_plan(&mut self, service_id: &model::ServiceId, plan_id: &model::PlanId) -> Result<(model::Service, model::ServicePlan)> {
+        if self.catalog.is_none() {
+            self.catalog = Some(self.client.catalog().await?);
+        }
+        let catalog = self.catalog.as_ref().expect("catalog is loaded");
+        let service = catalog.find_service(service_id).ok_or_else(|| anyhow!("Service '{}' is not in catalog", service_id))?;
+        let plan = catalog.find_plan(service_id, plan_id).ok_or_else(|| anyhow!("Plan '{}' is not in catalog", plan_id))?;
+        Ok((service.clone(), plan.clone()))
+    }
+
+    /// Client polling no longer than plan allows
+    fn polling_client(&self, plan: &model::ServicePlan) -> Client {
+        let mut client = self.client.clone();
+        if let Some(maximum) = plan.maximum_polling_duration() {
+            *client.poll_timeout_mut() = Duration::from_secs(maximum);
+        }
+        client
+    }
+
+    pub async fn provision(&mut self, service_id: &model::ServiceId, plan_id: &model::PlanId, parameters: Option<Value>) -> Result<PlatformInstance> {
+        let (service, plan) = self.find_plan(service_id, plan_id).await?;
+        let instance_id = model::InstanceId::generate();
+        let mut request = model::ProvisionRequest::new(service_id.clone(), plan_id.clone());
+        *request.context_mut() = Some(self.context(&format!("instance-{}", instance_id)));
+        *request.parameters_mut() = parameters;
+        if self.kind == PlatformKind::CloudFoundry {
+            *request.organization_guid_mut() = request.context().and_then(|context| context["organization_guid"].as_str()).map(str::to_owned);
+            *request.space_guid_mut() = request.context().and_then(|context| context["space_guid"].as_str()).map(str::to_owned);
+        }
+        let instance = PlatformInstance {
+            instance_id: instance_id.clone(),
+            service_id: service_id.clone(),
+            plan_id: plan_id.clone(),
+            dashboard_url: None,
+        };
+
+        let client = self.polling_client(&plan);
+        let result = async {
+            let response = match client.provision(&instance_id, &request, true).await? {
+                Completion::Sync(response)  => response,
+                Completion::Async(response) => {
+                    let last = client.wait_instance(&instance_id, response.operation()).await?;
+                    if last.state() != model::LastOperationState::Succeeded {
+                        bail!("Provisioning has failed: {}", last.description().unwrap_or("no description"));
+                    }
+                    response
+                },
+            };
+            Ok(response)
+        }.await;
+        let response = match result {
+            Ok(response) => response,
+            Err(error)   => {
+                if requires_mitigation(&error) {
+                    self.mitigate_instance(&instance, &plan).await;
+                }
+                return Err(error);
+            },
+        };
+
+        let instance = PlatformInstance { dashboard_url: response.dashboard_url().map(str::to_owned), ..instance };
+        if service.instances_retrievable() == Some(true) {
+            match self.client.fetch_instance(&instance_id).await {
+                Ok(fetched) if fetched.service_id() == service_id && fetched.plan_id() == plan_id => (),
+                Ok(fetched) => self.violations.push(format!("Instance '{}' is fetched with service '{}' and plan '{}'", instance_id, fetched.service_id(), fetched.plan_id())),
+                Err(error)  => self.violations.push(format!("Instance '{}' can't be fetched: {:#}", instance_id, error)),
+            }
+        }
+        Ok(instance)
+    }
+
+    pub async fn deprovision(&mut self, instance: &PlatformInstance) -> Result<()> {
+        let (_, plan) = self.find_plan(instance.service_id(), instance.plan_id()).await?;
+        self.remove_instance(instance, &plan).await?;
+        match self.client.deprovision(instance.instance_id(), instance.service_id(), instance.plan_id(), true).await {
+            Err(error) if matches!(error.downcast_ref(), Some(ServiceError::Gone)) => (),
+            _ => self.violations.push(format!("Deprovisioned instance '{}' isn't answered 410 Gone", instance.instance_id())),
+        }
+        Ok(())
+    }
+
+    async fn remove_instance(&self, instance: &PlatformInstance, plan: &model::ServicePlan) -> Result<()> {
+        let client = self.polling_client(plan);
+        if let Completion::Async(response) = client.deprovision(instance.instance_id(), instance.service_id(), instance.plan_id(), true).await? {
+            let last = client.wait_deprovision(instance.instance_id(), response.operation()).await?;
+            if last.state() != model::LastOperationState::Succeeded {
+                bail!("Deprovisioning has failed: {}", last.description().unwrap_or("no description"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn mitigate_instance(&mut self, instance: &PlatformInstance, plan: &model::ServicePlan) {
+        match self.remove_instance(instance, plan).await {
+            Ok(())                                                                      => (),
+            Err(error) if matches!(error.downcast_ref(), Some(ServiceError::Gone)) => (),
+            Err(error) => self.violations.push(format!("Orphan mitigation of instance '{}' has failed: {:#}", instance.instance_id(), error)),
+        }
+        self.mitigations.push(format!("instance '{}'", instance.instance_id()));
+    }
+
+    pub async fn bind(&mut self, instance: &PlatformInstance, parameters: Option<Value>) -> Result<PlatformBinding> {
+        let (service, plan) = self.find_plan(instance.service_id(), instance.plan_id()).await?;
+        if !plan.effective_bindable(&service) {
+            bail!("Plan '{}' is not bindable", plan.id());
+        }
+        let binding_id = model::BindingId::generate();
+        let mut request = model::BindRequest::new(instance.service_id().clone(), instance.plan_id().clone());
+        *request.context_mut() = Some(self.context(&format!("instance-{}", instance.instance_id())));
+        *request.parameters_mut() = parameters;
+        if self.kind == PlatformKind::CloudFoundry {
+            *request.bind_resource_mut() = Some(json!({ "app_guid": model::BindingId::generate() }));
+        }
+        let binding = PlatformBinding {
+            instance: instance.clone(),
+            binding_id: binding_id.clone(),
+            response: model::BindingResponse::new(),
+        };
+
+        let client = self.polling_client(&plan);
+        let result = async {
+            match client.bind(instance.instance_id(), &binding_id, &request, true).await? {
+                Completion::Sync(response)  => Ok(response),
+                Completion::Async(response) => {
+                    let last = client.wait_binding(instance.instance_id(), &binding_id, response.operation()).await?;
+                    if last.state() != model::LastOperationState::Succeeded {
+                        bail!("Binding has failed: {}", last.description().unwrap_or("no description"));
+                    }
+                    client.fetch_binding(instance.instance_id(), &binding_id).await
+                },
+            }
+        }.await;
+        let response = match result {
+            Ok(response) => response,
+            Err(error)   => {
+                if requires_mitigation(&error) {
+                    self.mitigate_binding(&binding, &plan).await;
+                }
+                return Err(error);
+            },
+        };
+
+        if response.credentials().is_none() && response.syslog_drain_url().is_none() && response.route_service_url().is_none()
+            && response.volume_mounts().is_none() && response.endpoints().is_none() {
+            self.violations.push(format!("Binding '{}' has neither credentials, syslog drain, route service, volume mounts nor endpoints", binding_id));
+        }
+        Ok(PlatformBinding { response, ..binding })
+    }
+
+    pub async fn unbind(&mut self, binding: &PlatformBinding) -> Result<()> {
+        let (_, plan) = self.find_plan(binding.instance.service_id(), binding.instance.plan_id()).await?;
+        self.remove_binding(binding, &plan).await?;
+        let instance = binding.instance();
+        match self.client.unbind(instance.instance_id(), binding.binding_id(), instance.service_id(), instance.plan_id(), true).await {
+            Err(error) if matches!(error.downcast_ref(), Some(ServiceError::Gone)) => (),
+            _ => self.violations.push(format!("Unbound binding '{}' isn't answered 410 Gone", binding.binding_id())),
+        }
+        Ok(())
+    }
+
+    async fn remove_binding(&self, binding: &PlatformBinding, plan: &model::ServicePlan) -> Result<()> {
+        let client = self.polling_client(plan);
+        let instance = binding.instance();
+        if let Completion::Async(response) = client.unbind(instance.instance_id(), binding.binding_id(), instance.service_id(), instance.plan_id(), true).await? {
+            let last = client.wait_unbind(instance.instance_id(), binding.binding_id(), response.operation()).await?;
+            if last.state() != model::LastOperationState::Succeeded {
+                bail!("Unbinding has failed: {}", last.description().unwrap_or("no description"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn mitigate_binding(&mut self, binding: &PlatformBinding, plan: &model::ServicePlan) {
+        match self.remove_binding(binding, plan).await {
+            Ok(())                                                                      => (),
+            Err(error) if matches!(error.downcast_ref(), Some(ServiceError::Gone)) => (),
+            Err(error) => self.violations.push(format!("Orphan mitigation of binding '{}' has failed: {:#}", binding.binding_id(), error)),
+        }
+        self.mitigations.push(format!("binding '{}'", binding.binding_id()));
+    }
+}
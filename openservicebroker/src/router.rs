@@ -0,0 +1,181 @@
+use super::{model, store};
+use super::service::{Completion, ServiceBroker, ServiceError};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use anyhow::anyhow;
+use async_trait::async_trait;
+
+/// Backend dispatching operations to other backends, by service or plan.
+///
+/// Instances are routed by their plan, so bindings and later operations reach the backend
+/// which has provisioned them. A plan override takes precedence over its service backend.
+#[derive(Clone, Default)]
+pub struct ServiceRouter {
+    services: HashMap<model::ServiceId, Arc<dyn ServiceBroker>>,
+    plans: HashMap<model::PlanId, Arc<dyn ServiceBroker>>,
+}
+
+impl ServiceRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn services(&self) -> &HashMap<model::ServiceId, Arc<dyn ServiceBroker>> {
+        &self.services
+    }
+    pub fn services_mut(&mut self) -> &mut HashMap<model::ServiceId, Arc<dyn ServiceBroker>> {
+        &mut self.services
+    }
+
+    pub fn plans(&self) -> &HashMap<model::PlanId, Arc<dyn ServiceBroker>> {
+        &self.plans
+    }
+    pub fn plans_mut(&mut self) -> &mut HashMap<model::PlanId, Arc<dyn ServiceBroker>> {
+        &mut self.plans
+    }
+
+    /// Registers the backend of all plans of a service
+    pub fn add_service<B: ServiceBroker + 'static>(&mut self, service_id: model::ServiceId, backend: B) -> &mut Self {
+        self.services.insert(service_id, Arc::new(backend));
+        self
+    }
+
+    /// Registers the backend of a single plan, overriding its service one
+    pub fn add_plan<B: ServiceBroker + 'static>(&mut self, plan_id: model::PlanId, backend: B) -> &mut Self {
+        self.plans.insert(plan_id, Arc::new(backend));
+        self
+    }
+
+    /// Checks every catalog plan has a backend, and every backend is registered for a catalog entry
+    pub fn check(&self, catalog: &model::Catalog) -> Result<()> {
+        let mut errors = Vec::new();
+        for service in catalog.services() {
+            if self.services.contains_key(service.id()) {
+                continue;
+            }
+            for plan in service.plans().iter().filter(|plan| !self.plans.contains_key(plan.id())) {
+                errors.push(format!("no backend for plan '{}' of service '{}'", plan.id(), service.id()));
+            }
+        }
+        for service_id in self.services.keys().filter(|service_id| catalog.find_service(service_id).is_none()) {
+            errors.push(format!("backend registered for unknown service '{}'", service_id));
+        }
+        for plan_id in self.plans.keys().filter(|plan_id| catalog.plan_with_service(plan_id).is_none()) {
+            errors.push(format!("backend registered for unknown plan '{}'", plan_id));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            errors.sort();
+            Err(anyhow!("Invalid service routing: {}", errors.join(", ")))
+        }
+    }
+
+    fn backend(&self, service_id: &model::ServiceId, plan_id: &model::PlanId) -> Result<&Arc<dyn ServiceBroker>> {
+        self.plans.get(plan_id)
+            .or_else(|| self.services.get(service_id))
+            .ok_or_else(|| anyhow!("No backend registered for plan '{}' of service '{}'", plan_id, service_id))
+    }
+
+    fn route(&self, service_id: &model::ServiceId, plan_id: &model::PlanId) -> Result<&dyn ServiceBroker> {
+        Ok(self.backend(service_id, plan_id)?.as_ref())
+    }
+
+    fn route_instance(&self, instance: &store::InstanceRecord) -> Result<&dyn ServiceBroker> {
+        self.route(instance.service_id(), instance.plan_id())
+    }
+}
+
+#[async_trait(?Send)]
+impl ServiceBroker for ServiceRouter {
+    async fn provision(&self, instance_id: &model::InstanceId, request: &model::ProvisionRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        self.route(request.service_id(), request.plan_id())?.provision(instance_id, request, accepts_incomplete).await
+    }
+
+    async fn update(&self, instance: &store::InstanceRecord, request: &model::UpdateRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        // Instances can't move between backends, as the new one has never provisioned them
+        let backend = self.backend(instance.service_id(), instance.plan_id())?;
+        if let Some(plan_id) = request.plan_id() {
+            if !Arc::ptr_eq(backend, self.backend(instance.service_id(), plan_id)?) {
+                return Err(ServiceError::UnprocessableEntity(format!("Plan '{}' is served by another backend than plan '{}'", plan_id, instance.plan_id())).into());
+            }
+        }
+        backend.update(instance, request, accepts_incomplete).await
+    }
+
+    async fn deprovision(&self, instance: &store::InstanceRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        self.route_instance(instance)?.deprovision(instance, accepts_incomplete).await
+    }
+
+    async fn last_operation(&self, instance: &store::InstanceRecord, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.route_instance(instance)?.last_operation(instance, operation).await
+    }
+
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        self.route_instance(instance)?.fetch_instance(instance).await
+    }
+
+    async fn bind(&self, instance: &store::InstanceRecord, binding_id: &model::BindingId, request: &model::BindRequest, accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        self.route_instance(instance)?.bind(instance, binding_id, request, accepts_incomplete).await
+    }
+
+    async fn unbind(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        self.route_instance(instance)?.unbind(instance, binding, accepts_incomplete).await
+    }
+
+    async fn last_binding_operation(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.route_instance(instance)?.last_binding_operation(instance, binding, operation).await
+    }
+
+    async fn fetch_binding(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        self.route_instance(instance)?.fetch_binding(instance, binding).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{model, ServiceRouter, ServiceBroker};
+
+    struct Unsupported;
+
+    impl ServiceBroker for Unsupported {}
+
+    fn catalog() -> model::Catalog {
+        serde_json::from_str(&std::fs::read_to_string("tests/default_catalog.json").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn check_services() {
+        let mut router = ServiceRouter::new();
+        router.add_service("mysql".parse().unwrap(), Unsupported)
+              .add_service("pgsql".parse().unwrap(), Unsupported);
+        assert!(router.check(&catalog()).is_ok());
+    }
+
+    #[test]
+    fn check_plans() {
+        let mut router = ServiceRouter::new();
+        router.add_service("mysql".parse().unwrap(), Unsupported)
+              .add_plan("pgsql_free".parse().unwrap(), Unsupported);
+        let error = router.check(&catalog()).expect_err("pgsql_small MUST be missing");
+        assert_eq!("Invalid service routing: no backend for plan 'pgsql_small' of service 'pgsql'", error.to_string());
+
+        router.add_plan("pgsql_small".parse().unwrap(), Unsupported);
+        assert!(router.check(&catalog()).is_ok());
+    }
+
+    #[test]
+    fn check_unknown() {
+        let mut router = ServiceRouter::new();
+        router.add_service("mysql".parse().unwrap(), Unsupported)
+              .add_service("pgsql".parse().unwrap(), Unsupported)
+              .add_service("redis".parse().unwrap(), Unsupported)
+              .add_plan("mysql_large".parse().unwrap(), Unsupported);
+        let error = router.check(&catalog()).expect_err("unknown entries MUST be rejected");
+        assert_eq!("Invalid service routing: backend registered for unknown plan 'mysql_large', backend registered for unknown service 'redis'", error.to_string());
+    }
+}
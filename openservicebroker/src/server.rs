@@ -0,0 +1,735 @@
+//! Configuration of a standalone broker server, read from a JSON file and overridden by
+//! command line flags.
+
+use super::{broker, model, service, store};
+use super::backends::{directory::DirectoryBackend, exec::ExecBackend};
+use super::service::CatalogProvider;
+
+use std::fs;
+use std::future::Future;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use actix_web::{http, HttpMessage, HttpResponse};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use anyhow::Result;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Accepted log levels, from the least to the most verbose
+pub const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum CatalogSource {
+    /// JSON file, read once at startup unless `reload` is set
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        reload: bool,
+    },
+    /// Catalog embedded in configuration
+    Inline {
+        catalog: Value,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BackendConfig {
+    Directory {
+        root: PathBuf,
+    },
+    Sqlite {
+        root: PathBuf,
+    },
+    Exec {
+        program: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+        /// Seconds after which runs are killed
+        #[serde(default)]
+        timeout: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StoreConfig {
+    Memory,
+    File {
+        root: PathBuf,
+    },
+    Sqlite {
+        path: PathBuf,
+    },
+}
+
+/// Log output, human readable or one JSON object per line
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Basic authentication credentials required from platforms
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(username: String, password: String) -> Self {
+        Credentials { username, password }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    fn authorization(&self) -> String {
+        format!("Basic {}", base64::encode(&format!("{}:{}", self.username, self.password)))
+    }
+}
+
+/// Identity of a platform authenticated by its client certificate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrokerIdentity {
+    subject: String,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    services: Option<Vec<model::ServiceId>>,
+}
+
+impl BrokerIdentity {
+    pub fn new(subject: String, name: String) -> Self {
+        BrokerIdentity {
+            subject,
+            name,
+            services: None,
+        }
+    }
+
+    /// Certificate subject, in RFC 4514 form (e.g. `CN=cloudfoundry,O=Example`)
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Services visible from catalog and usable, none meaning all of them
+    pub fn services(&self) -> Option<&Vec<model::ServiceId>> {
+        self.services.as_ref()
+    }
+    pub fn services_mut(&mut self) -> &mut Option<Vec<model::ServiceId>> {
+        &mut self.services
+    }
+
+    pub fn filter_catalog(&self, catalog: &mut model::Catalog) {
+        if let Some(services) = &self.services {
+            catalog.services_mut().retain(|service| services.contains(service.id()));
+        }
+    }
+
+    /// Fails with `BadRequest` if the service isn't available to this identity
+    pub fn check_service(&self, service_id: &model::ServiceId) -> Result<(), service::ServiceError> {
+        match &self.services {
+            Some(services) if !services.contains(service_id)
+                => Err(service::ServiceError::BadRequest(format!("Service '{}' is not available to '{}'", service_id, self.name))),
+            _   => Ok(()),
+        }
+    }
+}
+
+/// Checks that the identity of a request, if any, may use the service
+pub(crate) fn check_identity(req: &actix_web::HttpRequest, service_id: &model::ServiceId) -> Result<()> {
+    match req.extensions().get::<BrokerIdentity>() {
+        Some(identity) => Ok(identity.check_service(service_id)?),
+        None           => Ok(()),
+    }
+}
+
+/// HTTPS serving, optionally authenticating platforms by client certificate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    certificate: PathBuf,
+    private_key: PathBuf,
+    #[serde(default)]
+    client_ca: Option<PathBuf>,
+    #[serde(default)]
+    require_client_certificate: bool,
+    #[serde(default)]
+    identities: Vec<BrokerIdentity>,
+}
+
+impl TlsConfig {
+    pub fn new(certificate: PathBuf, private_key: PathBuf) -> Self {
+        TlsConfig {
+            certificate,
+            private_key,
+            client_ca: None,
+            require_client_certificate: false,
+            identities: Vec::new(),
+        }
+    }
+
+    /// PEM certificate chain, reloaded on change
+    pub fn certificate(&self) -> &Path {
+        &self.certificate
+    }
+    pub fn certificate_mut(&mut self) -> &mut PathBuf {
+        &mut self.certificate
+    }
+
+    /// PEM private key (PKCS#8 or RSA), reloaded on change
+    pub fn private_key(&self) -> &Path {
+        &self.private_key
+    }
+    pub fn private_key_mut(&mut self) -> &mut PathBuf {
+        &mut self.private_key
+    }
+
+    /// PEM authorities of client certificates, enabling mutual TLS
+    pub fn client_ca(&self) -> Option<&Path> {
+        self.client_ca.as_deref()
+    }
+    pub fn client_ca_mut(&mut self) -> &mut Option<PathBuf> {
+        &mut self.client_ca
+    }
+
+    pub fn require_client_certificate(&self) -> bool {
+        self.require_client_certificate
+    }
+    pub fn require_client_certificate_mut(&mut self) -> &mut bool {
+        &mut self.require_client_certificate
+    }
+
+    pub fn identities(&self) -> &Vec<BrokerIdentity> {
+        &self.identities
+    }
+    pub fn identities_mut(&mut self) -> &mut Vec<BrokerIdentity> {
+        &mut self.identities
+    }
+}
+
+/// Response of a request going through `ServerConfig::authorize`
+pub type ResponseFuture = Pin<Box<dyn Future<Output = Result<ServiceResponse, actix_web::Error>>>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    bind: Vec<String>,
+    catalog: CatalogSource,
+    credentials: Option<Credentials>,
+    min_api_version: Option<String>,
+    log_level: String,
+    log_format: LogFormat,
+    backend: BackendConfig,
+    store: StoreConfig,
+    tls: Option<TlsConfig>,
+    shutdown_timeout: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: vec!["127.0.0.1:8080".to_owned()],
+            catalog: CatalogSource::File { path: "tests/default_catalog.json".into(), reload: false },
+            credentials: None,
+            min_api_version: None,
+            log_level: "info".to_owned(),
+            log_format: LogFormat::Text,
+            backend: BackendConfig::Directory { root: std::env::temp_dir().join("dummy-servicebroker") },
+            store: StoreConfig::Memory,
+            tls: None,
+            shutdown_timeout: 30,
+        }
+    }
+}
+
+/// Parses a `major.minor` API version
+fn parse_api_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().splitn(2, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Fails if a directory can't be written, once created with its missing parents
+fn check_directory(path: &Path, name: &str) -> Result<()> {
+    let existing = path.ancestors()
+                       .map(|ancestor| if ancestor.as_os_str().is_empty() { Path::new(".") } else { ancestor })
+                       .find(|ancestor| ancestor.exists())
+                       .unwrap_or_else(|| Path::new("."));
+    if !existing.is_dir() {
+        bail!("{} '{}' isn't usable, '{}' is not a directory", name, path.display(), existing.display());
+    }
+    // Probed with a file, as permissions alone don't tell about the current user
+    let probe = existing.join(format!(".check-{}", super::backends::generate_secret(8)));
+    fs::OpenOptions::new().write(true).create_new(true).open(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .with_context(|| format!("{} '{}' isn't usable, '{}' can't be written", name, path.display(), existing.display()))
+}
+
+/// Fails if a file can't be written, or created in an existing directory
+fn check_file(path: &Path, name: &str) -> Result<()> {
+    if path.exists() {
+        fs::OpenOptions::new().read(true).write(true).open(path)
+            .with_context(|| format!("{} '{}' can't be written", name, path.display()))?;
+        return Ok(());
+    }
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) if !parent.is_dir() => bail!("{} '{}' isn't usable, '{}' is not a directory", name, path.display(), parent.display()),
+        Some(parent)                     => check_directory(parent, name),
+        None                             => check_directory(Path::new("."), name),
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        #[cfg(unix)]
+        Ok(metadata) => metadata.is_file() && std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o111 != 0,
+        #[cfg(not(unix))]
+        Ok(metadata) => metadata.is_file(),
+        Err(_)       => false,
+    }
+}
+
+/// Fails if a program can't be run, bare names being looked up in `PATH`
+fn check_program(program: &Path) -> Result<()> {
+    let bare = program.parent().is_none_or(|parent| parent.as_os_str().is_empty());
+    let found = if bare {
+        std::env::var_os("PATH").is_some_and(|paths| std::env::split_paths(&paths).any(|dir| is_executable(&dir.join(program))))
+    } else {
+        is_executable(program)
+    };
+    if !found {
+        bail!("Program '{}' doesn't exist or isn't executable", program.display());
+    }
+    Ok(())
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).with_context(|| format!("Can't read configuration file '{}'", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid configuration file '{}'", path.display()))
+    }
+
+    pub fn bind(&self) -> &Vec<String> {
+        &self.bind
+    }
+    pub fn bind_mut(&mut self) -> &mut Vec<String> {
+        &mut self.bind
+    }
+
+    pub fn catalog(&self) -> &CatalogSource {
+        &self.catalog
+    }
+    pub fn catalog_mut(&mut self) -> &mut CatalogSource {
+        &mut self.catalog
+    }
+
+    pub fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+    pub fn credentials_mut(&mut self) -> &mut Option<Credentials> {
+        &mut self.credentials
+    }
+
+    /// Oldest `X-Broker-API-Version` accepted, none meaning the header isn't checked
+    pub fn min_api_version(&self) -> Option<&str> {
+        self.min_api_version.as_deref()
+    }
+    pub fn min_api_version_mut(&mut self) -> &mut Option<String> {
+        &mut self.min_api_version
+    }
+
+    pub fn log_level(&self) -> &str {
+        &self.log_level
+    }
+    pub fn log_level_mut(&mut self) -> &mut String {
+        &mut self.log_level
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+    pub fn log_format_mut(&mut self) -> &mut LogFormat {
+        &mut self.log_format
+    }
+
+    pub fn backend(&self) -> &BackendConfig {
+        &self.backend
+    }
+    pub fn backend_mut(&mut self) -> &mut BackendConfig {
+        &mut self.backend
+    }
+
+    pub fn store(&self) -> &StoreConfig {
+        &self.store
+    }
+    pub fn store_mut(&mut self) -> &mut StoreConfig {
+        &mut self.store
+    }
+
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+    pub fn tls_mut(&mut self) -> &mut Option<TlsConfig> {
+        &mut self.tls
+    }
+
+    /// Seconds waited for running mutating requests on shutdown
+    pub fn shutdown_timeout(&self) -> u64 {
+        self.shutdown_timeout
+    }
+    pub fn shutdown_timeout_mut(&mut self) -> &mut u64 {
+        &mut self.shutdown_timeout
+    }
+
+    /// Validates configuration without opening backend nor store, loading the catalog and
+    /// checking their paths can be used
+    pub fn check(&self) -> Result<model::Catalog> {
+        if self.bind.is_empty() {
+            bail!("No bind address");
+        }
+        for address in &self.bind {
+            address.to_socket_addrs().with_context(|| format!("Invalid bind address '{}'", address))?;
+        }
+        if let Some(version) = &self.min_api_version {
+            parse_api_version(version).ok_or_else(|| anyhow!("Invalid minimum API version '{}'", version))?;
+        }
+        if !LOG_LEVELS.contains(&self.log_level.as_str()) {
+            bail!("Invalid log level '{}', expecting one of {}", self.log_level, LOG_LEVELS.join(", "));
+        }
+        if let Some(tls) = &self.tls {
+            if tls.require_client_certificate && tls.client_ca.is_none() {
+                bail!("Requiring client certificates needs a client CA");
+            }
+            #[cfg(feature = "tls")]
+            super::tls::server_config(tls)?;
+            #[cfg(not(feature = "tls"))]
+            bail!("TLS requires 'tls' feature");
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            if let BackendConfig::Sqlite { .. } = self.backend {
+                bail!("SQLite backend requires 'sqlite' feature");
+            }
+            if let StoreConfig::Sqlite { .. } = self.store {
+                bail!("SQLite store requires 'sqlite' feature");
+            }
+        }
+        match &self.backend {
+            BackendConfig::Directory { root } => check_directory(root, "Backend root")?,
+            BackendConfig::Sqlite { root }    => check_directory(root, "Backend root")?,
+            BackendConfig::Exec { program, .. } => check_program(program)?,
+        }
+        match &self.store {
+            StoreConfig::Memory          => (),
+            StoreConfig::File { root }   => check_directory(root, "Store root")?,
+            StoreConfig::Sqlite { path } => check_file(path, "Store database")?,
+        }
+        let catalog = self.load_catalog()?.get_catalog()?.into_owned();
+        #[cfg(feature = "sqlite")]
+        {
+            if let BackendConfig::Sqlite { .. } = self.backend {
+                super::backends::sqlite::SqliteBackend::read_quotas(&catalog)?;
+            }
+        }
+        Ok(catalog)
+    }
+
+    fn load_catalog(&self) -> Result<Box<dyn CatalogProvider>> {
+        Ok(match &self.catalog {
+            CatalogSource::File { path, reload: true } => {
+                let path = path.to_str().ok_or_else(|| anyhow!("Invalid catalog path '{}'", path.display()))?;
+                let provider = service::JsonFileCatalogProvider::new(path);
+                provider.get_catalog().with_context(|| format!("Error on loading catalog '{}'", path))?;
+                Box::new(provider)
+            },
+            CatalogSource::File { path, reload: false } => {
+                let path = path.to_str().ok_or_else(|| anyhow!("Invalid catalog path '{}'", path.display()))?;
+                Box::new(service::providers::catalog::file_json(path)
+                                                        .to_single()
+                                                        .with_context(|| format!("Error on loading catalog '{}'", path))?)
+            },
+            CatalogSource::Inline { catalog } => {
+                let catalog = serde_json::from_value(catalog.clone()).with_context(|| "Invalid inline catalog")?;
+                Box::new(service::SingleCatalogProvider::new(catalog))
+            },
+        })
+    }
+
+    /// Provider serving a checked catalog, or reloading its file on each request if configured
+    pub fn catalog_provider(&self, catalog: &model::Catalog) -> Box<dyn CatalogProvider> {
+        match &self.catalog {
+            CatalogSource::File { path, reload: true } => Box::new(service::JsonFileCatalogProvider::new(&path.to_string_lossy())),
+            _                                          => Box::new(service::SingleCatalogProvider::new(catalog.clone())),
+        }
+    }
+
+    /// Opens configured backend and store
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    pub fn open_broker(&self, catalog: &model::Catalog) -> Result<broker::Broker> {
+        let backend: Arc<dyn service::ServiceBroker> = match &self.backend {
+            BackendConfig::Directory { root } => Arc::new(DirectoryBackend::open(root.clone())?),
+            #[cfg(feature = "sqlite")]
+            BackendConfig::Sqlite { root }    => Arc::new(super::backends::sqlite::SqliteBackend::open(root.clone(), catalog)?),
+            #[cfg(not(feature = "sqlite"))]
+            BackendConfig::Sqlite { .. }      => bail!("SQLite backend requires 'sqlite' feature"),
+            BackendConfig::Exec { program, args, timeout } => {
+                let mut backend = ExecBackend::new(program.clone());
+                *backend.args_mut() = args.clone();
+                if let Some(timeout) = timeout {
+                    *backend.timeout_mut() = std::time::Duration::from_secs(*timeout);
+                }
+                Arc::new(backend)
+            },
+        };
+        let store: Arc<dyn store::Store> = match &self.store {
+            StoreConfig::Memory         => Arc::new(store::providers::store::memory()),
+            StoreConfig::File { root }  => Arc::new(store::providers::store::file_json(root)?),
+            #[cfg(feature = "sqlite")]
+            StoreConfig::Sqlite { path } => Arc::new(store::providers::store::sqlite(path)?),
+            #[cfg(not(feature = "sqlite"))]
+            StoreConfig::Sqlite { .. }   => bail!("SQLite store requires 'sqlite' feature"),
+        };
+        let mut broker = broker::Broker::from_service_broker(backend);
+        *broker.store_mut() = store;
+        Ok(broker)
+    }
+
+    /// Identity mapped from a client certificate subject
+    pub fn identify(&self, subject: &str) -> Option<&BrokerIdentity> {
+        self.tls.as_ref()?.identities.iter().find(|identity| identity.subject == subject)
+    }
+
+    /// Rejects requests lacking configured credentials or API version, platforms identified by
+    /// their client certificate needing no credentials
+    pub fn check_request(&self, headers: &http::HeaderMap, identity: Option<&BrokerIdentity>) -> Option<HttpResponse> {
+        if let (Some(credentials), None) = (&self.credentials, identity) {
+            let authorization = headers.get(http::header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+            if authorization != Some(credentials.authorization().as_str()) {
+                return Some(HttpResponse::Unauthorized()
+                                         .header(http::header::WWW_AUTHENTICATE, "Basic realm=\"Open Service Broker\"")
+                                         .finish());
+            }
+        }
+        if let Some(minimum) = self.min_api_version.as_deref().and_then(parse_api_version) {
+            let version = headers.get(model::API_VERSION_HEADER).and_then(|value| value.to_str().ok());
+            if version.and_then(parse_api_version).is_none_or(|version| version < minimum) {
+                let mut response = model::ErrorResponse::new();
+                *response.description_mut() = Some(format!("{} header MUST be at least {}.{}, found {}",
+                                                           model::API_VERSION_HEADER, minimum.0, minimum.1, version.unwrap_or("none")));
+                return Some(HttpResponse::PreconditionFailed().json(response));
+            }
+        }
+        None
+    }
+
+    /// Middleware checking requests, to be used with `App::wrap_fn`. Identities of platforms
+    /// are made available from request extensions as `BrokerIdentity`.
+    pub fn authorize<S>(&self, req: ServiceRequest, srv: &mut S) -> ResponseFuture
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+          S::Future: 'static {
+        #[cfg(feature = "tls")]
+        let identity = req.extensions()
+                          .get::<super::tls::ClientCertificate>()
+                          .and_then(|certificate| certificate.subject())
+                          .and_then(|subject| self.identify(subject))
+                          .cloned();
+        #[cfg(not(feature = "tls"))]
+        let identity: Option<BrokerIdentity> = None;
+        match self.check_request(req.headers(), identity.as_ref()) {
+            Some(response) => Box::pin(std::future::ready(Ok(req.into_response(response)))),
+            None           => {
+                if let Some(identity) = identity {
+                    req.extensions_mut().insert(identity);
+                }
+                Box::pin(srv.call(req))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_api_version, BackendConfig, CatalogSource, Credentials, LogFormat, ServerConfig, StoreConfig};
+    use actix_web::{http::StatusCode, test};
+    use serde_json::json;
+
+    #[test]
+    fn server_config_parse() {
+        let config: ServerConfig = serde_json::from_value(json!({
+            "bind": ["0.0.0.0:8443", "[::1]:8443"],
+            "catalog": { "type": "file", "path": "catalog.json", "reload": true },
+            "credentials": { "username": "admin", "password": "secret" },
+            "min_api_version": "2.13",
+            "log_format": "json",
+            "backend": { "type": "exec", "program": "/usr/bin/broker", "args": ["--verbose"], "timeout": 120 },
+            "store": { "type": "sqlite", "path": "/var/lib/broker.db" },
+            "shutdown_timeout": 60,
+        })).unwrap();
+        assert_eq!(&vec!["0.0.0.0:8443".to_owned(), "[::1]:8443".to_owned()], config.bind(), "bind");
+        assert_eq!(&CatalogSource::File { path: "catalog.json".into(), reload: true }, config.catalog(), "catalog");
+        assert_eq!(Some(&Credentials::new("admin".to_owned(), "secret".to_owned())), config.credentials(), "credentials");
+        assert_eq!(Some("2.13"), config.min_api_version(), "min_api_version");
+        assert_eq!("info", config.log_level(), "log_level");
+        assert_eq!(LogFormat::Json, config.log_format(), "log_format");
+        assert_eq!(&BackendConfig::Exec { program: "/usr/bin/broker".into(), args: vec!["--verbose".to_owned()], timeout: Some(120) }, config.backend(), "backend");
+        assert_eq!(&StoreConfig::Sqlite { path: "/var/lib/broker.db".into() }, config.store(), "store");
+        assert_eq!(60, config.shutdown_timeout(), "shutdown_timeout");
+
+        assert!(serde_json::from_value::<ServerConfig>(json!({ "port": 8080 })).is_err(), "unknown field");
+        assert!(serde_json::from_value::<ServerConfig>(json!({ "store": { "type": "redis" } })).is_err(), "unknown store");
+    }
+
+    #[test]
+    fn server_config_check() {
+        assert!(ServerConfig::new().check().is_ok(), "default");
+
+        let mut config = ServerConfig::new();
+        config.bind_mut().clear();
+        assert_eq!("No bind address", config.check().err().expect("MUST fail").to_string());
+
+        let mut config = ServerConfig::new();
+        *config.min_api_version_mut() = Some("two".to_owned());
+        assert_eq!("Invalid minimum API version 'two'", config.check().err().expect("MUST fail").to_string());
+
+        let mut config = ServerConfig::new();
+        *config.log_level_mut() = "verbose".to_owned();
+        assert!(config.check().is_err(), "log_level");
+
+        let mut config = ServerConfig::new();
+        *config.catalog_mut() = CatalogSource::File { path: "tests/missing_catalog.json".into(), reload: false };
+        assert!(config.check().is_err(), "missing catalog");
+
+        let mut config = ServerConfig::new();
+        *config.catalog_mut() = CatalogSource::Inline { catalog: json!({ "services": [] }) };
+        assert_eq!(0, config.check().unwrap().services().len(), "inline catalog");
+    }
+
+    #[test]
+    fn server_config_check_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        let mut config = ServerConfig::new();
+        *config.backend_mut() = BackendConfig::Directory { root: root.path().join("missing/backend") };
+        *config.store_mut() = StoreConfig::File { root: root.path().join("store") };
+        assert!(config.check().is_ok(), "missing directories");
+        assert!(!root.path().join("missing").exists(), "directories MUST NOT be created");
+
+        *config.backend_mut() = BackendConfig::Directory { root: file.join("backend") };
+        let error = config.check().err().expect("backend under a file MUST fail");
+        assert_eq!(format!("Backend root '{}' isn't usable, '{}' is not a directory", file.join("backend").display(), file.display()), error.to_string());
+
+        *config.backend_mut() = BackendConfig::Exec { program: "sh".into(), args: Vec::new(), timeout: None };
+        assert!(config.check().is_ok(), "program from PATH");
+        *config.backend_mut() = BackendConfig::Exec { program: root.path().join("missing.sh"), args: Vec::new(), timeout: None };
+        assert_eq!(format!("Program '{}' doesn't exist or isn't executable", root.path().join("missing.sh").display()), config.check().err().expect("missing program MUST fail").to_string());
+        #[cfg(unix)]
+        {
+            *config.backend_mut() = BackendConfig::Exec { program: file.clone(), args: Vec::new(), timeout: None };
+            assert!(config.check().is_err(), "non-executable program");
+        }
+
+        #[cfg(feature = "sqlite")]
+        {
+            *config.backend_mut() = BackendConfig::Directory { root: root.path().to_owned() };
+            *config.store_mut() = StoreConfig::Sqlite { path: root.path().join("missing/store.db") };
+            assert!(config.check().is_err(), "database in missing directory");
+            *config.store_mut() = StoreConfig::Sqlite { path: root.path().join("store.db") };
+            assert!(config.check().is_ok(), "new database");
+        }
+    }
+
+    #[test]
+    fn server_config_check_request() {
+        let mut config = ServerConfig::new();
+        assert!(config.check_request(test::TestRequest::get().to_http_request().headers(), None).is_none(), "unchecked");
+
+        *config.credentials_mut() = Some(Credentials::new("admin".to_owned(), "secret".to_owned()));
+        *config.min_api_version_mut() = Some("2.13".to_owned());
+        let response = config.check_request(test::TestRequest::get().to_http_request().headers(), None).expect("unauthenticated");
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status(), "unauthenticated");
+
+        let request = |version: &str| test::TestRequest::get()
+                                                  .header("Authorization", "Basic YWRtaW46c2VjcmV0")
+                                                  .header("X-Broker-API-Version", version)
+                                                  .to_http_request()
+                                                  .headers()
+                                                  .clone();
+        let response = config.check_request(&request("2.12"), None).expect("old version");
+        assert_eq!(StatusCode::PRECONDITION_FAILED, response.status(), "old version");
+        assert!(config.check_request(&request("2.13"), None).is_none(), "minimum version");
+        assert!(config.check_request(&request("2.16"), None).is_none(), "newer version");
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn server_config_identities() {
+        use super::{BrokerIdentity, TlsConfig};
+
+        let mut config: ServerConfig = serde_json::from_value(json!({
+            "credentials": { "username": "admin", "password": "secret" },
+            "tls": {
+                "certificate": "tests/tls/server.pem",
+                "private_key": "tests/tls/server-key.pem",
+                "client_ca": "tests/tls/ca.pem",
+                "identities": [{ "subject": "CN=cloudfoundry,O=Example", "name": "cf", "services": ["pgsql"] }],
+            },
+        })).unwrap();
+        let identity = config.identify("CN=cloudfoundry,O=Example").expect("identity").clone();
+        assert_eq!("cf", identity.name(), "name");
+        assert_eq!(None, config.identify("CN=kubernetes"), "unknown subject");
+
+        let headers = test::TestRequest::get().to_http_request().headers().clone();
+        assert!(config.check_request(&headers, Some(&identity)).is_none(), "identified");
+        assert!(config.check_request(&headers, None).is_some(), "anonymous");
+
+        let mut catalog = config.check().unwrap();
+        identity.filter_catalog(&mut catalog);
+        assert_eq!(vec!["pgsql"], catalog.services().iter().map(|service| service.id().as_str()).collect::<Vec<_>>(), "filtered catalog");
+        let mut catalog = config.check().unwrap();
+        BrokerIdentity::new("CN=admin".to_owned(), "admin".to_owned()).filter_catalog(&mut catalog);
+        assert_eq!(2, catalog.services().len(), "unfiltered catalog");
+        assert!(identity.check_service(&"pgsql".parse().unwrap()).is_ok(), "allowed service");
+        assert_eq!("Service 'mysql' is not available to 'cf'", identity.check_service(&"mysql".parse().unwrap()).unwrap_err().to_string(), "forbidden service");
+
+        let tls = config.tls_mut().as_mut().unwrap();
+        *tls.client_ca_mut() = None;
+        *tls.require_client_certificate_mut() = true;
+        assert_eq!("Requiring client certificates needs a client CA", config.check().err().expect("MUST fail").to_string());
+        *config.tls_mut() = Some(TlsConfig::new("tests/tls/server.pem".into(), "tests/tls/missing.pem".into()));
+        assert!(config.check().is_err(), "missing private key");
+    }
+
+    #[test]
+    fn api_version_parse() {
+        assert_eq!(Some((2, 16)), parse_api_version("2.16"));
+        assert_eq!(None, parse_api_version("2"));
+        assert_eq!(None, parse_api_version(""));
+    }
+}
@@ -1,9 +1,13 @@
-use super::model;
+use super::{model, store};
 
 use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
 
+use actix_web::http::StatusCode;
 use anyhow::Result;
 use anyhow::Context;
+use async_trait::async_trait;
 
 pub trait CatalogProvider {
     fn get_catalog(&self) -> Result<Cow<model::Catalog>>;
@@ -68,27 +72,277 @@ impl CatalogProvider for JsonFileCatalogProvider {
 
 pub struct CachingCatalogProvider<T: CatalogProvider> {
     provider: T,
-    cache: std::cell::RefCell<Option<SingleCatalogProvider>>,
+    cache: std::cell::OnceCell<SingleCatalogProvider>,
 }
 
 impl<T: CatalogProvider> CachingCatalogProvider<T> {
     pub fn new(provider: T) -> Self {
         CachingCatalogProvider {
             provider,
-            cache: std::cell::RefCell::default(),
+            cache: std::cell::OnceCell::new(),
         }
     }
 }
 
 impl<T: CatalogProvider> CatalogProvider for CachingCatalogProvider<T> {
     fn get_catalog(&self) -> Result<Cow<model::Catalog>> {
-        if let Some(provider) = self.cache.borrow().as_ref() {
-            let catalog = provider.get_catalog()?;
-            return Ok(Cow::Owned(catalog.into_owned()))
+        if let Some(provider) = self.cache.get() {
+            return provider.get_catalog();
         }
         let caching = self.provider.to_single()?;
-        *self.cache.borrow_mut() = Some(caching);
-        self.get_catalog()
+        self.cache.get_or_init(|| caching).get_catalog()
+    }
+}
+
+/// Errors defined by OSB specification.
+///
+/// Providers return them (as `anyhow::Error`) to control the response sent to the platform,
+/// any other error leads to `500 Internal Server Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    BadRequest(String),
+    NotFound(String),
+    Conflict(String),
+    Gone,
+    AsyncRequired,
+    ConcurrencyError,
+    RequiresApp,
+    MaintenanceInfoConflict,
+    UnprocessableEntity(String),
+    NotSupported(String),
+}
+
+impl ServiceError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServiceError::BadRequest(_)           => StatusCode::BAD_REQUEST,
+            ServiceError::NotFound(_)             => StatusCode::NOT_FOUND,
+            ServiceError::Conflict(_)             => StatusCode::CONFLICT,
+            ServiceError::Gone                    => StatusCode::GONE,
+            ServiceError::AsyncRequired
+            | ServiceError::ConcurrencyError
+            | ServiceError::RequiresApp
+            | ServiceError::MaintenanceInfoConflict
+            | ServiceError::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServiceError::NotSupported(_)         => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    /// Error code, as expected by platforms in `error` field
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            ServiceError::AsyncRequired           => Some("AsyncRequired"),
+            ServiceError::ConcurrencyError        => Some("ConcurrencyError"),
+            ServiceError::RequiresApp             => Some("RequiresApp"),
+            ServiceError::MaintenanceInfoConflict => Some("MaintenanceInfoConflict"),
+            _                                     => None,
+        }
+    }
+
+    pub fn to_response(&self) -> model::ErrorResponse {
+        let mut response = model::ErrorResponse::new();
+        *response.error_mut() = self.code().map(str::to_owned);
+        *response.description_mut() = Some(self.to_string());
+        response
+    }
+
+    /// Reads an error answered by a broker, if its status is defined by the specification
+    pub fn from_response(status: StatusCode, response: &model::ErrorResponse) -> Option<ServiceError> {
+        let description = || response.description().unwrap_or_default().to_owned();
+        match (status, response.error()) {
+            (StatusCode::BAD_REQUEST, _)                                        => Some(ServiceError::BadRequest(description())),
+            (StatusCode::NOT_FOUND, _)                                          => Some(ServiceError::NotFound(description())),
+            (StatusCode::CONFLICT, _)                                           => Some(ServiceError::Conflict(description())),
+            (StatusCode::GONE, _)                                               => Some(ServiceError::Gone),
+            (StatusCode::UNPROCESSABLE_ENTITY, Some("AsyncRequired"))           => Some(ServiceError::AsyncRequired),
+            (StatusCode::UNPROCESSABLE_ENTITY, Some("ConcurrencyError"))        => Some(ServiceError::ConcurrencyError),
+            (StatusCode::UNPROCESSABLE_ENTITY, Some("RequiresApp"))             => Some(ServiceError::RequiresApp),
+            (StatusCode::UNPROCESSABLE_ENTITY, Some("MaintenanceInfoConflict")) => Some(ServiceError::MaintenanceInfoConflict),
+            (StatusCode::UNPROCESSABLE_ENTITY, _)                               => Some(ServiceError::UnprocessableEntity(description())),
+            (StatusCode::NOT_IMPLEMENTED, _)                                    => Some(ServiceError::NotSupported(description())),
+            _                                                                   => None,
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceError::BadRequest(description)
+            | ServiceError::NotFound(description)
+            | ServiceError::Conflict(description)
+            | ServiceError::UnprocessableEntity(description)
+            | ServiceError::NotSupported(description) => f.write_str(description),
+            ServiceError::Gone                    => f.write_str("Resource does not exist"),
+            ServiceError::AsyncRequired           => f.write_str("This request requires client support for asynchronous service operations"),
+            ServiceError::ConcurrencyError        => f.write_str("Another operation for this resource is in progress"),
+            ServiceError::RequiresApp             => f.write_str("This service supports generation of credentials through binding an application only"),
+            ServiceError::MaintenanceInfoConflict => f.write_str("Maintenance info does not match the catalog one"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+/// Result of an operation which may complete later
+#[derive(Debug, Clone, PartialEq)]
+pub enum Completion<T> {
+    /// Operation has completed
+    Sync(T),
+    /// Operation is still running, platform will poll `last_operation`
+    Async(T),
+}
+
+impl<T> Completion<T> {
+    pub fn is_async(&self) -> bool {
+        matches!(self, Completion::Async(_))
+    }
+
+    pub fn into_inner(self) -> T {
+        match self {
+            Completion::Sync(value) | Completion::Async(value) => value,
+        }
+    }
+}
+
+/// Backend handling service instances lifecycle.
+///
+/// When `accepts_incomplete` is false, providers must fail with `ServiceError::AsyncRequired`
+/// rather than start an asynchronous operation. Operations started anyway are answered
+/// `422 AsyncRequired` too, new resources being recorded as orphans and others as running.
+#[async_trait(?Send)]
+pub trait InstanceProvider: Send + Sync {
+    async fn provision(&self, instance_id: &model::InstanceId, request: &model::ProvisionRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>>;
+
+    async fn update(&self, instance: &store::InstanceRecord, request: &model::UpdateRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>>;
+
+    async fn deprovision(&self, instance: &store::InstanceRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>>;
+
+    /// Polls an asynchronous operation previously accepted for this instance
+    async fn last_operation(&self, instance: &store::InstanceRecord, operation: Option<&str>) -> Result<model::LastOperation>;
+
+    /// Describes an instance for `GET`, defaults to what has been stored at provisioning
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        Ok(instance.to_instance_response())
+    }
+}
+
+/// Backend handling service bindings lifecycle, following `InstanceProvider` rules about
+/// `accepts_incomplete`
+#[async_trait(?Send)]
+pub trait BindingProvider: Send + Sync {
+    async fn bind(&self, instance: &store::InstanceRecord, binding_id: &model::BindingId, request: &model::BindRequest, accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>>;
+
+    async fn unbind(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>>;
+
+    /// Polls an asynchronous operation previously accepted for this binding
+    async fn last_binding_operation(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, operation: Option<&str>) -> Result<model::LastOperation>;
+
+    /// Describes a binding for `GET`, defaults to what has been stored at binding
+    async fn fetch_binding(&self, _instance: &store::InstanceRecord, binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        Ok(binding.to_binding_response())
+    }
+}
+
+fn not_supported<T>(operation: &str) -> Result<T> {
+    Err(ServiceError::NotSupported(format!("{} is not supported", operation)).into())
+}
+
+/// Backend handling all broker operations at once.
+///
+/// Every operation defaults to `501 Not Implemented`, so implementations only override the
+/// supported ones. Stored records can be described using `to_instance_response` and
+/// `to_binding_response` to support fetching.
+#[async_trait(?Send)]
+pub trait ServiceBroker: Send + Sync {
+    async fn provision(&self, _instance_id: &model::InstanceId, _request: &model::ProvisionRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        not_supported("Provisioning")
+    }
+
+    async fn update(&self, _instance: &store::InstanceRecord, _request: &model::UpdateRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        not_supported("Updating")
+    }
+
+    async fn deprovision(&self, _instance: &store::InstanceRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        not_supported("Deprovisioning")
+    }
+
+    async fn last_operation(&self, _instance: &store::InstanceRecord, _operation: Option<&str>) -> Result<model::LastOperation> {
+        not_supported("Polling instance operations")
+    }
+
+    async fn fetch_instance(&self, _instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        not_supported("Fetching instances")
+    }
+
+    async fn bind(&self, _instance: &store::InstanceRecord, _binding_id: &model::BindingId, _request: &model::BindRequest, _accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        not_supported("Binding")
+    }
+
+    async fn unbind(&self, _instance: &store::InstanceRecord, _binding: &store::BindingRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        not_supported("Unbinding")
+    }
+
+    async fn last_binding_operation(&self, _instance: &store::InstanceRecord, _binding: &store::BindingRecord, _operation: Option<&str>) -> Result<model::LastOperation> {
+        not_supported("Polling binding operations")
+    }
+
+    async fn fetch_binding(&self, _instance: &store::InstanceRecord, _binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        not_supported("Fetching bindings")
+    }
+}
+
+/// Exposes a `ServiceBroker` as both instance and binding providers
+#[derive(Clone)]
+pub struct ServiceBrokerProvider {
+    service_broker: Arc<dyn ServiceBroker>,
+}
+
+impl ServiceBrokerProvider {
+    pub fn new(service_broker: Arc<dyn ServiceBroker>) -> Self {
+        ServiceBrokerProvider { service_broker }
+    }
+}
+
+#[async_trait(?Send)]
+impl InstanceProvider for ServiceBrokerProvider {
+    async fn provision(&self, instance_id: &model::InstanceId, request: &model::ProvisionRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        self.service_broker.provision(instance_id, request, accepts_incomplete).await
+    }
+
+    async fn update(&self, instance: &store::InstanceRecord, request: &model::UpdateRequest, accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        self.service_broker.update(instance, request, accepts_incomplete).await
+    }
+
+    async fn deprovision(&self, instance: &store::InstanceRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        self.service_broker.deprovision(instance, accepts_incomplete).await
+    }
+
+    async fn last_operation(&self, instance: &store::InstanceRecord, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.service_broker.last_operation(instance, operation).await
+    }
+
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        self.service_broker.fetch_instance(instance).await
+    }
+}
+
+#[async_trait(?Send)]
+impl BindingProvider for ServiceBrokerProvider {
+    async fn bind(&self, instance: &store::InstanceRecord, binding_id: &model::BindingId, request: &model::BindRequest, accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        self.service_broker.bind(instance, binding_id, request, accepts_incomplete).await
+    }
+
+    async fn unbind(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        self.service_broker.unbind(instance, binding, accepts_incomplete).await
+    }
+
+    async fn last_binding_operation(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord, operation: Option<&str>) -> Result<model::LastOperation> {
+        self.service_broker.last_binding_operation(instance, binding, operation).await
+    }
+
+    async fn fetch_binding(&self, instance: &store::InstanceRecord, binding: &store::BindingRecord) -> Result<model::BindingResponse> {
+        self.service_broker.fetch_binding(instance, binding).await
     }
 }
 
@@ -113,7 +367,7 @@ pub mod providers {
 
 #[cfg(test)]
 mod tests {
-    use super::{model, CatalogProvider, SingleCatalogProvider, JsonFileCatalogProvider, CachingCatalogProvider};
+    use super::{model, CatalogProvider, SingleCatalogProvider, JsonFileCatalogProvider, CachingCatalogProvider, ServiceError};
     use anyhow::Result;
 
     fn build_catalog() -> model::Catalog {
@@ -121,16 +375,16 @@ mod tests {
 
 
         let mut mysql = model::Service::new();
-        *mysql.id_mut() = "mysql".to_owned();
+        *mysql.id_mut() = "mysql".parse().unwrap();
         *mysql.name_mut() = "MySQL".to_owned();
 
         let mut mysql_free = model::ServicePlan::new();
-        *mysql_free.id_mut() = "mysql_free".to_owned();
+        *mysql_free.id_mut() = "mysql_free".parse().unwrap();
         *mysql_free.name_mut() = "MySQL (Free)".to_owned();
         mysql.plans_mut().push(mysql_free);
 
         let mut mysql_small = model::ServicePlan::new();
-        *mysql_small.id_mut() = "mysql_small".to_owned();
+        *mysql_small.id_mut() = "mysql_small".parse().unwrap();
         *mysql_small.name_mut() = "MySQL (Small)".to_owned();
         mysql.plans_mut().push(mysql_small);
 
@@ -138,16 +392,16 @@ mod tests {
 
 
         let mut pgsql = model::Service::new();
-        *pgsql.id_mut() = "pgsql".to_owned();
+        *pgsql.id_mut() = "pgsql".parse().unwrap();
         *pgsql.name_mut() = "PostgreSQL".to_owned();
 
         let mut pgsql_free = model::ServicePlan::new();
-        *pgsql_free.id_mut() = "pgsql_free".to_owned();
+        *pgsql_free.id_mut() = "pgsql_free".parse().unwrap();
         *pgsql_free.name_mut() = "PostgreSQL (Free)".to_owned();
         pgsql.plans_mut().push(pgsql_free);
 
         let mut pgsql_small = model::ServicePlan::new();
-        *pgsql_small.id_mut() = "pgsql_small".to_owned();
+        *pgsql_small.id_mut() = "pgsql_small".parse().unwrap();
         *pgsql_small.name_mut() = "PostgreSQL (Small)".to_owned();
         pgsql.plans_mut().push(pgsql_small);
 
@@ -261,7 +515,23 @@ mod tests {
         assert!(cache.get_catalog().is_ok());
         assert_eq!(1, counter.get());
 
-        assert!(cache.get_catalog().is_ok());
+        assert!(matches!(cache.get_catalog(), Ok(std::borrow::Cow::Borrowed(_))), "cached catalog MUST be borrowed");
         assert_eq!(1, counter.get());
     }
+
+    #[test]
+    fn service_error_from_response() {
+        let errors = vec![
+            ServiceError::BadRequest("invalid".to_owned()),
+            ServiceError::Gone,
+            ServiceError::ConcurrencyError,
+            ServiceError::MaintenanceInfoConflict,
+            ServiceError::UnprocessableEntity("full".to_owned()),
+            ServiceError::NotSupported("unsupported".to_owned()),
+        ];
+        for error in errors {
+            assert_eq!(Some(&error), ServiceError::from_response(error.status(), &error.to_response()).as_ref(), "{:?}", error);
+        }
+        assert_eq!(None, ServiceError::from_response(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR, &model::ErrorResponse::new()));
+    }
 }
@@ -0,0 +1,273 @@
+//! Graceful shutdown.
+//!
+//! Once draining starts, mutating requests are refused with `503 Service Unavailable` while
+//! running ones are awaited for a bounded time, so that their outcome is recorded into the
+//! store. Asynchronous operations left in progress are then driven to completion on next
+//! startup by `OperationRecovery`.
+
+use super::{bindings, instances, model};
+use super::broker::Broker;
+use super::server::ResponseFuture;
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use actix_web::{http, HttpResponse};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use anyhow::Result;
+
+/// Mutating requests currently handled, refusing new ones once draining has started
+#[derive(Debug, Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// Mutating request being handled, released on drop
+struct InFlight(Arc<Drain>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn is_mutating(method: &http::Method) -> bool {
+    !matches!(*method, http::Method::GET | http::Method::HEAD | http::Method::OPTIONS)
+}
+
+impl Drain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting mutating requests
+    pub fn start(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Waits for running mutating requests, returning whether they all completed in time
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let started = Instant::now();
+        while self.in_flight() > 0 {
+            if started.elapsed() >= timeout {
+                return false;
+            }
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+        true
+    }
+
+    /// Middleware tracking requests, to be used with `App::wrap_fn`
+    pub fn guard<S>(self: &Arc<Self>, req: ServiceRequest, srv: &mut S) -> ResponseFuture
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+          S::Future: 'static {
+        if !is_mutating(req.method()) {
+            return Box::pin(srv.call(req));
+        }
+        // Counted before checking, so that `wait` can't miss a request accepted meanwhile
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let in_flight = InFlight(self.clone());
+        if self.is_draining() {
+            let mut response = model::ErrorResponse::new();
+            *response.description_mut() = Some("Broker is shutting down, retry later".to_owned());
+            let response = HttpResponse::ServiceUnavailable()
+                                        .header(http::header::RETRY_AFTER, "30")
+                                        .json(response);
+            return Box::pin(std::future::ready(Ok(req.into_response(response))));
+        }
+        let response = srv.call(req);
+        Box::pin(async move {
+            let response = response.await;
+            drop(in_flight);
+            response
+        })
+    }
+}
+
+/// Waits for an interrupt or termination signal
+pub async fn signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        std::future::poll_fn(|cx| match (interrupt.poll_recv(cx), terminate.poll_recv(cx)) {
+            (Poll::Pending, Poll::Pending) => Poll::Pending,
+            _                              => Poll::Ready(()),
+        }).await;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    actix_rt::signal::ctrl_c().await
+}
+
+/// Outcome of an operation recovery run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecoveryReport {
+    completed: Vec<String>,
+    pending: usize,
+    failures: Vec<String>,
+}
+
+impl RecoveryReport {
+    /// Operations which have succeeded or failed
+    pub fn completed(&self) -> &[String] {
+        &self.completed
+    }
+
+    /// Operations still running
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn failures(&self) -> &[String] {
+        &self.failures
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_empty() && self.pending == 0 && self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operation recovery: {} completed, {} pending, {} failure(s)",
+               self.completed.len(), self.pending, self.failures.len())?;
+        for completed in &self.completed {
+            write!(f, "\n - {}", completed)?;
+        }
+        for failure in &self.failures {
+            write!(f, "\n - {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists asynchronous operations recorded as running
+pub fn running_operations(broker: &Broker) -> Result<Vec<String>> {
+    let mut running = Vec::new();
+    for instance in broker.store().list_instances()? {
+        if let Some(operation) = instance.operation().filter(|operation| operation.in_progress()) {
+            running.push(format!("{:?} of instance '{}'", operation.kind(), instance.instance_id()));
+        }
+        for binding in broker.store().list_bindings(instance.instance_id())? {
+            if let Some(operation) = binding.operation().filter(|operation| operation.in_progress()) {
+                running.push(format!("{:?} of binding '{}' of instance '{}'", operation.kind(), binding.binding_id(), binding.instance_id()));
+            }
+        }
+    }
+    Ok(running)
+}
+
+/// Drives asynchronous operations recorded as running, such as those left by a previous run,
+/// polling their backend until they complete
+pub struct OperationRecovery {
+    broker: Broker,
+    interval: Duration,
+}
+
+impl OperationRecovery {
+    pub fn new(broker: Broker) -> Self {
+        OperationRecovery {
+            broker,
+            interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Delay between background runs
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+    pub fn interval_mut(&mut self) -> &mut Duration {
+        &mut self.interval
+    }
+
+    /// Polls backends once about running operations
+    pub async fn run(&self) -> Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+        for instance in self.broker.store().list_instances()? {
+            for binding in self.broker.store().list_bindings(instance.instance_id())? {
+                if binding.operation().is_some_and(|operation| operation.in_progress()) {
+                    let id = format!("binding '{}' of instance '{}'", binding.binding_id(), binding.instance_id());
+                    match self.recover_binding(&instance, &binding).await {
+                        Ok(Some(state)) => report.completed.push(format!("{} {:?}", id, state)),
+                        Ok(None)        => report.pending += 1,
+                        Err(error)      => report.failures.push(format!("{}: {}", id, error)),
+                    }
+                }
+            }
+            if instance.operation().is_some_and(|operation| operation.in_progress()) {
+                let id = format!("instance '{}'", instance.instance_id());
+                match self.recover_instance(&instance).await {
+                    Ok(Some(state)) => report.completed.push(format!("{} {:?}", id, state)),
+                    Ok(None)        => report.pending += 1,
+                    Err(error)      => report.failures.push(format!("{}: {}", id, error)),
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns final state, if operation has completed
+    async fn recover_instance(&self, instance: &super::store::InstanceRecord) -> Result<Option<model::LastOperationState>> {
+        // Busy instances are being handled by a request, or left to next run
+        let _lock = match self.broker.lock_instance(instance.instance_id()) {
+            Ok(lock) => lock,
+            Err(_)   => return Ok(None),
+        };
+        let instance = match self.broker.store().get_instance(instance.instance_id())? {
+            Some(instance) => instance,
+            None           => return Ok(None),
+        };
+        let current = match instance.operation().filter(|operation| operation.in_progress()) {
+            Some(current) => current,
+            None          => return Ok(None),
+        };
+        let last = instances::poll_operation(&self.broker, &instance, current, None).await?;
+        Ok(Some(last.state()).filter(|state| state.is_terminal()))
+    }
+
+    async fn recover_binding(&self, instance: &super::store::InstanceRecord, binding: &super::store::BindingRecord) -> Result<Option<model::LastOperationState>> {
+        let _lock = match self.broker.lock_binding(binding.instance_id(), binding.binding_id()) {
+            Ok(lock) => lock,
+            Err(_)   => return Ok(None),
+        };
+        let binding = match self.broker.store().get_binding(binding.instance_id(), binding.binding_id())? {
+            Some(binding) => binding,
+            None          => return Ok(None),
+        };
+        let current = match binding.operation().filter(|operation| operation.in_progress()) {
+            Some(current) => current,
+            None          => return Ok(None),
+        };
+        let last = bindings::poll_operation(&self.broker, instance, &binding, current, None).await?;
+        Ok(Some(last.state()).filter(|state| state.is_terminal()))
+    }
+
+    /// Runs recovery periodically on current actix system, handing non-empty reports to the callback
+    pub fn start<F: FnMut(RecoveryReport) + 'static>(self, mut on_report: F) {
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                match self.run().await {
+                    Ok(report) if report.is_empty() => (),
+                    Ok(report)                      => on_report(report),
+                    Err(error)                      => tracing::error!(error = %super::trace::ErrorChain(&error), "Operation recovery has failed"),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,643 @@
+use super::model;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use anyhow::anyhow;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+pub mod file;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Provision,
+    Update,
+    Deprovision,
+    Bind,
+    Unbind,
+}
+
+/// Last operation run against an instance or a binding
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Operation {
+    kind: OperationKind,
+    operation: Option<String>,
+    state: model::LastOperationState,
+    description: Option<String>,
+    started_at: SystemTime,
+}
+
+impl Operation {
+    pub fn new(kind: OperationKind, state: model::LastOperationState) -> Operation {
+        Operation {
+            kind,
+            operation: None,
+            state,
+            description: None,
+            started_at: SystemTime::now(),
+        }
+    }
+
+    pub fn kind(&self) -> OperationKind {
+        self.kind
+    }
+    pub fn kind_mut(&mut self) -> &mut OperationKind {
+        &mut self.kind
+    }
+
+    /// Operation identifier returned to the platform, if any
+    pub fn operation(&self) -> Option<&str> {
+        self.operation.as_deref()
+    }
+    pub fn operation_mut(&mut self) -> &mut Option<String> {
+        &mut self.operation
+    }
+
+    pub fn state(&self) -> model::LastOperationState {
+        self.state
+    }
+    pub fn state_mut(&mut self) -> &mut model::LastOperationState {
+        &mut self.state
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+    pub fn description_mut(&mut self) -> &mut Option<String> {
+        &mut self.description
+    }
+
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+    pub fn started_at_mut(&mut self) -> &mut SystemTime {
+        &mut self.started_at
+    }
+
+    pub fn in_progress(&self) -> bool {
+        !self.state.is_terminal()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstanceRecord {
+    instance_id: model::InstanceId,
+    service_id: model::ServiceId,
+    plan_id: model::PlanId,
+    parameters: Option<Value>,
+    context: Option<Value>,
+    dashboard_url: Option<String>,
+    metadata: Option<Value>,
+    operation: Option<Operation>,
+    orphaned_at: Option<SystemTime>,
+    created_at: SystemTime,
+    updated_at: SystemTime,
+}
+
+impl InstanceRecord {
+    pub fn new(instance_id: model::InstanceId, service_id: model::ServiceId, plan_id: model::PlanId) -> InstanceRecord {
+        let now = SystemTime::now();
+        InstanceRecord {
+            instance_id,
+            service_id,
+            plan_id,
+            parameters: None,
+            context: None,
+            dashboard_url: None,
+            metadata: None,
+            operation: None,
+            orphaned_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn instance_id(&self) -> &model::InstanceId {
+        &self.instance_id
+    }
+
+    pub fn service_id(&self) -> &model::ServiceId {
+        &self.service_id
+    }
+    pub fn service_id_mut(&mut self) -> &mut model::ServiceId {
+        &mut self.service_id
+    }
+
+    pub fn plan_id(&self) -> &model::PlanId {
+        &self.plan_id
+    }
+    pub fn plan_id_mut(&mut self) -> &mut model::PlanId {
+        &mut self.plan_id
+    }
+
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+
+    pub fn context(&self) -> Option<&Value> {
+        self.context.as_ref()
+    }
+    pub fn context_mut(&mut self) -> &mut Option<Value> {
+        &mut self.context
+    }
+
+    pub fn dashboard_url(&self) -> Option<&str> {
+        self.dashboard_url.as_deref()
+    }
+    pub fn dashboard_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.dashboard_url
+    }
+
+    pub fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref()
+    }
+    pub fn metadata_mut(&mut self) -> &mut Option<Value> {
+        &mut self.metadata
+    }
+
+    pub fn operation(&self) -> Option<&Operation> {
+        self.operation.as_ref()
+    }
+    pub fn operation_mut(&mut self) -> &mut Option<Operation> {
+        &mut self.operation
+    }
+
+    /// When the platform may have missed the creation outcome, making this record a cleanup candidate
+    pub fn orphaned_at(&self) -> Option<SystemTime> {
+        self.orphaned_at
+    }
+    pub fn orphaned_at_mut(&mut self) -> &mut Option<SystemTime> {
+        &mut self.orphaned_at
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+    pub fn created_at_mut(&mut self) -> &mut SystemTime {
+        &mut self.created_at
+    }
+
+    pub fn updated_at(&self) -> SystemTime {
+        self.updated_at
+    }
+    pub fn updated_at_mut(&mut self) -> &mut SystemTime {
+        &mut self.updated_at
+    }
+
+    /// Describes the instance as stored
+    pub fn to_instance_response(&self) -> model::InstanceResponse {
+        let mut response = model::InstanceResponse::new(self.service_id.clone(), self.plan_id.clone());
+        *response.dashboard_url_mut() = self.dashboard_url.clone();
+        *response.parameters_mut() = self.parameters.clone();
+        *response.metadata_mut() = self.metadata.clone();
+        response
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindingRecord {
+    instance_id: model::InstanceId,
+    binding_id: model::BindingId,
+    service_id: model::ServiceId,
+    plan_id: model::PlanId,
+    parameters: Option<Value>,
+    context: Option<Value>,
+    bind_resource: Option<Value>,
+    credentials: Option<Value>,
+    syslog_drain_url: Option<String>,
+    route_service_url: Option<String>,
+    volume_mounts: Option<Vec<model::VolumeMount>>,
+    endpoints: Option<Vec<model::Endpoint>>,
+    metadata: Option<Value>,
+    operation: Option<Operation>,
+    orphaned_at: Option<SystemTime>,
+    created_at: SystemTime,
+    updated_at: SystemTime,
+}
+
+impl BindingRecord {
+    pub fn new(instance_id: model::InstanceId, binding_id: model::BindingId, service_id: model::ServiceId, plan_id: model::PlanId) -> BindingRecord {
+        let now = SystemTime::now();
+        BindingRecord {
+            instance_id,
+            binding_id,
+            service_id,
+            plan_id,
+            parameters: None,
+            context: None,
+            bind_resource: None,
+            credentials: None,
+            syslog_drain_url: None,
+            route_service_url: None,
+            volume_mounts: None,
+            endpoints: None,
+            metadata: None,
+            operation: None,
+            orphaned_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn instance_id(&self) -> &model::InstanceId {
+        &self.instance_id
+    }
+
+    pub fn binding_id(&self) -> &model::BindingId {
+        &self.binding_id
+    }
+
+    pub fn service_id(&self) -> &model::ServiceId {
+        &self.service_id
+    }
+    pub fn service_id_mut(&mut self) -> &mut model::ServiceId {
+        &mut self.service_id
+    }
+
+    pub fn plan_id(&self) -> &model::PlanId {
+        &self.plan_id
+    }
+    pub fn plan_id_mut(&mut self) -> &mut model::PlanId {
+        &mut self.plan_id
+    }
+
+    pub fn parameters(&self) -> Option<&Value> {
+        self.parameters.as_ref()
+    }
+    pub fn parameters_mut(&mut self) -> &mut Option<Value> {
+        &mut self.parameters
+    }
+
+    pub fn context(&self) -> Option<&Value> {
+        self.context.as_ref()
+    }
+    pub fn context_mut(&mut self) -> &mut Option<Value> {
+        &mut self.context
+    }
+
+    pub fn bind_resource(&self) -> Option<&Value> {
+        self.bind_resource.as_ref()
+    }
+    pub fn bind_resource_mut(&mut self) -> &mut Option<Value> {
+        &mut self.bind_resource
+    }
+
+    pub fn credentials(&self) -> Option<&Value> {
+        self.credentials.as_ref()
+    }
+    pub fn credentials_mut(&mut self) -> &mut Option<Value> {
+        &mut self.credentials
+    }
+
+    pub fn syslog_drain_url(&self) -> Option<&str> {
+        self.syslog_drain_url.as_deref()
+    }
+    pub fn syslog_drain_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.syslog_drain_url
+    }
+
+    pub fn route_service_url(&self) -> Option<&str> {
+        self.route_service_url.as_deref()
+    }
+    pub fn route_service_url_mut(&mut self) -> &mut Option<String> {
+        &mut self.route_service_url
+    }
+
+    pub fn volume_mounts(&self) -> Option<&Vec<model::VolumeMount>> {
+        self.volume_mounts.as_ref()
+    }
+    pub fn volume_mounts_mut(&mut self) -> &mut Option<Vec<model::VolumeMount>> {
+        &mut self.volume_mounts
+    }
+
+    pub fn endpoints(&self) -> Option<&Vec<model::Endpoint>> {
+        self.endpoints.as_ref()
+    }
+    pub fn endpoints_mut(&mut self) -> &mut Option<Vec<model::Endpoint>> {
+        &mut self.endpoints
+    }
+
+    pub fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref()
+    }
+    pub fn metadata_mut(&mut self) -> &mut Option<Value> {
+        &mut self.metadata
+    }
+
+    pub fn operation(&self) -> Option<&Operation> {
+        self.operation.as_ref()
+    }
+    pub fn operation_mut(&mut self) -> &mut Option<Operation> {
+        &mut self.operation
+    }
+
+    /// When the platform may have missed the creation outcome, making this record a cleanup candidate
+    pub fn orphaned_at(&self) -> Option<SystemTime> {
+        self.orphaned_at
+    }
+    pub fn orphaned_at_mut(&mut self) -> &mut Option<SystemTime> {
+        &mut self.orphaned_at
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+    pub fn created_at_mut(&mut self) -> &mut SystemTime {
+        &mut self.created_at
+    }
+
+    pub fn updated_at(&self) -> SystemTime {
+        self.updated_at
+    }
+    pub fn updated_at_mut(&mut self) -> &mut SystemTime {
+        &mut self.updated_at
+    }
+
+    /// Describes the binding as stored, without its parameters
+    pub fn to_binding_response(&self) -> model::BindingResponse {
+        let mut response = model::BindingResponse::new();
+        *response.credentials_mut() = self.credentials.clone();
+        *response.syslog_drain_url_mut() = self.syslog_drain_url.clone();
+        *response.route_service_url_mut() = self.route_service_url.clone();
+        *response.volume_mounts_mut() = self.volume_mounts.clone();
+        *response.endpoints_mut() = self.endpoints.clone();
+        *response.metadata_mut() = self.metadata.clone();
+        response
+    }
+}
+
+/// Persistence of service instances.
+///
+/// Implementations are shared between server workers, thus MUST be safe to use concurrently.
+pub trait InstanceStore: Send + Sync {
+    fn get_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>>;
+
+    /// Inserts or replaces an instance
+    fn put_instance(&self, record: InstanceRecord) -> Result<()>;
+
+    /// Atomically updates an existing instance, bumping its `updated_at`
+    fn update_instance(&self, instance_id: &model::InstanceId, update: &mut dyn FnMut(&mut InstanceRecord)) -> Result<Option<InstanceRecord>>;
+
+    fn delete_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>>;
+
+    fn list_instances(&self) -> Result<Vec<InstanceRecord>>;
+}
+
+/// Persistence of service bindings.
+///
+/// Implementations are shared between server workers, thus MUST be safe to use concurrently.
+pub trait BindingStore: Send + Sync {
+    fn get_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>>;
+
+    /// Inserts or replaces a binding
+    fn put_binding(&self, record: BindingRecord) -> Result<()>;
+
+    /// Atomically updates an existing binding, bumping its `updated_at`
+    fn update_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, update: &mut dyn FnMut(&mut BindingRecord)) -> Result<Option<BindingRecord>>;
+
+    fn delete_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>>;
+
+    fn list_bindings(&self, instance_id: &model::InstanceId) -> Result<Vec<BindingRecord>>;
+}
+
+pub trait Store: InstanceStore + BindingStore {}
+
+impl<T: InstanceStore + BindingStore> Store for T {}
+
+fn poisoned<T>(_: T) -> anyhow::Error {
+    anyhow!("In-memory store lock has been poisoned")
+}
+
+/// Store keeping records in process memory, lost on restart
+#[derive(Default)]
+pub struct InMemoryStore {
+    instances: RwLock<HashMap<model::InstanceId, InstanceRecord>>,
+    bindings: RwLock<HashMap<model::InstanceId, HashMap<model::BindingId, BindingRecord>>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl InstanceStore for InMemoryStore {
+    fn get_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+        let instances = self.instances.read().map_err(poisoned)?;
+        Ok(instances.get(instance_id).cloned())
+    }
+
+    fn put_instance(&self, record: InstanceRecord) -> Result<()> {
+        let mut instances = self.instances.write().map_err(poisoned)?;
+        instances.insert(record.instance_id().clone(), record);
+        Ok(())
+    }
+
+    fn update_instance(&self, instance_id: &model::InstanceId, update: &mut dyn FnMut(&mut InstanceRecord)) -> Result<Option<InstanceRecord>> {
+        let mut instances = self.instances.write().map_err(poisoned)?;
+        Ok(instances.get_mut(instance_id).map(|record| {
+            update(record);
+            record.updated_at = SystemTime::now();
+            record.clone()
+        }))
+    }
+
+    fn delete_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+        let mut instances = self.instances.write().map_err(poisoned)?;
+        Ok(instances.remove(instance_id))
+    }
+
+    fn list_instances(&self) -> Result<Vec<InstanceRecord>> {
+        let instances = self.instances.read().map_err(poisoned)?;
+        Ok(instances.values().cloned().collect())
+    }
+}
+
+impl BindingStore for InMemoryStore {
+    fn get_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+        let bindings = self.bindings.read().map_err(poisoned)?;
+        Ok(bindings.get(instance_id).and_then(|bindings| bindings.get(binding_id)).cloned())
+    }
+
+    fn put_binding(&self, record: BindingRecord) -> Result<()> {
+        let mut bindings = self.bindings.write().map_err(poisoned)?;
+        bindings.entry(record.instance_id().clone())
+                .or_default()
+                .insert(record.binding_id().clone(), record);
+        Ok(())
+    }
+
+    fn update_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, update: &mut dyn FnMut(&mut BindingRecord)) -> Result<Option<BindingRecord>> {
+        let mut bindings = self.bindings.write().map_err(poisoned)?;
+        Ok(bindings.get_mut(instance_id)
+                   .and_then(|bindings| bindings.get_mut(binding_id))
+                   .map(|record| {
+                       update(record);
+                       record.updated_at = SystemTime::now();
+                       record.clone()
+                   }))
+    }
+
+    fn delete_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+        let mut bindings = self.bindings.write().map_err(poisoned)?;
+        let removed = bindings.get_mut(instance_id).and_then(|bindings| bindings.remove(binding_id));
+        if bindings.get(instance_id).map(HashMap::is_empty).unwrap_or(false) {
+            bindings.remove(instance_id);
+        }
+        Ok(removed)
+    }
+
+    fn list_bindings(&self, instance_id: &model::InstanceId) -> Result<Vec<BindingRecord>> {
+        let bindings = self.bindings.read().map_err(poisoned)?;
+        Ok(bindings.get(instance_id)
+                   .map(|bindings| bindings.values().cloned().collect())
+                   .unwrap_or_default())
+    }
+}
+
+pub mod providers {
+    pub mod store {
+        use super::super::{InMemoryStore, file::JsonFileStore};
+
+        pub fn memory() -> InMemoryStore {
+            InMemoryStore::new()
+        }
+
+        pub fn file_json<P: AsRef<std::path::Path>>(root: P) -> anyhow::Result<JsonFileStore> {
+            JsonFileStore::open(root)
+        }
+
+        #[cfg(feature = "sqlite")]
+        pub fn sqlite<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<super::super::sqlite::SqliteStore> {
+            super::super::sqlite::SqliteStore::open(path)
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{model, Store, InstanceStore, BindingStore, InMemoryStore, InstanceRecord, BindingRecord, Operation, OperationKind};
+    use serde_json::json;
+
+    fn instance(id: &str) -> InstanceRecord {
+        let mut record = InstanceRecord::new(id.parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        *record.parameters_mut() = Some(json!({ "size": 1 }));
+        *record.context_mut() = Some(json!({ "platform": "cloudfoundry", "space_guid": "dev" }));
+        record
+    }
+
+    fn binding(instance_id: &str, binding_id: &str) -> BindingRecord {
+        let mut record = BindingRecord::new(instance_id.parse().unwrap(), binding_id.parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        *record.credentials_mut() = Some(json!({ "username": binding_id }));
+        *record.syslog_drain_url_mut() = Some("syslog://logs:514".to_owned());
+        *record.volume_mounts_mut() = Some(vec![serde_json::from_value(json!({
+            "driver": "nfs",
+            "container_dir": "/data",
+            "mode": "r",
+            "device_type": "shared",
+            "device": { "volume_id": binding_id },
+        })).unwrap()]);
+        *record.endpoints_mut() = Some(vec![model::Endpoint::new("db.local".to_owned(), vec!["3306".parse().unwrap()])]);
+        record
+    }
+
+    /// Checks behaviour every store implementation MUST comply with
+    pub(crate) fn check_store(store: &dyn Store) {
+        check_instances(store);
+        check_bindings(store);
+    }
+
+    fn check_instances(store: &dyn Store) {
+        let i1: model::InstanceId = "i1".parse().unwrap();
+        assert!(store.get_instance(&i1).unwrap().is_none(), "[Empty] get");
+        assert!(store.list_instances().unwrap().is_empty(), "[Empty] list");
+        assert!(store.update_instance(&i1, &mut |_| panic!("MUST not be called")).unwrap().is_none(), "[Empty] update");
+        assert!(store.delete_instance(&i1).unwrap().is_none(), "[Empty] delete");
+
+        let record = instance("i1");
+        store.put_instance(record.clone()).unwrap();
+        // Leading dot MUST not hide records from listing
+        store.put_instance(instance(".i2")).unwrap();
+        assert_eq!(Some(&record), store.get_instance(&i1).unwrap().as_ref(), "[Put] get");
+        assert_eq!(2, store.list_instances().unwrap().len(), "[Put] list");
+
+        let updated = store.update_instance(&i1, &mut |record| {
+            *record.plan_id_mut() = "mysql_small".parse().unwrap();
+            *record.dashboard_url_mut() = Some("http://dashboard/i1".to_owned());
+            *record.operation_mut() = Some(Operation::new(OperationKind::Update, model::LastOperationState::InProgress));
+            *record.orphaned_at_mut() = Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1));
+        }).unwrap().expect("[Update] MUST return record");
+        assert_eq!("mysql_small", updated.plan_id(), "[Update] plan_id");
+        assert!(updated.updated_at() >= record.updated_at(), "[Update] updated_at");
+        assert_eq!(record.created_at(), updated.created_at(), "[Update] created_at");
+        assert_eq!(Some(&updated), store.get_instance(&i1).unwrap().as_ref(), "[Update] get");
+        let operation = updated.operation().expect("[Update] operation");
+        assert_eq!(OperationKind::Update, operation.kind(), "[Update] operation.kind");
+        assert!(operation.in_progress(), "[Update] operation.in_progress");
+
+        assert_eq!(Some(updated), store.delete_instance(&i1).unwrap(), "[Delete] removed");
+        assert!(store.get_instance(&i1).unwrap().is_none(), "[Delete] get");
+        assert_eq!(1, store.list_instances().unwrap().len(), "[Delete] list");
+        store.delete_instance(&".i2".parse().unwrap()).unwrap();
+    }
+
+    fn check_bindings(store: &dyn Store) {
+        let i1: model::InstanceId = "i1".parse().unwrap();
+        let b1: model::BindingId = "b1".parse().unwrap();
+        assert!(store.get_binding(&i1, &b1).unwrap().is_none(), "[Empty] get");
+        assert!(store.list_bindings(&i1).unwrap().is_empty(), "[Empty] list");
+        assert!(store.delete_binding(&i1, &b1).unwrap().is_none(), "[Empty] delete");
+
+        let record = binding("i1", "b1");
+        store.put_binding(record.clone()).unwrap();
+        store.put_binding(binding("i1", ".b2")).unwrap();
+        store.put_binding(binding("i2", "b1")).unwrap();
+        assert_eq!(Some(&record), store.get_binding(&i1, &b1).unwrap().as_ref(), "[Put] get");
+        assert_eq!(2, store.list_bindings(&i1).unwrap().len(), "[Put] list");
+
+        let updated = store.update_binding(&i1, &b1, &mut |record| {
+            *record.operation_mut() = Some(Operation::new(OperationKind::Unbind, model::LastOperationState::Failed));
+            *record.orphaned_at_mut() = Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1));
+        }).unwrap().expect("[Update] MUST return record");
+        assert_eq!(Some(&updated), store.get_binding(&i1, &b1).unwrap().as_ref(), "[Update] get");
+
+        assert_eq!(Some(updated), store.delete_binding(&i1, &b1).unwrap(), "[Delete] removed");
+        assert!(store.get_binding(&i1, &b1).unwrap().is_none(), "[Delete] get");
+        assert_eq!(1, store.list_bindings(&i1).unwrap().len(), "[Delete] list.i1");
+        assert_eq!(1, store.list_bindings(&"i2".parse().unwrap()).unwrap().len(), "[Delete] list.i2");
+    }
+
+    #[test]
+    fn store_memory() {
+        check_store(&InMemoryStore::new());
+    }
+
+    #[test]
+    fn store_memory_concurrent() {
+        let store = std::sync::Arc::new(InMemoryStore::new());
+        let threads: Vec<_> = (0..8).map(|t| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    let id = format!("i{}-{}", t, i);
+                    store.put_instance(instance(&id)).unwrap();
+                    store.put_binding(binding(&id, "b")).unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(400, store.list_instances().unwrap().len());
+    }
+}
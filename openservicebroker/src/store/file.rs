@@ -0,0 +1,295 @@
+use super::{model, InstanceStore, BindingStore, InstanceRecord, BindingRecord};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use anyhow::Context;
+use anyhow::anyhow;
+use fs2::FileExt;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Store writing each record as a JSON file under a root directory:
+///
+/// ```text
+/// <root>/.lock
+/// <root>/instances/<instance_id>.json
+/// <root>/bindings/<instance_id>/<binding_id>.json
+/// ```
+///
+/// Files are replaced using atomic rename, followed by a sync of their directory. Operations
+/// hold an advisory lock on `.lock` (shared for reads, exclusive for writes) so several broker
+/// processes can use the same directory without corrupting it.
+pub struct JsonFileStore {
+    root: PathBuf,
+    lock: RwLock<()>,
+}
+
+/// Held advisory lock, released on drop
+struct FileLock(File);
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+impl JsonFileStore {
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_owned();
+        for dir in &[root.join("instances"), root.join("bindings")] {
+            fs::create_dir_all(dir)
+               .with_context(|| format!("Can't create store directory '{}'", dir.display()))?;
+        }
+        Ok(JsonFileStore {
+            root,
+            lock: RwLock::default(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn file_lock(&self, exclusive: bool) -> Result<FileLock> {
+        let path = self.root.join(".lock");
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+                                     .with_context(|| format!("Can't open store lock '{}'", path.display()))?;
+        if exclusive {
+            file.lock_exclusive()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(FileLock(file))
+    }
+
+    fn read<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let _guard = self.lock.read().map_err(|_| anyhow!("File store lock has been poisoned"))?;
+        let _lock = self.file_lock(false)?;
+        f()
+    }
+
+    fn write<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let _guard = self.lock.write().map_err(|_| anyhow!("File store lock has been poisoned"))?;
+        let _lock = self.file_lock(true)?;
+        f()
+    }
+
+    fn instance_path(&self, instance_id: &model::InstanceId) -> PathBuf {
+        self.root.join("instances").join(format!("{}.json", instance_id))
+    }
+
+    fn bindings_dir(&self, instance_id: &model::InstanceId) -> PathBuf {
+        self.root.join("bindings").join(instance_id.as_str())
+    }
+
+    fn binding_path(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> PathBuf {
+        self.bindings_dir(instance_id).join(format!("{}.json", binding_id))
+    }
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let file = match File::open(path) {
+        Ok(file)                                        => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error)                                      => return Err(error).with_context(|| format!("Can't open store file '{}'", path.display())),
+    };
+    let record = serde_json::from_reader(file)
+                            .with_context(|| format!("Can't read store file '{}' as JSON", path.display()))?;
+    Ok(Some(record))
+}
+
+fn save<T: Serialize>(path: &Path, record: &T) -> Result<()> {
+    let dir = path.parent().ok_or_else(|| anyhow!("Store file '{}' has no parent", path.display()))?;
+    fs::create_dir_all(dir)?;
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let temp = dir.join(format!(".{}.tmp", name));
+    {
+        let mut file = File::create(&temp)
+                            .with_context(|| format!("Can't create store file '{}'", temp.display()))?;
+        serde_json::to_writer_pretty(&mut file, record)?;
+        file.flush()?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp, path)
+       .with_context(|| format!("Can't replace store file '{}'", path.display()))?;
+    sync_dir(dir)
+}
+
+/// Makes a rename durable, as it is only recorded in the directory entry
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir).and_then(|dir| dir.sync_all())
+                   .with_context(|| format!("Can't sync store directory '{}'", dir.display()))
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error).with_context(|| format!("Can't remove store file '{}'", path.display())),
+        _ => Ok(()),
+    }
+}
+
+fn load_all<T: DeserializeOwned>(dir: &Path) -> Result<Vec<T>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries)                                       => entries,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(error)                                        => return Err(error).with_context(|| format!("Can't list store directory '{}'", dir.display())),
+    };
+    let mut records = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        // Skips `.tmp` files of interrupted writes
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        if let Some(record) = load(&path)? {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+impl InstanceStore for JsonFileStore {
+    fn get_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+        self.read(|| load(&self.instance_path(instance_id)))
+    }
+
+    fn put_instance(&self, record: InstanceRecord) -> Result<()> {
+        self.write(|| save(&self.instance_path(record.instance_id()), &record))
+    }
+
+    fn update_instance(&self, instance_id: &model::InstanceId, update: &mut dyn FnMut(&mut InstanceRecord)) -> Result<Option<InstanceRecord>> {
+        self.write(|| {
+            let path = self.instance_path(instance_id);
+            let mut record: InstanceRecord = match load(&path)? {
+                Some(record) => record,
+                None         => return Ok(None),
+            };
+            update(&mut record);
+            record.updated_at = SystemTime::now();
+            save(&path, &record)?;
+            Ok(Some(record))
+        })
+    }
+
+    fn delete_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+        self.write(|| {
+            let path = self.instance_path(instance_id);
+            let record = load(&path)?;
+            remove(&path)?;
+            Ok(record)
+        })
+    }
+
+    fn list_instances(&self) -> Result<Vec<InstanceRecord>> {
+        self.read(|| load_all(&self.root.join("instances")))
+    }
+}
+
+impl BindingStore for JsonFileStore {
+    fn get_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+        self.read(|| load(&self.binding_path(instance_id, binding_id)))
+    }
+
+    fn put_binding(&self, record: BindingRecord) -> Result<()> {
+        self.write(|| save(&self.binding_path(record.instance_id(), record.binding_id()), &record))
+    }
+
+    fn update_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, update: &mut dyn FnMut(&mut BindingRecord)) -> Result<Option<BindingRecord>> {
+        self.write(|| {
+            let path = self.binding_path(instance_id, binding_id);
+            let mut record: BindingRecord = match load(&path)? {
+                Some(record) => record,
+                None         => return Ok(None),
+            };
+            update(&mut record);
+            record.updated_at = SystemTime::now();
+            save(&path, &record)?;
+            Ok(Some(record))
+        })
+    }
+
+    fn delete_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+        self.write(|| {
+            let path = self.binding_path(instance_id, binding_id);
+            let record = load(&path)?;
+            remove(&path)?;
+            // Only succeeds once the instance has no binding left
+            let _ = fs::remove_dir(self.bindings_dir(instance_id));
+            Ok(record)
+        })
+    }
+
+    fn list_bindings(&self, instance_id: &model::InstanceId) -> Result<Vec<BindingRecord>> {
+        self.read(|| load_all(&self.bindings_dir(instance_id)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::JsonFileStore;
+    use super::super::{InstanceStore, BindingStore, InstanceRecord, BindingRecord};
+    use super::super::tests::check_store;
+
+    #[test]
+    fn store_file_json() {
+        let dir = tempfile::tempdir().unwrap();
+        check_store(&JsonFileStore::open(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn store_file_json_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let instance = InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        let binding = BindingRecord::new("i1".parse().unwrap(), "b1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        {
+            let store = JsonFileStore::open(dir.path()).unwrap();
+            store.put_instance(instance.clone()).unwrap();
+            store.put_binding(binding.clone()).unwrap();
+        }
+        assert!(dir.path().join("instances/i1.json").is_file(), "instance file");
+        assert!(dir.path().join("bindings/i1/b1.json").is_file(), "binding file");
+        // Left over by an interrupted write
+        std::fs::write(dir.path().join("instances/.i2.json.tmp"), "{").unwrap();
+
+        let store = JsonFileStore::open(dir.path()).unwrap();
+        assert_eq!(vec![instance], store.list_instances().unwrap(), "instances");
+        assert_eq!(vec![binding], store.list_bindings(&"i1".parse().unwrap()).unwrap(), "bindings");
+    }
+
+    #[test]
+    fn store_file_json_concurrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = std::sync::Arc::new(JsonFileStore::open(dir.path()).unwrap());
+        let instance = InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        store.put_instance(instance).unwrap();
+
+        // Separate store on the same directory, as another broker process would do
+        let other = std::sync::Arc::new(JsonFileStore::open(dir.path()).unwrap());
+        let threads: Vec<_> = vec![store.clone(), other].into_iter().map(|store| {
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    store.update_instance(&"i1".parse().unwrap(), &mut |record| {
+                        let count = record.parameters().and_then(|count| count.as_u64()).unwrap_or(0);
+                        *record.parameters_mut() = Some((count + 1).into());
+                    }).unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let record = store.get_instance(&"i1".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(Some(50), record.parameters().and_then(|count| count.as_u64()));
+    }
+}
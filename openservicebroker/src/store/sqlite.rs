@@ -0,0 +1,479 @@
+use super::{model, InstanceStore, BindingStore, InstanceRecord, BindingRecord, Operation, OperationKind};
+
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use anyhow::Context;
+use anyhow::anyhow;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use serde_json::Value;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` holds how many have been applied.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE instances (
+        instance_id   TEXT    NOT NULL PRIMARY KEY,
+        service_id    TEXT    NOT NULL,
+        plan_id       TEXT    NOT NULL,
+        parameters    TEXT,
+        context       TEXT,
+        dashboard_url TEXT,
+        metadata      TEXT,
+        created_at    INTEGER NOT NULL,
+        updated_at    INTEGER NOT NULL
+    );
+    CREATE TABLE bindings (
+        instance_id   TEXT    NOT NULL,
+        binding_id    TEXT    NOT NULL,
+        service_id    TEXT    NOT NULL,
+        plan_id       TEXT    NOT NULL,
+        parameters    TEXT,
+        context       TEXT,
+        bind_resource TEXT,
+        credentials   TEXT,
+        metadata      TEXT,
+        created_at    INTEGER NOT NULL,
+        updated_at    INTEGER NOT NULL,
+        PRIMARY KEY (instance_id, binding_id)
+    );
+    CREATE TABLE operations (
+        instance_id   TEXT    NOT NULL,
+        binding_id    TEXT    NOT NULL DEFAULT '',
+        kind          TEXT    NOT NULL,
+        operation     TEXT,
+        state         TEXT    NOT NULL,
+        description   TEXT,
+        started_at    INTEGER NOT NULL,
+        PRIMARY KEY (instance_id, binding_id)
+    );",
+    "ALTER TABLE instances ADD COLUMN orphaned_at INTEGER;
+    ALTER TABLE bindings ADD COLUMN orphaned_at INTEGER;",
+    "ALTER TABLE bindings ADD COLUMN syslog_drain_url TEXT;
+    ALTER TABLE bindings ADD COLUMN route_service_url TEXT;
+    ALTER TABLE bindings ADD COLUMN volume_mounts TEXT;",
+    "ALTER TABLE bindings ADD COLUMN endpoints TEXT;",
+];
+
+/// Store persisting records into a SQLite database file
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+                                    .with_context(|| format!("Can't open SQLite store '{}'", path.display()))?;
+        Self::from_connection(connection)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<Self> {
+        connection.busy_timeout(Duration::from_secs(5))?;
+        migrate(&mut connection)?;
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Number of schema migrations applied to the database
+    pub fn schema_version(&self) -> Result<usize> {
+        let connection = self.lock()?;
+        schema_version(&connection)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.connection.lock().map_err(|_| anyhow!("SQLite store lock has been poisoned"))
+    }
+
+    fn transaction<T>(&self, f: impl FnOnce(&Transaction) -> Result<T>) -> Result<T> {
+        let mut connection = self.lock()?;
+        let transaction = connection.transaction()?;
+        let result = f(&transaction)?;
+        transaction.commit()?;
+        Ok(result)
+    }
+}
+
+fn schema_version(connection: &Connection) -> Result<usize> {
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let transaction = connection.transaction()?;
+    let version = schema_version(&transaction)?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!("SQLite store schema version {} is newer than supported one ({})", version, MIGRATIONS.len()));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        transaction.execute_batch(migration)
+                   .with_context(|| format!("SQLite store migration #{} has failed", index + 1))?;
+    }
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len() as i64)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn to_timestamp(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as i64).unwrap_or(0)
+}
+
+fn from_timestamp(nanos: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos as u64)
+}
+
+fn to_json(value: Option<&Value>) -> Result<Option<String>> {
+    Ok(match value {
+        Some(value) => Some(serde_json::to_string(value)?),
+        None        => None,
+    })
+}
+
+fn from_json(text: Option<String>) -> Result<Option<Value>> {
+    Ok(match text {
+        Some(text) => Some(serde_json::from_str(&text)?),
+        None       => None,
+    })
+}
+
+/// Enumerations are stored using their JSON string representation
+fn to_text<T: serde::Serialize>(value: T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        Value::String(text) => Ok(text),
+        other               => Err(anyhow!("Unexpected enumeration value '{}'", other)),
+    }
+}
+
+fn from_text<T: serde::de::DeserializeOwned>(text: String) -> Result<T> {
+    Ok(serde_json::from_value(Value::String(text))?)
+}
+
+fn parse_id<T: std::str::FromStr<Err = model::IdError>>(text: String) -> Result<T> {
+    text.parse().with_context(|| "Invalid identifier found in SQLite store")
+}
+
+fn read_operation(transaction: &Transaction, instance_id: &str, binding_id: &str) -> Result<Option<Operation>> {
+    let row = transaction.query_row(
+        "SELECT kind, operation, state, description, started_at FROM operations WHERE instance_id = ?1 AND binding_id = ?2",
+        params![instance_id, binding_id],
+        |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get(3)?, row.get(4)?)),
+    ).optional()?;
+    Ok(match row {
+        Some((kind, operation, state, description, started_at)) => Some(Operation {
+            kind: from_text::<OperationKind>(kind)?,
+            operation,
+            state: from_text(state)?,
+            description,
+            started_at: from_timestamp(started_at),
+        }),
+        None => None,
+    })
+}
+
+fn write_operation(transaction: &Transaction, instance_id: &str, binding_id: &str, operation: Option<&Operation>) -> Result<()> {
+    match operation {
+        Some(operation) => transaction.execute(
+            "INSERT OR REPLACE INTO operations (instance_id, binding_id, kind, operation, state, description, started_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                instance_id,
+                binding_id,
+                to_text(operation.kind)?,
+                operation.operation,
+                to_text(operation.state)?,
+                operation.description,
+                to_timestamp(operation.started_at),
+            ],
+        )?,
+        None => transaction.execute(
+            "DELETE FROM operations WHERE instance_id = ?1 AND binding_id = ?2",
+            params![instance_id, binding_id],
+        )?,
+    };
+    Ok(())
+}
+
+const INSTANCE_COLUMNS: &str = "instance_id, service_id, plan_id, parameters, context, dashboard_url, metadata, created_at, updated_at, orphaned_at";
+
+fn read_instance_row(row: &Row) -> rusqlite::Result<[Option<String>; 7]> {
+    Ok([row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?])
+}
+
+fn to_instance(transaction: &Transaction, (columns, created_at, updated_at, orphaned_at): ([Option<String>; 7], i64, i64, Option<i64>)) -> Result<InstanceRecord> {
+    let [instance_id, service_id, plan_id, parameters, context, dashboard_url, metadata] = columns;
+    let instance_id = instance_id.unwrap_or_default();
+    let operation = read_operation(transaction, &instance_id, "")?;
+    Ok(InstanceRecord {
+        instance_id: parse_id(instance_id)?,
+        service_id: parse_id(service_id.unwrap_or_default())?,
+        plan_id: parse_id(plan_id.unwrap_or_default())?,
+        parameters: from_json(parameters)?,
+        context: from_json(context)?,
+        dashboard_url,
+        metadata: from_json(metadata)?,
+        operation,
+        orphaned_at: orphaned_at.map(from_timestamp),
+        created_at: from_timestamp(created_at),
+        updated_at: from_timestamp(updated_at),
+    })
+}
+
+fn select_instance(transaction: &Transaction, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+    let row = transaction.query_row(
+        &format!("SELECT {} FROM instances WHERE instance_id = ?1", INSTANCE_COLUMNS),
+        params![instance_id.as_str()],
+        |row| Ok((read_instance_row(row)?, row.get(7)?, row.get(8)?, row.get(9)?)),
+    ).optional()?;
+    row.map(|row| to_instance(transaction, row)).transpose()
+}
+
+fn write_instance(transaction: &Transaction, record: &InstanceRecord) -> Result<()> {
+    transaction.execute(
+        &format!("INSERT OR REPLACE INTO instances ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", INSTANCE_COLUMNS),
+        params![
+            record.instance_id.as_str(),
+            record.service_id.as_str(),
+            record.plan_id.as_str(),
+            to_json(record.parameters.as_ref())?,
+            to_json(record.context.as_ref())?,
+            record.dashboard_url,
+            to_json(record.metadata.as_ref())?,
+            to_timestamp(record.created_at),
+            to_timestamp(record.updated_at),
+            record.orphaned_at.map(to_timestamp),
+        ],
+    )?;
+    write_operation(transaction, record.instance_id.as_str(), "", record.operation.as_ref())
+}
+
+const BINDING_COLUMNS: &str = "instance_id, binding_id, service_id, plan_id, parameters, context, bind_resource, credentials, metadata, created_at, updated_at, orphaned_at, syslog_drain_url, route_service_url, volume_mounts, endpoints";
+
+/// Text columns, skipping timestamps ones
+fn read_binding_row(row: &Row) -> rusqlite::Result<[Option<String>; 13]> {
+    Ok([row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?, row.get(12)?, row.get(13)?, row.get(14)?, row.get(15)?])
+}
+
+fn to_binding(transaction: &Transaction, (columns, created_at, updated_at, orphaned_at): ([Option<String>; 13], i64, i64, Option<i64>)) -> Result<BindingRecord> {
+    let [instance_id, binding_id, service_id, plan_id, parameters, context, bind_resource, credentials, metadata, syslog_drain_url, route_service_url, volume_mounts, endpoints] = columns;
+    let instance_id = instance_id.unwrap_or_default();
+    let binding_id = binding_id.unwrap_or_default();
+    let operation = read_operation(transaction, &instance_id, &binding_id)?;
+    Ok(BindingRecord {
+        instance_id: parse_id(instance_id)?,
+        binding_id: parse_id(binding_id)?,
+        service_id: parse_id(service_id.unwrap_or_default())?,
+        plan_id: parse_id(plan_id.unwrap_or_default())?,
+        parameters: from_json(parameters)?,
+        context: from_json(context)?,
+        bind_resource: from_json(bind_resource)?,
+        credentials: from_json(credentials)?,
+        syslog_drain_url,
+        route_service_url,
+        volume_mounts: from_json(volume_mounts)?.map(serde_json::from_value).transpose()?,
+        endpoints: from_json(endpoints)?.map(serde_json::from_value).transpose()?,
+        metadata: from_json(metadata)?,
+        operation,
+        orphaned_at: orphaned_at.map(from_timestamp),
+        created_at: from_timestamp(created_at),
+        updated_at: from_timestamp(updated_at),
+    })
+}
+
+fn select_binding(transaction: &Transaction, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+    let row = transaction.query_row(
+        &format!("SELECT {} FROM bindings WHERE instance_id = ?1 AND binding_id = ?2", BINDING_COLUMNS),
+        params![instance_id.as_str(), binding_id.as_str()],
+        |row| Ok((read_binding_row(row)?, row.get(9)?, row.get(10)?, row.get(11)?)),
+    ).optional()?;
+    row.map(|row| to_binding(transaction, row)).transpose()
+}
+
+fn write_binding(transaction: &Transaction, record: &BindingRecord) -> Result<()> {
+    transaction.execute(
+        &format!("INSERT OR REPLACE INTO bindings ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)", BINDING_COLUMNS),
+        params![
+            record.instance_id.as_str(),
+            record.binding_id.as_str(),
+            record.service_id.as_str(),
+            record.plan_id.as_str(),
+            to_json(record.parameters.as_ref())?,
+            to_json(record.context.as_ref())?,
+            to_json(record.bind_resource.as_ref())?,
+            to_json(record.credentials.as_ref())?,
+            to_json(record.metadata.as_ref())?,
+            to_timestamp(record.created_at),
+            to_timestamp(record.updated_at),
+            record.orphaned_at.map(to_timestamp),
+            record.syslog_drain_url,
+            record.route_service_url,
+            to_json(record.volume_mounts.as_ref().map(serde_json::to_value).transpose()?.as_ref())?,
+            to_json(record.endpoints.as_ref().map(serde_json::to_value).transpose()?.as_ref())?,
+        ],
+    )?;
+    write_operation(transaction, record.instance_id.as_str(), record.binding_id.as_str(), record.operation.as_ref())
+}
+
+impl InstanceStore for SqliteStore {
+    fn get_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+        self.transaction(|transaction| select_instance(transaction, instance_id))
+    }
+
+    fn put_instance(&self, record: InstanceRecord) -> Result<()> {
+        self.transaction(|transaction| write_instance(transaction, &record))
+    }
+
+    fn update_instance(&self, instance_id: &model::InstanceId, update: &mut dyn FnMut(&mut InstanceRecord)) -> Result<Option<InstanceRecord>> {
+        self.transaction(|transaction| {
+            let mut record = match select_instance(transaction, instance_id)? {
+                Some(record) => record,
+                None         => return Ok(None),
+            };
+            update(&mut record);
+            record.updated_at = SystemTime::now();
+            write_instance(transaction, &record)?;
+            Ok(Some(record))
+        })
+    }
+
+    fn delete_instance(&self, instance_id: &model::InstanceId) -> Result<Option<InstanceRecord>> {
+        self.transaction(|transaction| {
+            let record = select_instance(transaction, instance_id)?;
+            if record.is_some() {
+                transaction.execute("DELETE FROM instances WHERE instance_id = ?1", params![instance_id.as_str()])?;
+                write_operation(transaction, instance_id.as_str(), "", None)?;
+            }
+            Ok(record)
+        })
+    }
+
+    fn list_instances(&self) -> Result<Vec<InstanceRecord>> {
+        self.transaction(|transaction| {
+            let rows = {
+                let mut statement = transaction.prepare(&format!("SELECT {} FROM instances ORDER BY instance_id", INSTANCE_COLUMNS))?;
+                let rows = statement.query_map([], |row| Ok((read_instance_row(row)?, row.get(7)?, row.get(8)?, row.get(9)?)))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            rows.into_iter().map(|row| to_instance(transaction, row)).collect()
+        })
+    }
+}
+
+impl BindingStore for SqliteStore {
+    fn get_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+        self.transaction(|transaction| select_binding(transaction, instance_id, binding_id))
+    }
+
+    fn put_binding(&self, record: BindingRecord) -> Result<()> {
+        self.transaction(|transaction| write_binding(transaction, &record))
+    }
+
+    fn update_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId, update: &mut dyn FnMut(&mut BindingRecord)) -> Result<Option<BindingRecord>> {
+        self.transaction(|transaction| {
+            let mut record = match select_binding(transaction, instance_id, binding_id)? {
+                Some(record) => record,
+                None         => return Ok(None),
+            };
+            update(&mut record);
+            record.updated_at = SystemTime::now();
+            write_binding(transaction, &record)?;
+            Ok(Some(record))
+        })
+    }
+
+    fn delete_binding(&self, instance_id: &model::InstanceId, binding_id: &model::BindingId) -> Result<Option<BindingRecord>> {
+        self.transaction(|transaction| {
+            let record = select_binding(transaction, instance_id, binding_id)?;
+            if record.is_some() {
+                transaction.execute(
+                    "DELETE FROM bindings WHERE instance_id = ?1 AND binding_id = ?2",
+                    params![instance_id.as_str(), binding_id.as_str()],
+                )?;
+                write_operation(transaction, instance_id.as_str(), binding_id.as_str(), None)?;
+            }
+            Ok(record)
+        })
+    }
+
+    fn list_bindings(&self, instance_id: &model::InstanceId) -> Result<Vec<BindingRecord>> {
+        self.transaction(|transaction| {
+            let rows = {
+                let mut statement = transaction.prepare(&format!("SELECT {} FROM bindings WHERE instance_id = ?1 ORDER BY binding_id", BINDING_COLUMNS))?;
+                let rows = statement.query_map(params![instance_id.as_str()], |row| Ok((read_binding_row(row)?, row.get(9)?, row.get(10)?, row.get(11)?)))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            rows.into_iter().map(|row| to_binding(transaction, row)).collect()
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{SqliteStore, MIGRATIONS};
+    use super::super::{model, InstanceStore, BindingStore, InstanceRecord, BindingRecord, Operation, OperationKind};
+    use super::super::tests::check_store;
+
+    #[test]
+    fn store_sqlite_memory() {
+        check_store(&SqliteStore::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn store_sqlite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        check_store(&SqliteStore::open(dir.path().join("store.db")).unwrap());
+    }
+
+    #[test]
+    fn store_sqlite_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.db");
+
+        let mut instance = InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        *instance.operation_mut() = Some(Operation::new(OperationKind::Provision, model::LastOperationState::InProgress));
+        let binding = BindingRecord::new("i1".parse().unwrap(), "b1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+        {
+            let store = SqliteStore::open(&path).unwrap();
+            store.put_instance(instance.clone()).unwrap();
+            store.put_binding(binding.clone()).unwrap();
+        }
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(MIGRATIONS.len(), store.schema_version().unwrap(), "schema_version");
+        assert_eq!(Some(instance), store.get_instance(&"i1".parse().unwrap()).unwrap(), "instance");
+        assert_eq!(Some(binding), store.get_binding(&"i1".parse().unwrap(), &"b1".parse().unwrap()).unwrap(), "binding");
+    }
+
+    #[test]
+    fn store_sqlite_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.db");
+        {
+            let connection = rusqlite::Connection::open(&path).unwrap();
+            connection.execute_batch(MIGRATIONS[0]).unwrap();
+            connection.execute_batch("INSERT INTO instances (instance_id, service_id, plan_id, created_at, updated_at) VALUES ('i1', 'mysql', 'mysql_free', 0, 0)").unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+        }
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(MIGRATIONS.len(), store.schema_version().unwrap(), "schema_version");
+        let instance = store.get_instance(&"i1".parse().unwrap()).unwrap().expect("instance");
+        assert_eq!(None, instance.orphaned_at(), "orphaned_at");
+    }
+
+    #[test]
+    fn store_sqlite_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.db");
+        {
+            let connection = rusqlite::Connection::open(&path).unwrap();
+            connection.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1).unwrap();
+        }
+        assert!(SqliteStore::open(&path).is_err());
+    }
+}
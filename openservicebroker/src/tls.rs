@@ -0,0 +1,306 @@
+//! TLS serving based on rustls, with certificate reloaded on change and optional client
+//! certificate authentication.
+
+use super::server::TlsConfig;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use actix_http::{HttpService, Request, Response};
+use actix_rt::net::TcpStream;
+use actix_server::ServerBuilder;
+use actix_service::{map_config, IntoServiceFactory, ServiceFactory};
+use actix_tls::rustls::TlsStream;
+use actix_web::dev::{AppConfig, MessageBody};
+use anyhow::Result;
+use anyhow::{anyhow, Context};
+use rustls::internal::pemfile;
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, Session};
+use x509_parser::der_parser::asn1_rs::{Any, BmpString, Tag};
+
+/// Subject of the certificate presented by the client of a connection, if any
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientCertificate {
+    subject: Option<String>,
+}
+
+impl ClientCertificate {
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    fn of(io: &TlsStream<TcpStream>) -> Self {
+        let subject = io.get_ref().1
+                        .get_peer_certificates()
+                        .and_then(|certificates| certificates.into_iter().next())
+                        .and_then(|certificate| subject(&certificate.0));
+        ClientCertificate { subject }
+    }
+}
+
+fn read_pem<T>(path: &Path, parse: fn(&mut dyn io::BufRead) -> Result<Vec<T>, ()>) -> Result<Vec<T>> {
+    let content = fs::read(path).with_context(|| format!("Can't read '{}'", path.display()))?;
+    parse(&mut content.as_slice()).map_err(|_| anyhow!("Invalid PEM file '{}'", path.display()))
+}
+
+fn load_certified_key(certificate: &Path, private_key: &Path) -> Result<CertifiedKey> {
+    let chain = read_pem(certificate, pemfile::certs)?;
+    if chain.is_empty() {
+        return Err(anyhow!("No certificate in '{}'", certificate.display()));
+    }
+    let key = read_pem(private_key, pemfile::pkcs8_private_keys)?
+                  .into_iter()
+                  .chain(read_pem(private_key, pemfile::rsa_private_keys)?)
+                  .next()
+                  .ok_or_else(|| anyhow!("No private key in '{}'", private_key.display()))?;
+    let key = rustls::sign::any_supported_type(&key).map_err(|_| anyhow!("Unsupported private key in '{}'", private_key.display()))?;
+    if !matches_certificate(key.as_ref(), &chain[0]) {
+        return Err(anyhow!("Private key '{}' doesn't match certificate '{}'", private_key.display(), certificate.display()));
+    }
+    Ok(CertifiedKey::new(chain, Arc::new(key)))
+}
+
+/// Whether a private key is the one of a certificate, checking that a signature it makes is
+/// verified by the certificate public key
+fn matches_certificate(key: &dyn rustls::sign::SigningKey, certificate: &rustls::Certificate) -> bool {
+    use rustls::SignatureScheme::*;
+    let schemes = [ECDSA_NISTP256_SHA256, ECDSA_NISTP384_SHA384, ED25519, RSA_PSS_SHA256, RSA_PKCS1_SHA256];
+    let signer = match key.choose_scheme(&schemes) {
+        Some(signer) => signer,
+        None         => return false,
+    };
+    let algorithm = match signer.get_scheme() {
+        ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        ED25519               => &webpki::ED25519,
+        RSA_PSS_SHA256        => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+        RSA_PKCS1_SHA256      => &webpki::RSA_PKCS1_2048_8192_SHA256,
+        _                     => return false,
+    };
+    let message = b"openservicebroker certificate key check";
+    match (webpki::EndEntityCert::from(&certificate.0), signer.sign(message)) {
+        (Ok(certificate), Ok(signature)) => certificate.verify_signature(algorithm, message, &signature).is_ok(),
+        _                                => false,
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Server certificate, reloaded once its files have been modified
+pub struct ReloadingCertResolver {
+    certificate: PathBuf,
+    private_key: PathBuf,
+    /// Modification times of the last files tried, successfully or not, and served certificate
+    loaded: Mutex<(Option<SystemTime>, Option<SystemTime>, CertifiedKey)>,
+}
+
+impl ReloadingCertResolver {
+    pub fn open<P: Into<PathBuf>>(certificate: P, private_key: P) -> Result<Self> {
+        let certificate = certificate.into();
+        let private_key = private_key.into();
+        let modifications = (modified(&certificate), modified(&private_key));
+        let key = load_certified_key(&certificate, &private_key)?;
+        Ok(ReloadingCertResolver {
+            certificate,
+            private_key,
+            loaded: Mutex::new((modifications.0, modifications.1, key)),
+        })
+    }
+
+    pub fn certificate(&self) -> &Path {
+        &self.certificate
+    }
+
+    pub fn private_key(&self) -> &Path {
+        &self.private_key
+    }
+
+    /// Currently served certificate, reloading it if its files have changed
+    pub fn current(&self) -> Option<CertifiedKey> {
+        let mut loaded = self.loaded.lock().ok()?;
+        let modifications = (modified(&self.certificate), modified(&self.private_key));
+        if (loaded.0, loaded.1) != modifications {
+            // Files may be written one after the other, the previous certificate is kept
+            // until the new key matches the new certificate. Failures are only retried once
+            // files change again, rather than on each handshake.
+            loaded.0 = modifications.0;
+            loaded.1 = modifications.1;
+            match load_certified_key(&self.certificate, &self.private_key) {
+                Ok(key) => {
+                    tracing::info!("Certificate '{}' has been reloaded", self.certificate.display());
+                    loaded.2 = key;
+                },
+                Err(error) => tracing::warn!(error = %super::trace::ErrorChain(&error), "Can't reload certificate"),
+            }
+        }
+        Some(loaded.2.clone())
+    }
+}
+
+impl rustls::ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _server_name: Option<webpki::DNSNameRef>, _sigschemes: &[rustls::SignatureScheme]) -> Option<CertifiedKey> {
+        self.current()
+    }
+}
+
+/// Builds rustls configuration, checking certificates can be loaded
+pub fn server_config(config: &TlsConfig) -> Result<rustls::ServerConfig> {
+    let verifier = match config.client_ca() {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_pem(path, pemfile::certs)? {
+                roots.add(&certificate).map_err(|error| anyhow!("Invalid client CA '{}': {:?}", path.display(), error))?;
+            }
+            if config.require_client_certificate() {
+                rustls::AllowAnyAuthenticatedClient::new(roots)
+            } else {
+                rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+            }
+        },
+        None => rustls::NoClientAuth::new(),
+    };
+    let mut server_config = rustls::ServerConfig::new(verifier);
+    server_config.cert_resolver = Arc::new(ReloadingCertResolver::open(config.certificate(), config.private_key())?);
+    Ok(server_config)
+}
+
+/// Serves HTTPS on a listener, client certificates being available from request extensions as
+/// `ClientCertificate`
+pub fn listen<F, I, S, B>(builder: ServerBuilder, listener: std::net::TcpListener, factory: F, config: rustls::ServerConfig) -> io::Result<ServerBuilder>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request>,
+    S::Error: Into<actix_web::Error> + 'static,
+    S::InitError: std::fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as actix_service::Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let address = listener.local_addr()?;
+    builder.listen(format!("openservicebroker-tls-{}", address), listener, move || {
+        HttpService::build()
+            .on_connect(ClientCertificate::of)
+            .finish(map_config(factory(), |_| AppConfig::default()))
+            .rustls(config.clone())
+    })
+}
+
+/// RFC 4514 keyword of an attribute type, dotted OID otherwise
+fn attribute_name(oid: &str) -> &str {
+    match oid {
+        "2.5.4.3"                    => "CN",
+        "2.5.4.6"                    => "C",
+        "2.5.4.7"                    => "L",
+        "2.5.4.8"                    => "ST",
+        "2.5.4.10"                   => "O",
+        "2.5.4.11"                   => "OU",
+        "0.9.2342.19200300.100.1.25" => "DC",
+        "0.9.2342.19200300.100.1.1"  => "UID",
+        _                            => oid,
+    }
+}
+
+/// Attribute value as a string, if it is of a string type
+fn attribute_value(value: &Any) -> Option<String> {
+    match value.tag() {
+        Tag::BmpString => BmpString::try_from(value.clone()).ok().map(|value| value.string()),
+        Tag::NumericString | Tag::PrintableString | Tag::Utf8String | Tag::Ia5String |
+        Tag::VisibleString | Tag::TeletexString | Tag::GeneralString => std::str::from_utf8(value.data).ok().map(str::to_owned),
+        _ => None,
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if ",+\"\\<>;=".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+/// Subject distinguished name of a DER certificate, in RFC 4514 form (e.g.
+/// `CN=cloudfoundry,O=Example`)
+pub fn subject(certificate: &[u8]) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    let mut attributes = certificate.subject().iter().map(|rdn| {
+        rdn.iter()
+           .map(|attribute| {
+               let oid = attribute.attr_type().to_id_string();
+               let value = attribute_value(attribute.attr_value())?;
+               Some(format!("{}={}", attribute_name(&oid), escape(&value)))
+           })
+           .collect::<Option<Vec<_>>>()
+           .map(|values| values.join("+"))
+    }).collect::<Option<Vec<_>>>()?;
+    attributes.reverse();
+    Some(attributes.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attribute_name, load_certified_key, read_pem, subject, ReloadingCertResolver};
+    use rustls::internal::pemfile;
+    use std::path::Path;
+
+    #[test]
+    fn tls_subject() {
+        let client = read_pem(Path::new("tests/tls/client.pem"), pemfile::certs).unwrap();
+        assert_eq!(Some("CN=cloudfoundry,OU=Platforms,O=Example"), subject(&client[0].0).as_deref(), "client");
+        let server = read_pem(Path::new("tests/tls/server.pem"), pemfile::certs).unwrap();
+        assert_eq!(Some("CN=localhost"), subject(&server[0].0).as_deref(), "server");
+        assert_eq!(None, subject(&client[0].0[..20]), "truncated");
+        assert_eq!("OU", attribute_name("2.5.4.11"), "organizationalUnitName");
+        assert_eq!("1.2.840.113549.1.9.1", attribute_name("1.2.840.113549.1.9.1"), "emailAddress");
+    }
+
+    #[test]
+    fn tls_reloading_cert_resolver() {
+        let root = tempfile::tempdir().unwrap();
+        let certificate = root.path().join("server.pem");
+        let private_key = root.path().join("server-key.pem");
+        std::fs::copy("tests/tls/server.pem", &certificate).unwrap();
+        std::fs::copy("tests/tls/server-key.pem", &private_key).unwrap();
+        let resolver = ReloadingCertResolver::open(&certificate, &private_key).unwrap();
+        let served = |resolver: &ReloadingCertResolver| subject(&resolver.current().unwrap().cert[0].0);
+        assert_eq!(Some("CN=localhost"), served(&resolver).as_deref(), "initial");
+
+        // Invalid files keep previous certificate
+        std::fs::write(&certificate, "garbage").unwrap();
+        assert_eq!(Some("CN=localhost"), served(&resolver).as_deref(), "invalid");
+
+        std::fs::copy("tests/tls/server-renewed.pem", &certificate).unwrap();
+        std::fs::copy("tests/tls/server-renewed-key.pem", &private_key).unwrap();
+        // Ensures modification is seen on filesystems with coarse timestamps
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::OpenOptions::new().write(true).open(&certificate).unwrap().set_modified(later).unwrap();
+        assert_eq!(Some("CN=localhost,O=Renewed"), served(&resolver).as_deref(), "renewed");
+
+        // Certificate written without its key keeps previous pair
+        std::fs::copy("tests/tls/server.pem", &certificate).unwrap();
+        let later = later + std::time::Duration::from_secs(5);
+        std::fs::OpenOptions::new().write(true).open(&certificate).unwrap().set_modified(later).unwrap();
+        assert_eq!(Some("CN=localhost,O=Renewed"), served(&resolver).as_deref(), "mismatched");
+
+        // Failed files aren't loaded again until they change
+        let failed = std::fs::metadata(&private_key).unwrap().modified().unwrap();
+        std::fs::copy("tests/tls/server-key.pem", &private_key).unwrap();
+        std::fs::OpenOptions::new().write(true).open(&private_key).unwrap().set_modified(failed).unwrap();
+        assert_eq!(Some("CN=localhost,O=Renewed"), served(&resolver).as_deref(), "unchanged");
+        std::fs::OpenOptions::new().write(true).open(&private_key).unwrap().set_modified(later).unwrap();
+        assert_eq!(Some("CN=localhost"), served(&resolver).as_deref(), "fixed");
+
+        assert!(load_certified_key(Path::new("tests/tls/server.pem"), Path::new("tests/tls/ca.pem")).is_err(), "missing key");
+        assert!(load_certified_key(Path::new("tests/tls/server.pem"), Path::new("tests/tls/server-renewed-key.pem")).is_err(), "mismatched key");
+    }
+}
@@ -0,0 +1,136 @@
+//! Structured request logging.
+//!
+//! `instrument` opens a `request` span around each request, carrying the broker API request
+//! identity, the instance and binding IDs, the service and plan, and the originating platform
+//! and user. Events logged while handling the request, including those of the handlers, are
+//! reported within this span.
+
+use super::model;
+use super::server::ResponseFuture;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+use actix_web::web;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use serde_json::Value;
+use tracing::Instrument;
+use tracing::field::{display, Empty};
+
+/// Displays an error with its causes, `outer: inner: root`
+pub struct ErrorChain<'a>(pub &'a anyhow::Error);
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+/// Instance and binding IDs of a broker API path, whatever its prefix
+fn path_ids(path: &str) -> (Option<&str>, Option<&str>) {
+    let mut segments = path.split('/').skip_while(|&segment| segment != "service_instances").skip(1);
+    let instance_id = segments.next().filter(|id| !id.is_empty());
+    let binding_id = match segments.next() {
+        Some("service_bindings") => segments.next().filter(|id| !id.is_empty()),
+        _                        => None,
+    };
+    (instance_id, binding_id)
+}
+
+/// Platform and user of an `X-Broker-API-Originating-Identity` header
+fn originating_identity(header: &str) -> Option<(&str, Option<String>)> {
+    let (platform, encoded) = header.trim().split_once(' ')?;
+    let user = base64::decode(encoded.trim()).ok()
+                    .and_then(|decoded| serde_json::from_slice::<Value>(&decoded).ok())
+                    .and_then(|identity| identity.get("user_id").or_else(|| identity.get("username")).and_then(Value::as_str).map(str::to_owned));
+    Some((platform, user))
+}
+
+/// Span of a request, with the fields known before it is handled
+fn request_span(req: &ServiceRequest) -> tracing::Span {
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let span = tracing::info_span!("request",
+        method = %req.method(),
+        path = req.path(),
+        request_identity = header(model::REQUEST_IDENTITY_HEADER),
+        instance_id = Empty,
+        binding_id = Empty,
+        service_id = Empty,
+        plan_id = Empty,
+        platform = Empty,
+        user = Empty,
+    );
+    let (instance_id, binding_id) = path_ids(req.path());
+    span.record("instance_id", instance_id);
+    span.record("binding_id", binding_id);
+    // Deprovisioning and unbinding identify the plan in the query
+    if let Ok(query) = web::Query::<HashMap<String, String>>::from_query(req.query_string()) {
+        span.record("service_id", query.get("service_id").map(String::as_str));
+        span.record("plan_id", query.get("plan_id").map(String::as_str));
+    }
+    if let Some((platform, user)) = header(model::ORIGINATING_IDENTITY_HEADER).and_then(originating_identity) {
+        span.record("platform", platform);
+        span.record("user", user);
+    }
+    span
+}
+
+/// Records the plan of the current request, as found in its body
+pub(crate) fn record_plan(service_id: &model::ServiceId, plan_id: Option<&model::PlanId>) {
+    let span = tracing::Span::current();
+    span.record("service_id", display(service_id));
+    if let Some(plan_id) = plan_id {
+        span.record("plan_id", display(plan_id));
+    }
+}
+
+/// Middleware logging requests within their span, to be used with `App::wrap_fn`
+pub fn instrument<S>(req: ServiceRequest, srv: &mut S) -> ResponseFuture
+where S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+      S::Future: 'static {
+    let span = request_span(&req);
+    let started = Instant::now();
+    let response = span.in_scope(|| srv.call(req));
+    Box::pin(async move {
+        let response = response.await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &response {
+            Ok(response) if response.status().is_server_error() => tracing::error!(status = response.status().as_u16(), elapsed_ms, "Request has failed"),
+            Ok(response)                                        => tracing::info!(status = response.status().as_u16(), elapsed_ms, "Request completed"),
+            Err(error)                                          => tracing::error!(error = %error, elapsed_ms, "Request has failed"),
+        }
+        response
+    }.instrument(span))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{originating_identity, path_ids, ErrorChain};
+    use anyhow::Context;
+
+    #[test]
+    fn trace_path_ids() {
+        assert_eq!((Some("i1"), None), path_ids("/v2/service_instances/i1"));
+        assert_eq!((Some("i1"), None), path_ids("/broker/v2/service_instances/i1/last_operation"));
+        assert_eq!((Some("i1"), Some("b1")), path_ids("/v2/service_instances/i1/service_bindings/b1"));
+        assert_eq!((None, None), path_ids("/v2/catalog"));
+    }
+
+    #[test]
+    fn trace_originating_identity() {
+        let header = format!("cloudfoundry {}", base64::encode(r#"{ "user_id": "683ea748-3092-4ff4-b656-39cacc4d5360" }"#));
+        assert_eq!(Some(("cloudfoundry", Some("683ea748-3092-4ff4-b656-39cacc4d5360".to_owned()))), originating_identity(&header));
+        let header = format!("kubernetes {}", base64::encode(r#"{ "username": "duke", "uid": "c2dde242" }"#));
+        assert_eq!(Some(("kubernetes", Some("duke".to_owned()))), originating_identity(&header));
+        assert_eq!(Some(("kubernetes", None)), originating_identity("kubernetes invalid"));
+        assert_eq!(None, originating_identity("kubernetes"));
+    }
+
+    #[test]
+    fn trace_error_chain() {
+        let error = Err::<(), _>(anyhow::anyhow!("Disk full")).context("Can't write instance").unwrap_err();
+        assert_eq!("Can't write instance: Disk full", ErrorChain(&error).to_string());
+    }
+}
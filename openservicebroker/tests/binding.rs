@@ -0,0 +1,224 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::{model, store};
+use osb::orphans::OrphanCleanup;
+use common::TestBackend;
+
+use std::time::{Duration, SystemTime};
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::json;
+
+#[actix_rt::test]
+async fn bind() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Missing instance] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Create] status");
+    assert_eq!(json!({ "credentials": { "username": "b1" } }), response, "[Create] body");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Identical] status");
+    assert_eq!(json!({ "credentials": { "username": "b1" } }), response, "[Identical] body");
+
+    let other = json!({ "service_id": "mysql", "plan_id": "mysql_free", "parameters": { "read_only": true } });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&other).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CONFLICT, status, "[Conflict] status");
+
+    assert_eq!(2, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn bind_other_plan() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&json!({ "service_id": "pgsql", "plan_id": "pgsql_free" })).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&json!({ "service_id": "mysql", "plan_id": "mysql_free" })).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::BAD_REQUEST, status, "[Other service] status");
+    assert_eq!(json!("Instance 'i1' is of plan 'pgsql_free' of service 'pgsql'"), response["description"], "[Other service] description");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&json!({ "service_id": "pgsql", "plan_id": "pgsql_small" })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::BAD_REQUEST, status, "[Other plan] status");
+
+    assert_eq!(1, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn bind_unusable_instance() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let mut orphan = store::InstanceRecord::new("i1".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+    *orphan.orphaned_at_mut() = Some(SystemTime::now());
+    broker.store().put_instance(orphan).unwrap();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Orphan] status");
+    assert_eq!(json!("Instance 'i1' provisioning outcome is unknown"), response["description"], "[Orphan] description");
+
+    let mut failed = store::InstanceRecord::new("i2".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+    *failed.operation_mut() = Some(store::Operation::new(store::OperationKind::Provision, model::LastOperationState::Failed));
+    broker.store().put_instance(failed).unwrap();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Failed] status");
+    assert_eq!(json!("Instance 'i2' provisioning has failed"), response["description"], "[Failed] description");
+
+    assert_eq!(0, backend.calls(), "backend.calls");
+    assert!(broker.store().list_bindings(&"i1".parse().unwrap()).unwrap().is_empty(), "bindings");
+}
+
+#[actix_rt::test]
+async fn unbind() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1/service_bindings/b1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Delete] status");
+    assert_eq!(json!({}), response, "[Delete] body");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1/service_bindings/b1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::GONE, status, "[Gone] status");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/service_bindings/b1/last_operation").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::GONE, status, "[Last operation] status");
+}
+
+#[actix_rt::test]
+async fn bind_shared() {
+    let backend = TestBackend::sync();
+    let catalog = common::catalog_with(|catalog| {
+        let mysql = catalog.services_mut().iter_mut().find(|service| service.id() == "mysql").unwrap();
+        *mysql.shareable_mut() = Some(true);
+    });
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", catalog, common::broker(backend.clone())))
+    ).await;
+    let in_space = |space: &str, service_id: &str, plan_id: &str| json!({
+        "service_id": service_id,
+        "plan_id": plan_id,
+        "context": { "platform": "cloudfoundry", "space_guid": space },
+    });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&in_space("dev", "mysql", "mysql_free")).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2").set_json(&in_space("dev", "pgsql", "pgsql_free")).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&in_space("dev", "mysql", "mysql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Same space] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b2").set_json(&in_space("prod", "mysql", "mysql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Shareable] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2/service_bindings/b1").set_json(&in_space("prod", "pgsql", "pgsql_free")).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Not shareable] status");
+    assert_eq!(json!("Instance 'i2' can't be shared with space 'prod'"), response["description"], "[Not shareable] description");
+
+    // Missing space is another one
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2/service_bindings/b2").set_json(&json!({ "service_id": "pgsql", "plan_id": "pgsql_free" })).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[No space] status");
+    assert_eq!(json!("Instance 'i2' can't be bound outside of its space"), response["description"], "[No space] description");
+    let req = test::TestRequest::put().uri("/v2/service_instances/i3").set_json(&json!({ "service_id": "pgsql", "plan_id": "pgsql_free" })).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i3/service_bindings/b1").set_json(&in_space("dev", "pgsql", "pgsql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Instance without space] status");
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b3").set_json(&json!({ "service_id": "mysql", "plan_id": "mysql_free" })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Shareable without space] status");
+
+    assert_eq!(6, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn bind_requires() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let catalog = common::catalog_with(|catalog| {
+        let mysql = catalog.services_mut().iter_mut().find(|service| service.id() == "mysql").unwrap();
+        mysql.requires_mut().push(osb::model::REQUIRES_SYSLOG_DRAIN.to_owned());
+    });
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", catalog, broker.clone()))
+    ).await;
+    let with_drain = |service_id: &str, plan_id: &str| json!({
+        "service_id": service_id,
+        "plan_id": plan_id,
+        "parameters": { "syslog_drain_url": "syslog://logs:514" },
+    });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&with_drain("mysql", "mysql_free")).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2").set_json(&with_drain("pgsql", "pgsql_free")).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&with_drain("mysql", "mysql_free")).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Declared] status");
+    assert_eq!(json!("syslog://logs:514"), response["syslog_drain_url"], "[Declared] syslog_drain_url");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&with_drain("mysql", "mysql_free")).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Identical] status");
+    assert_eq!(json!("syslog://logs:514"), response["syslog_drain_url"], "[Identical] syslog_drain_url");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2/service_bindings/b1").set_json(&with_drain("pgsql", "pgsql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status, "[Undeclared] status");
+    let orphan = broker.store().get_binding(&"i2".parse().unwrap(), &"b1".parse().unwrap()).unwrap().expect("[Undeclared] orphan");
+    assert!(orphan.orphaned_at().is_some(), "[Undeclared] orphaned_at");
+
+    let mut cleanup = OrphanCleanup::new(broker.clone());
+    *cleanup.grace_period_mut() = Duration::from_secs(0);
+    let report = cleanup.run().await.unwrap();
+    assert_eq!(1, report.bindings().len(), "[Cleanup] bindings: {}", report);
+    assert!(broker.store().get_binding(&"i2".parse().unwrap(), &"b1".parse().unwrap()).unwrap().is_none(), "[Cleanup] binding");
+}
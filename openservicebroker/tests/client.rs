@@ -0,0 +1,103 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::client::{Client, ClientError};
+use osb::model;
+use osb::service::{Completion, ServiceError};
+use common::TestBackend;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use serde_json::json;
+
+#[actix_rt::test]
+async fn client_lifecycle() {
+    let server = test::start(|| {
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(TestBackend::asynchronous())))
+    });
+    let mut client = Client::new(format!("http://{}", server.addr()));
+    *client.poll_interval_mut() = Duration::from_millis(10);
+    let instance_id: model::InstanceId = "i1".parse().unwrap();
+    let binding_id: model::BindingId = "b1".parse().unwrap();
+
+    let catalog = client.catalog().await.unwrap();
+    assert_eq!(2, catalog.services().len(), "[Catalog] services");
+
+    let request = model::ProvisionRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+    let operation = match client.provision(&instance_id, &request, true).await.unwrap() {
+        Completion::Async(response) => response.operation().map(str::to_owned),
+        Completion::Sync(_)         => panic!("[Provision] MUST be asynchronous"),
+    };
+    let last = client.wait_instance(&instance_id, operation.as_deref()).await.unwrap();
+    assert_eq!(model::LastOperationState::Succeeded, last.state(), "[Provision] state");
+
+    let request = model::BindRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+    let response = client.bind(&instance_id, &binding_id, &request, true).await.unwrap();
+    assert!(response.is_async(), "[Bind] is_async");
+    client.wait_binding(&instance_id, &binding_id, None).await.unwrap();
+
+    let error = client.bind(&"i2".parse().unwrap(), &binding_id, &request, true).await.expect_err("[Bind unknown] MUST fail");
+    assert!(matches!(error.downcast_ref(), Some(ServiceError::NotFound(_))), "[Bind unknown] error: {}", error);
+
+    let request = model::ProvisionRequest::new("mysql".parse().unwrap(), "mysql_free".parse().unwrap());
+    let response = client.provision(&instance_id, &request, true).await.unwrap();
+    assert!(!response.is_async(), "[Provision again] is_async");
+    let error = client.provision(&"i2".parse().unwrap(), &request, false).await.expect_err("[Provision sync] MUST fail");
+    assert_eq!(Some(&ServiceError::AsyncRequired), error.downcast_ref(), "[Provision sync] error");
+
+    let response = client.deprovision(&instance_id, &"mysql".parse().unwrap(), &"mysql_free".parse().unwrap(), true).await.unwrap();
+    let last = client.wait_deprovision(&instance_id, response.into_inner().operation()).await.unwrap();
+    assert_eq!(model::LastOperationState::Succeeded, last.state(), "[Deprovision] state");
+    let error = client.wait_instance(&instance_id, None).await.expect_err("[Wait deprovisioned] MUST fail");
+    assert_eq!(Some(&ServiceError::Gone), error.downcast_ref(), "[Wait deprovisioned] error");
+    let error = client.fetch_instance(&instance_id).await.expect_err("[Fetch deprovisioned] MUST fail");
+    assert!(error.downcast_ref::<ServiceError>().is_some(), "[Fetch deprovisioned] error: {}", error);
+}
+
+#[actix_rt::test]
+async fn client_polling() {
+    let polls = Arc::new(AtomicUsize::new(0));
+    let server_polls = polls.clone();
+    let server = test::start(move || {
+        let polls = server_polls.clone();
+        App::new()
+            .route("/v2/service_instances/i1/last_operation", web::get().to(move |req: HttpRequest| {
+                let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+                assert_eq!(Some("2.16"), header("X-Broker-API-Version"), "X-Broker-API-Version");
+                assert_eq!(Some("test eyJ1c2VyIjoiYWRtaW4ifQ=="), header("X-Broker-API-Originating-Identity"), "X-Broker-API-Originating-Identity");
+                assert_eq!(Some("Basic YWRtaW46c2VjcmV0"), header("Authorization"), "Authorization");
+                match polls.fetch_add(1, Ordering::SeqCst) {
+                    0 => HttpResponse::Ok().header("Retry-After", "0").json(json!({ "state": "in progress" })),
+                    1 => HttpResponse::Ok().json(json!({ "state": "in progress" })),
+                    _ => HttpResponse::Ok().json(json!({ "state": "failed", "description": "out of space" })),
+                }
+            }))
+            .route("/v2/service_instances/i2/last_operation", web::get().to(|| {
+                HttpResponse::Ok().json(json!({ "state": "in progress" }))
+            }))
+            .route("/v2/catalog", web::get().to(|| HttpResponse::ServiceUnavailable().body("maintenance")))
+    });
+    let mut client = Client::new(format!("http://{}", server.addr()));
+    *client.basic_auth_mut() = Some(("admin".to_owned(), "secret".to_owned()));
+    *client.originating_identity_mut() = Some(("test".to_owned(), json!({ "user": "admin" })));
+    *client.poll_interval_mut() = Duration::from_millis(10);
+
+    let last = client.wait_instance(&"i1".parse().unwrap(), None).await.unwrap();
+    assert_eq!(model::LastOperationState::Failed, last.state(), "[Polling] state");
+    assert_eq!(Some("out of space"), last.description(), "[Polling] description");
+    assert_eq!(3, polls.load(Ordering::SeqCst), "[Polling] polls");
+
+    *client.poll_timeout_mut() = Duration::from_millis(50);
+    let error = client.wait_instance(&"i2".parse().unwrap(), None).await.expect_err("[Timeout] MUST fail");
+    assert_eq!(Some(&ClientError::Timeout(Duration::from_millis(50))), error.downcast_ref(), "[Timeout] error");
+
+    let error = match client.catalog().await {
+        Ok(_)      => panic!("[Unexpected] MUST fail"),
+        Err(error) => error,
+    };
+    assert!(matches!(error.downcast_ref(), Some(ClientError::UnexpectedStatus(status, body)) if status.as_u16() == 503 && body == "maintenance"), "[Unexpected] error: {}", error);
+}
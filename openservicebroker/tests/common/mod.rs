@@ -0,0 +1,170 @@
+use openservicebroker as osb;
+use osb::{model, service, store};
+use osb::service::Completion;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use actix_web::{test, http::StatusCode, dev::{MessageBody, Service, ServiceResponse}};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use serde_json::{json, Value};
+
+/// Backend recording calls, completing operations synchronously or asynchronously
+#[derive(Default)]
+pub struct TestBackend {
+    asynchronous: bool,
+    calls: AtomicUsize,
+    failing: AtomicBool,
+    gate: Mutex<Option<oneshot::Receiver<()>>>,
+}
+
+#[allow(dead_code)]
+impl TestBackend {
+    pub fn sync() -> Arc<Self> {
+        Arc::new(TestBackend::default())
+    }
+
+    pub fn asynchronous() -> Arc<Self> {
+        Arc::new(TestBackend {
+            asynchronous: true,
+            ..TestBackend::default()
+        })
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Makes next provisioning or binding wait until the returned sender is used (or dropped)
+    pub fn hold(&self) -> oneshot::Sender<()> {
+        let (sender, receiver) = oneshot::channel();
+        *self.gate.lock().unwrap() = Some(receiver);
+        sender
+    }
+
+    /// Makes next provisioning or binding fail with an unexpected error
+    pub fn fail_next(&self) {
+        self.failing.store(true, Ordering::SeqCst);
+    }
+
+    fn check_failure(&self) -> Result<()> {
+        if self.failing.swap(false, Ordering::SeqCst) {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            return Err(anyhow!("Backend failure"));
+        }
+        Ok(())
+    }
+
+    async fn wait(&self) {
+        let gate = self.gate.lock().unwrap().take();
+        if let Some(gate) = gate {
+            let _ = gate.await;
+        }
+    }
+
+    fn complete<T>(&self, value: T) -> Completion<T> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if self.asynchronous {
+            Completion::Async(value)
+        } else {
+            Completion::Sync(value)
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl service::InstanceProvider for TestBackend {
+    async fn provision(&self, instance_id: &model::InstanceId, _request: &model::ProvisionRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        self.wait().await;
+        self.check_failure()?;
+        let mut response = model::ProvisionResponse::new();
+        *response.dashboard_url_mut() = Some(format!("http://dashboard/{}", instance_id));
+        if self.asynchronous {
+            *response.operation_mut() = Some("provision".to_owned());
+        }
+        Ok(self.complete(response))
+    }
+
+    async fn update(&self, _instance: &store::InstanceRecord, _request: &model::UpdateRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        Ok(self.complete(model::ProvisionResponse::new()))
+    }
+
+    async fn deprovision(&self, _instance: &store::InstanceRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        Ok(self.complete(model::OperationResponse::new()))
+    }
+
+    async fn last_operation(&self, _instance: &store::InstanceRecord, _operation: Option<&str>) -> Result<model::LastOperation> {
+        Ok(model::LastOperation::new(model::LastOperationState::Succeeded))
+    }
+}
+
+#[async_trait(?Send)]
+impl service::BindingProvider for TestBackend {
+    async fn bind(&self, _instance: &store::InstanceRecord, binding_id: &model::BindingId, request: &model::BindRequest, _accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        self.wait().await;
+        self.check_failure()?;
+        let mut response = model::BindingResponse::new();
+        *response.credentials_mut() = Some(json!({ "username": binding_id.as_str() }));
+        // Echoes requested syslog drain and endpoints
+        *response.syslog_drain_url_mut() = request.parameters()
+                                                  .and_then(|parameters| parameters.get("syslog_drain_url"))
+                                                  .and_then(Value::as_str)
+                                                  .map(str::to_owned);
+        *response.endpoints_mut() = request.parameters()
+                                           .and_then(|parameters| parameters.get("endpoints"))
+                                           .map(|endpoints| serde_json::from_value(endpoints.clone()))
+                                           .transpose()?;
+        Ok(self.complete(response))
+    }
+
+    async fn unbind(&self, _instance: &store::InstanceRecord, _binding: &store::BindingRecord, _accepts_incomplete: bool) -> Result<Completion<model::OperationResponse>> {
+        Ok(self.complete(model::OperationResponse::new()))
+    }
+
+    async fn last_binding_operation(&self, _instance: &store::InstanceRecord, _binding: &store::BindingRecord, _operation: Option<&str>) -> Result<model::LastOperation> {
+        Ok(model::LastOperation::new(model::LastOperationState::Succeeded))
+    }
+}
+
+#[allow(dead_code)]
+pub fn broker(backend: Arc<TestBackend>) -> osb::broker::Broker {
+    osb::broker::Broker::new(backend.clone(), backend)
+}
+
+pub fn catalog() -> Box<dyn service::CatalogProvider> {
+    Box::new(osb::service::JsonFileCatalogProvider::new("tests/default_catalog.json"))
+}
+
+/// Default catalog, customized for a test
+#[allow(dead_code)]
+pub fn catalog_with<F: FnOnce(&mut model::Catalog)>(customize: F) -> Box<dyn service::CatalogProvider> {
+    let mut catalog = catalog().get_catalog().expect("Default catalog MUST be loaded").into_owned();
+    customize(&mut catalog);
+    Box::new(osb::service::SingleCatalogProvider::new(catalog))
+}
+
+/// Calls a service, returning response status and JSON body (`null` if empty)
+#[allow(dead_code)]
+pub async fn call<S, R, B, E>(app: &mut S, request: R) -> (StatusCode, Value)
+where
+    S: Service<Request = R, Response = ServiceResponse<B>, Error = E>,
+    E: std::fmt::Debug,
+    B: MessageBody,
+{
+    read(test::call_service(app, request).await).await
+}
+
+/// Reads response status and JSON body (`null` if empty)
+#[allow(dead_code)]
+pub async fn read<B: MessageBody>(response: ServiceResponse<B>) -> (StatusCode, Value) {
+    let status = response.status();
+    let body = test::read_body(response).await;
+    let json = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body).expect("Response body MUST be JSON")
+    };
+    (status, json)
+}
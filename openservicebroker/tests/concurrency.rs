@@ -0,0 +1,119 @@
+mod common;
+
+use openservicebroker as osb;
+use common::TestBackend;
+
+use actix_web::{test, App, dev::Service, http::StatusCode};
+use serde_json::json;
+
+#[actix_rt::test]
+async fn concurrent_provision() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let gate = backend.hold();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let mut first = Box::pin(app.call(req));
+    assert!(futures::poll!(&mut first).is_pending(), "[First] waits for backend");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Retry] status");
+    assert_eq!(json!("ConcurrencyError"), response["error"], "[Retry] error");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Deprovision] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Other instance] status");
+
+    gate.send(()).unwrap();
+    let (status, _) = common::read(first.await.unwrap()).await;
+    assert_eq!(StatusCode::CREATED, status, "[First] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[After] status");
+
+    assert_eq!(2, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn concurrent_bind() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let gate = backend.hold();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let mut first = Box::pin(app.call(req));
+    assert!(futures::poll!(&mut first).is_pending(), "[First] waits for backend");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Retry] status");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Deprovision] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b2").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Other binding] status");
+
+    gate.send(()).unwrap();
+    let (status, _) = common::read(first.await.unwrap()).await;
+    assert_eq!(StatusCode::CREATED, status, "[First] status");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[After] status");
+}
+
+#[actix_rt::test]
+async fn operation_in_progress() {
+    let backend = TestBackend::asynchronous();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::ACCEPTED, status, "[Provision] status");
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Update] status");
+    assert_eq!(json!("ConcurrencyError"), response["error"], "[Update] error");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1?accepts_incomplete=true").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Bind] status");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/last_operation").to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free&accepts_incomplete=true").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::ACCEPTED, status, "[Deprovision] status");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free&accepts_incomplete=true").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::ACCEPTED, status, "[Deprovision retry] status");
+
+    assert_eq!(2, backend.calls(), "backend.calls");
+}
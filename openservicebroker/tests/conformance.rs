@@ -0,0 +1,107 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::backends::directory::DirectoryBackend;
+use osb::client::Client;
+use osb::conformance::{Conformance, Level, Outcome};
+use common::TestBackend;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use actix_web::{test, web, App, HttpResponse};
+use serde_json::json;
+
+fn conformance(server: &test::TestServer) -> Conformance {
+    let mut client = Client::new(format!("http://{}", server.addr()));
+    *client.poll_interval_mut() = Duration::from_millis(10);
+    Conformance::new(client)
+}
+
+#[actix_rt::test]
+async fn conformance_sync() {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().to_owned();
+    let server = test::start(move || {
+        App::new()
+            // Only required after an asynchronous operation
+            .route("/v2/service_instances/{instance_id}/last_operation", web::get().to(HttpResponse::NotFound))
+            .service(osb::new_service_broker_scope("", common::catalog(), DirectoryBackend::open(&path).unwrap()))
+    });
+    let report = conformance(&server).run().await;
+    assert!(report.is_success(), "{}", report);
+    assert!(matches!(report.find("last_operation").map(|result| result.outcome()), Some(Outcome::Skipped(_))), "{}", report);
+    assert_eq!(Some(&Outcome::Passed), report.find("provision idempotency").map(|result| result.outcome()), "{}", report);
+    assert_eq!(Some(&Outcome::Passed), report.find("unbind missing").map(|result| result.outcome()), "{}", report);
+    assert_eq!(Some(&Outcome::Passed), report.find("bind missing instance").map(|result| result.outcome()), "{}", report);
+    assert!(matches!(report.find("bind non-bindable plan").map(|result| result.outcome()), Some(Outcome::Skipped(_))), "{}", report);
+    assert!(matches!(report.find("authentication").map(|result| result.outcome()), Some(Outcome::Skipped(_))), "{}", report);
+}
+
+#[actix_rt::test]
+async fn conformance_async() {
+    let broker = common::broker(TestBackend::asynchronous());
+    let server = test::start(move || {
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    });
+    let report = conformance(&server).run().await;
+    assert!(report.is_success(), "{}", report);
+    let version = report.find("version header").expect("version header scenario");
+    assert_eq!(Level::Should, version.level(), "version header level");
+    assert_eq!(Some(&Outcome::Passed), report.find("last_operation").map(|result| result.outcome()), "{}", report);
+}
+
+#[actix_rt::test]
+async fn conformance_not_bindable() {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().to_owned();
+    let server = test::start(move || {
+        let catalog = common::catalog_with(|catalog| *catalog.services_mut()[1].plans_mut()[1].bindable_mut() = Some(false));
+        App::new()
+            .service(osb::new_service_broker_scope("", catalog, DirectoryBackend::open(&path).unwrap()))
+    });
+    let report = conformance(&server).run().await;
+    assert!(report.is_success(), "{}", report);
+    assert_eq!(Some(&Outcome::Passed), report.find("bind non-bindable plan").map(|result| result.outcome()), "{}", report);
+}
+
+#[actix_rt::test]
+async fn conformance_last_operation() {
+    let polls = Arc::new(AtomicUsize::new(0));
+    let server_polls = polls.clone();
+    let server = test::start(move || {
+        let polls = server_polls.clone();
+        App::new()
+            .route("/v2/service_instances/{instance_id}", web::put().to(|| HttpResponse::Accepted().json(json!({ "operation": "op" }))))
+            .route("/v2/service_instances/{instance_id}/last_operation", web::get().to(move || {
+                // Succeeded operation going back in progress
+                match polls.fetch_add(1, Ordering::SeqCst) {
+                    0 => HttpResponse::Ok().json(json!({ "state": "in progress" })),
+                    1 => HttpResponse::Ok().json(json!({ "state": "succeeded" })),
+                    _ => HttpResponse::Ok().json(json!({ "state": "in progress" })),
+                }
+            }))
+            .service(osb::new_scope("", common::catalog()))
+    });
+    let report = conformance(&server).run().await;
+    assert!(!report.is_success(), "{}", report);
+    assert_eq!(Some(&Outcome::Passed), report.find("provision").map(|result| result.outcome()), "{}", report);
+    assert!(matches!(report.find("last_operation").map(|result| result.outcome()), Some(Outcome::Failed(reason)) if reason.contains("then reported as 'InProgress'")), "{}", report);
+    assert!(matches!(report.find("bind").map(|result| result.outcome()), Some(Outcome::Skipped(_))), "{}", report);
+    assert_eq!(3, polls.load(Ordering::SeqCst), "polls");
+}
+
+#[actix_rt::test]
+async fn conformance_failure() {
+    let server = test::start(|| {
+        App::new()
+            .route("/v2/service_instances/{instance_id}", web::put().to(HttpResponse::InternalServerError))
+            .service(osb::new_scope("", common::catalog()))
+    });
+    let report = conformance(&server).run().await;
+    assert!(!report.is_success(), "{}", report);
+    assert!(matches!(report.find("provision").map(|result| result.outcome()), Some(Outcome::Failed(reason)) if reason.contains("500")), "{}", report);
+    assert!(matches!(report.find("deprovision").map(|result| result.outcome()), Some(Outcome::Skipped(_))), "{}", report);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::backends::directory::DirectoryBackend;
+
+use std::path::Path;
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::json;
+
+#[actix_rt::test]
+async fn directory() {
+    let root = tempfile::tempdir().unwrap();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_service_broker_scope("", common::catalog(), DirectoryBackend::open(root.path()).unwrap()))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Provision] status");
+    assert!(root.path().join("i1/data").is_dir(), "[Provision] data directory");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Bind] status");
+    let credentials = &response["credentials"];
+    assert_eq!(json!(root.path().join("i1/data")), credentials["path"], "[Bind] path");
+    let token_file = credentials["token_file"].as_str().expect("[Bind] token_file");
+    assert_eq!(credentials["token"].as_str(), std::fs::read_to_string(token_file).ok().as_deref(), "[Bind] token");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(0o600, std::fs::metadata(token_file).unwrap().permissions().mode() & 0o777, "[Bind] token_file mode");
+    }
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b2").set_json(&body).to_request();
+    let (_, response) = common::call(&mut app, req).await;
+    assert_ne!(credentials["token"], response["credentials"]["token"], "[Bind] distinct tokens");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1/service_bindings/b1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Unbind] status");
+    assert!(!Path::new(token_file).exists(), "[Unbind] token_file");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Deprovision] status");
+    assert!(!root.path().join("i1").exists(), "[Deprovision] directory");
+}
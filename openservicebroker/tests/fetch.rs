@@ -0,0 +1,154 @@
+mod common;
+
+use openservicebroker as osb;
+use common::TestBackend;
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::json;
+
+fn retrievable(catalog: &mut osb::model::Catalog) {
+    for service in catalog.services_mut().iter_mut().filter(|service| service.id() == "mysql") {
+        *service.instances_retrievable_mut() = Some(true);
+        *service.bindings_retrievable_mut() = Some(true);
+    }
+}
+
+#[actix_rt::test]
+async fn fetch_instance() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog_with(retrievable), common::broker(TestBackend::sync())))
+    ).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Missing] status");
+
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free", "parameters": { "size": 1 } });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Found] status");
+    assert_eq!(json!({
+        "service_id": "mysql",
+        "plan_id": "mysql_free",
+        "dashboard_url": "http://dashboard/i1",
+        "parameters": { "size": 1 },
+    }), body, "[Found] body");
+}
+
+#[actix_rt::test]
+async fn fetch_instance_not_retrievable() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(TestBackend::sync())))
+    ).await;
+
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(json!("Service 'mysql' does not support fetching instances"), response["description"]);
+}
+
+#[actix_rt::test]
+async fn fetch_instance_in_progress() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog_with(retrievable), common::broker(TestBackend::asynchronous())))
+    ).await;
+
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Provisioning] status");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/last_operation").to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Updating] status");
+    assert_eq!(json!("ConcurrencyError"), body["error"], "[Updating] error");
+}
+
+#[actix_rt::test]
+async fn fetch_binding() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog_with(retrievable), common::broker(TestBackend::sync())))
+    ).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/service_bindings/b1").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Missing] status");
+
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free", "parameters": { "read_only": true } });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/service_bindings/b1").to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Found] status");
+    assert_eq!(json!({
+        "credentials": { "username": "b1" },
+        "parameters": { "read_only": true },
+    }), body, "[Found] body");
+}
+
+#[actix_rt::test]
+async fn fetch_binding_endpoints() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog_with(retrievable), common::broker(TestBackend::sync())))
+    ).await;
+    let endpoints = json!([
+        { "host": "db.local", "ports": ["3306"], "protocol": "tcp" },
+        { "host": "10.0.0.1", "ports": ["9000-9010", "53"], "protocol": "udp" },
+    ]);
+
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free", "parameters": { "endpoints": endpoints } });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Bind] status");
+    assert_eq!(endpoints, response["endpoints"], "[Bind] endpoints");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/service_bindings/b1").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Fetch] status");
+    assert_eq!(endpoints, response["endpoints"], "[Fetch] endpoints");
+}
+
+#[actix_rt::test]
+async fn fetch_binding_not_retrievable() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(TestBackend::sync())))
+    ).await;
+
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/service_bindings/b1").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status);
+    assert_eq!(json!("Service 'mysql' does not support fetching bindings"), response["description"]);
+}
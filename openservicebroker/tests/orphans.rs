@@ -0,0 +1,123 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::store;
+use osb::orphans::OrphanCleanup;
+use common::TestBackend;
+
+use std::time::Duration;
+
+use actix_web::{test, App, dev::Service, http::StatusCode};
+use serde_json::json;
+
+#[actix_rt::test]
+async fn provision_failure() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    backend.fail_next();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status, "[Provision] status");
+    let orphan = broker.store().get_instance(&"i1".parse().unwrap()).unwrap().expect("[Provision] orphan");
+    assert!(orphan.orphaned_at().is_some(), "[Provision] orphaned_at");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/last_operation").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Last operation] status");
+    assert_eq!(json!("failed"), response["state"], "[Last operation] state");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Mitigation] status");
+    assert!(broker.store().get_instance(&"i1".parse().unwrap()).unwrap().is_none(), "[Mitigation] removed");
+
+    assert_eq!(2, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn provision_retry() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    backend.fail_next();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Retry] status");
+    let instance = broker.store().get_instance(&"i1".parse().unwrap()).unwrap().expect("[Retry] instance");
+    assert!(instance.orphaned_at().is_none(), "[Retry] orphaned_at");
+}
+
+#[actix_rt::test]
+async fn provision_dropped() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let _gate = backend.hold();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let mut request = Box::pin(app.call(req));
+    assert!(futures::poll!(&mut request).is_pending(), "waits for backend");
+    drop(request);
+
+    let orphan = broker.store().get_instance(&"i1".parse().unwrap()).unwrap().expect("orphan");
+    assert!(orphan.orphaned_at().is_some(), "orphaned_at");
+    assert!(!broker.locks().is_locked(&"i1".parse().unwrap()), "lock released");
+}
+
+#[actix_rt::test]
+async fn cleanup() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    backend.fail_next();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2").set_json(&body).to_request();
+    common::call(&mut app, req).await;
+    backend.fail_next();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2/service_bindings/b1").set_json(&body).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status, "[Bind] status");
+    // Binding left on an orphaned instance, MUST be removed along with it
+    broker.store().put_binding(store::BindingRecord::new("i1".parse().unwrap(), "b2".parse().unwrap(), "mysql".parse().unwrap(), "mysql_free".parse().unwrap())).unwrap();
+
+    let mut cleanup = OrphanCleanup::new(broker.clone());
+    let report = cleanup.run().await.unwrap();
+    assert!(report.is_empty(), "[Grace period] report: {}", report);
+
+    *cleanup.grace_period_mut() = Duration::from_secs(0);
+    let report = cleanup.run().await.unwrap();
+    assert_eq!(&["i1"], report.instances(), "[Cleanup] instances");
+    assert_eq!(1, report.bindings().len(), "[Cleanup] bindings");
+    assert!(report.failures().is_empty(), "[Cleanup] failures: {}", report);
+    assert!(broker.store().get_instance(&"i1".parse().unwrap()).unwrap().is_none(), "[Cleanup] i1");
+    assert!(broker.store().list_bindings(&"i1".parse().unwrap()).unwrap().is_empty(), "[Cleanup] b2");
+    assert!(broker.store().get_instance(&"i2".parse().unwrap()).unwrap().is_some(), "[Cleanup] i2");
+    assert!(broker.store().list_bindings(&"i2".parse().unwrap()).unwrap().is_empty(), "[Cleanup] b1");
+
+    let report = cleanup.run().await.unwrap();
+    assert!(report.is_empty(), "[Again] report: {}", report);
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::client::{Client, ClientError};
+use osb::platform::{Platform, PlatformKind};
+use osb::service::ServiceError;
+use common::TestBackend;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use actix_web::{test, web, App, HttpResponse};
+use serde_json::json;
+
+fn client(addr: std::net::SocketAddr) -> Client {
+    let mut client = Client::new(format!("http://{}", addr));
+    *client.poll_interval_mut() = Duration::from_millis(10);
+    client
+}
+
+#[actix_rt::test]
+async fn platform_lifecycle() {
+    for &kind in &[PlatformKind::CloudFoundry, PlatformKind::Kubernetes] {
+        let backend = TestBackend::asynchronous();
+        let broker = common::broker(backend.clone());
+        let server_broker = broker.clone();
+        let server = test::start(move || {
+            let catalog = common::catalog_with(|catalog| {
+                let service = &mut catalog.services_mut()[0];
+                *service.instances_retrievable_mut() = Some(true);
+                *service.bindings_retrievable_mut() = Some(true);
+            });
+            App::new()
+                .service(osb::new_broker_scope("", catalog, server_broker.clone()))
+        });
+        let mut platform = Platform::new(kind, client(server.addr()));
+
+        let instance = platform.provision(&"mysql".parse().unwrap(), &"mysql_free".parse().unwrap(), Some(json!({ "size": 1 }))).await.unwrap();
+        let binding = platform.bind(&instance, None).await.unwrap();
+        assert!(binding.response().credentials().is_some(), "[{:?}] credentials", kind);
+        platform.unbind(&binding).await.unwrap();
+        platform.deprovision(&instance).await.unwrap();
+
+        platform.assert_invariants();
+        assert!(platform.mitigations().is_empty(), "[{:?}] mitigations", kind);
+        assert!(broker.store().get_instance(instance.instance_id()).unwrap().is_none(), "[{:?}] removed", kind);
+    }
+}
+
+#[actix_rt::test]
+async fn platform_orphan_mitigation() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let server_broker = broker.clone();
+    let server = test::start(move || {
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), server_broker.clone()))
+    });
+    let mut platform = Platform::new(PlatformKind::CloudFoundry, client(server.addr()));
+
+    backend.fail_next();
+    let error = platform.provision(&"mysql".parse().unwrap(), &"mysql_free".parse().unwrap(), None).await.expect_err("[Provision] MUST fail");
+    assert!(matches!(error.downcast_ref(), Some(ClientError::UnexpectedStatus(status, _)) if status.as_u16() == 500), "[Provision] error: {}", error);
+    assert_eq!(1, platform.mitigations().len(), "[Provision] mitigations");
+
+    let error = platform.provision(&"mysql".parse().unwrap(), &"unknown".parse().unwrap(), None).await.expect_err("[Unknown plan] MUST fail");
+    assert!(error.downcast_ref::<ServiceError>().is_none(), "[Unknown plan] error: {}", error);
+    assert_eq!(1, platform.mitigations().len(), "[Unknown plan] mitigations");
+
+    platform.assert_invariants();
+    assert_eq!(2, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn platform_maximum_polling_duration() {
+    let catalog = common::catalog_with(|catalog| {
+        *catalog.services_mut()[0].plans_mut()[0].maximum_polling_duration_mut() = Some(0);
+    });
+    let catalog = serde_json::to_value(catalog.get_catalog().unwrap().as_ref()).unwrap();
+    let deletes = Arc::new(AtomicUsize::new(0));
+    let server_deletes = deletes.clone();
+    let server = test::start(move || {
+        let deletes = server_deletes.clone();
+        let catalog = catalog.clone();
+        App::new()
+            .route("/v2/catalog", web::get().to(move || HttpResponse::Ok().json(catalog.clone())))
+            .route("/v2/service_instances/{instance_id}", web::put().to(|_: web::Bytes| HttpResponse::Accepted().json(json!({}))))
+            .route("/v2/service_instances/{instance_id}", web::delete().to(move || {
+                deletes.fetch_add(1, Ordering::SeqCst);
+                HttpResponse::Ok().json(json!({}))
+            }))
+            .route("/v2/service_instances/{instance_id}/last_operation", web::get().to(|| {
+                HttpResponse::Ok().json(json!({ "state": "in progress" }))
+            }))
+    });
+    let mut platform = Platform::new(PlatformKind::Kubernetes, client(server.addr()));
+
+    let error = platform.provision(&"mysql".parse().unwrap(), &"mysql_free".parse().unwrap(), None).await.expect_err("[Provision] MUST time out");
+    assert_eq!(Some(&ClientError::Timeout(Duration::from_secs(0))), error.downcast_ref(), "[Provision] error: {:#}", error);
+    assert_eq!(1, platform.mitigations().len(), "[Provision] mitigations");
+    assert_eq!(1, deletes.load(Ordering::SeqCst), "[Provision] deprovisioned");
+    platform.assert_invariants();
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use openservicebroker as osb;
+use common::TestBackend;
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::json;
+
+fn provision_body(plan_id: &str) -> serde_json::Value {
+    json!({
+        "service_id": "mysql",
+        "plan_id": plan_id,
+        "context": { "platform": "cloudfoundry" },
+        "parameters": { "size": 1 },
+    })
+}
+
+#[actix_rt::test]
+async fn provision() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("mysql_free")).to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Create] status");
+    assert_eq!(json!({ "dashboard_url": "http://dashboard/i1" }), body, "[Create] body");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("mysql_free")).to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Identical] status");
+    assert_eq!(json!({ "dashboard_url": "http://dashboard/i1" }), body, "[Identical] body");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("mysql_small")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CONFLICT, status, "[Conflict] status");
+
+    assert_eq!(1, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn provision_invalid() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("pgsql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::BAD_REQUEST, status, "[Unknown plan] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/%20").set_json(&provision_body("mysql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::BAD_REQUEST, status, "[Invalid ID] status");
+
+    assert_eq!(0, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn provision_async() {
+    let backend = TestBackend::asynchronous();
+    let broker = common::broker(backend.clone());
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    ).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("mysql_free")).to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Sync] status");
+    assert_eq!(json!("AsyncRequired"), body["error"], "[Sync] error");
+    let orphan = broker.store().get_instance(&"i1".parse().unwrap()).unwrap().expect("[Sync] orphan");
+    assert!(orphan.orphaned_at().is_some(), "[Sync] orphaned_at");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&provision_body("mysql_free")).to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::ACCEPTED, status, "[Async] status");
+    assert_eq!(json!("provision"), body["operation"], "[Async] operation");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&provision_body("mysql_free")).to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::ACCEPTED, status, "[In progress] status");
+    assert_eq!(json!("provision"), body["operation"], "[In progress] operation");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/last_operation?operation=provision").to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Last operation] status");
+    assert_eq!(json!({ "state": "succeeded" }), body, "[Last operation] body");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1?accepts_incomplete=true").set_json(&provision_body("mysql_free")).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Identical] status");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Sync deprovision] status");
+    let instance = broker.store().get_instance(&"i1".parse().unwrap()).unwrap().expect("[Sync deprovision] instance");
+    assert!(instance.operation().is_some_and(|operation| operation.in_progress()), "[Sync deprovision] operation tracked");
+
+    assert_eq!(3, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn update() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1").set_json(&json!({ "service_id": "mysql" })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Missing] status");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("mysql_free")).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1").set_json(&json!({ "service_id": "mysql", "parameters": { "size": 2 } })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Parameters] status");
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1").set_json(&json!({ "service_id": "mysql", "plan_id": "mysql_small" })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Plan not updateable] status");
+}
+
+#[actix_rt::test]
+async fn deprovision() {
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&provision_body("mysql_free")).to_request();
+    common::call(&mut app, req).await;
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, body) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Delete] status");
+    assert_eq!(json!({}), body, "[Delete] body");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::GONE, status, "[Gone] status");
+
+    assert_eq!(2, backend.calls(), "backend.calls");
+}
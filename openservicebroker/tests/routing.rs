@@ -0,0 +1,107 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::{model, service, store};
+use osb::router::ServiceRouter;
+use osb::service::Completion;
+
+use actix_web::{test, App, http::StatusCode};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Backend tagging its responses with its name
+struct Named(&'static str);
+
+#[async_trait(?Send)]
+impl service::ServiceBroker for Named {
+    async fn provision(&self, _instance_id: &model::InstanceId, _request: &model::ProvisionRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let mut response = model::ProvisionResponse::new();
+        *response.dashboard_url_mut() = Some(format!("http://{}", self.0));
+        Ok(Completion::Sync(response))
+    }
+
+    async fn update(&self, _instance: &store::InstanceRecord, _request: &model::UpdateRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        Ok(Completion::Sync(model::ProvisionResponse::new()))
+    }
+
+    async fn bind(&self, _instance: &store::InstanceRecord, _binding_id: &model::BindingId, _request: &model::BindRequest, _accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        let mut response = model::BindingResponse::new();
+        *response.credentials_mut() = Some(json!({ "backend": self.0 }));
+        Ok(Completion::Sync(response))
+    }
+}
+
+fn router() -> ServiceRouter {
+    let mut router = ServiceRouter::new();
+    router.add_service("mysql".parse().unwrap(), Named("mysql"))
+          .add_service("pgsql".parse().unwrap(), Named("pgsql"))
+          .add_plan("pgsql_small".parse().unwrap(), Named("pgsql-dedicated"));
+    router
+}
+
+#[actix_rt::test]
+async fn routing() {
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_router_scope("", common::catalog(), router()).unwrap())
+    ).await;
+
+    for (instance_id, service_id, plan_id, backend) in &[
+        ("i1", "mysql", "mysql_free",  "mysql"),
+        ("i2", "pgsql", "pgsql_free",  "pgsql"),
+        ("i3", "pgsql", "pgsql_small", "pgsql-dedicated"),
+    ] {
+        let body = json!({ "service_id": service_id, "plan_id": plan_id });
+        let req = test::TestRequest::put().uri(&format!("/v2/service_instances/{}", instance_id)).set_json(&body).to_request();
+        let (status, response) = common::call(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, status, "[{}] provision status", instance_id);
+        assert_eq!(json!(format!("http://{}", backend)), response["dashboard_url"], "[{}] provision backend", instance_id);
+
+        let req = test::TestRequest::put().uri(&format!("/v2/service_instances/{}/service_bindings/b1", instance_id)).set_json(&body).to_request();
+        let (status, response) = common::call(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, status, "[{}] bind status", instance_id);
+        assert_eq!(json!(backend), response["credentials"]["backend"], "[{}] bind backend", instance_id);
+    }
+}
+
+#[actix_rt::test]
+async fn routing_plan_update() {
+    let catalog = common::catalog_with(|catalog| {
+        for service in catalog.services_mut() {
+            *service.plan_updateable_mut() = Some(true);
+        }
+    });
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_router_scope("", catalog, router()).unwrap())
+    ).await;
+
+    for (instance_id, service_id, plan_id) in &[("i1", "mysql", "mysql_free"), ("i2", "pgsql", "pgsql_free")] {
+        let body = json!({ "service_id": service_id, "plan_id": plan_id });
+        let req = test::TestRequest::put().uri(&format!("/v2/service_instances/{}", instance_id)).set_json(&body).to_request();
+        common::call(&mut app, req).await;
+    }
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1").set_json(&json!({ "service_id": "mysql", "plan_id": "mysql_small" })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Same backend] status");
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i2").set_json(&json!({ "service_id": "pgsql", "plan_id": "pgsql_small" })).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "[Other backend] status");
+    assert_eq!(json!("Plan 'pgsql_small' is served by another backend than plan 'pgsql_free'"), response["description"], "[Other backend] description");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2/service_bindings/b1").set_json(&json!({ "service_id": "pgsql", "plan_id": "pgsql_free" })).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Bind] status");
+    assert_eq!(json!("pgsql"), response["credentials"]["backend"], "[Bind] backend");
+}
+
+#[test]
+fn routing_missing_backend() {
+    let mut router = ServiceRouter::new();
+    router.add_service("mysql".parse().unwrap(), Named("mysql"));
+    let error = osb::new_router_scope("", common::catalog(), router).err().expect("pgsql MUST have a backend");
+    assert!(error.to_string().contains("no backend for plan 'pgsql_free' of service 'pgsql'"), "{}", error);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::{model, service, store};
+use osb::service::Completion;
+
+use actix_web::{test, App, http::StatusCode};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Only supports provisioning, binding and fetching instances
+struct MinimalBroker;
+
+#[async_trait(?Send)]
+impl service::ServiceBroker for MinimalBroker {
+    async fn provision(&self, instance_id: &model::InstanceId, _request: &model::ProvisionRequest, _accepts_incomplete: bool) -> Result<Completion<model::ProvisionResponse>> {
+        let mut response = model::ProvisionResponse::new();
+        *response.dashboard_url_mut() = Some(format!("http://minimal/{}", instance_id));
+        Ok(Completion::Sync(response))
+    }
+
+    async fn fetch_instance(&self, instance: &store::InstanceRecord) -> Result<model::InstanceResponse> {
+        Ok(instance.to_instance_response())
+    }
+
+    async fn bind(&self, _instance: &store::InstanceRecord, binding_id: &model::BindingId, _request: &model::BindRequest, _accepts_incomplete: bool) -> Result<Completion<model::BindingResponse>> {
+        let mut response = model::BindingResponse::new();
+        *response.credentials_mut() = Some(json!({ "token": binding_id.as_str() }));
+        Ok(Completion::Sync(response))
+    }
+}
+
+#[actix_rt::test]
+async fn service_broker() {
+    let catalog = common::catalog_with(|catalog| {
+        for service in catalog.services_mut() {
+            *service.instances_retrievable_mut() = Some(true);
+            *service.bindings_retrievable_mut() = Some(true);
+        }
+    });
+    let mut app = test::init_service(
+        App::new()
+            .service(osb::new_service_broker_scope("", catalog, MinimalBroker))
+    ).await;
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Provision] status");
+    assert_eq!(json!({ "dashboard_url": "http://minimal/i1" }), response, "[Provision] body");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Fetch instance] status");
+    assert_eq!(json!("http://minimal/i1"), response["dashboard_url"], "[Fetch instance] dashboard_url");
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1").set_json(&body).to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::CREATED, status, "[Bind] status");
+    assert_eq!(json!({ "credentials": { "token": "b1" } }), response, "[Bind] body");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1/service_bindings/b1").to_request();
+    let (status, response) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_IMPLEMENTED, status, "[Fetch binding] status");
+    assert_eq!(json!("Fetching bindings is not supported"), response["description"], "[Fetch binding] description");
+
+    let req = test::TestRequest::patch().uri("/v2/service_instances/i1").set_json(&json!({ "service_id": "mysql" })).to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_IMPLEMENTED, status, "[Update] status");
+
+    let req = test::TestRequest::delete().uri("/v2/service_instances/i1?service_id=mysql&plan_id=mysql_free").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_IMPLEMENTED, status, "[Deprovision] status");
+
+    let req = test::TestRequest::get().uri("/v2/service_instances/i1").to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::OK, status, "[Still provisioned] status");
+}
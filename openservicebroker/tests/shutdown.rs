@@ -0,0 +1,93 @@
+mod common;
+
+use openservicebroker as osb;
+use osb::model::LastOperationState;
+use osb::shutdown::{Drain, OperationRecovery};
+use common::TestBackend;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::json;
+
+#[actix_rt::test]
+async fn shutdown_drain() {
+    let backend = TestBackend::sync();
+    let broker = common::broker(backend.clone());
+    let drain = Arc::new(Drain::new());
+    let server_drain = drain.clone();
+    let server = test::start(move || {
+        let drain = server_drain.clone();
+        App::new()
+            .wrap_fn(move |req, srv| drain.guard(req, srv))
+            .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+    });
+    let url = |path: &str| format!("http://{}{}", server.addr(), path);
+    let client = awc::Client::default();
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    let gate = backend.hold();
+    let provision = client.put(url("/v2/service_instances/i1")).send_json(&body);
+    let shutdown = async {
+        while drain.in_flight() == 0 {
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+        drain.start();
+
+        let response = client.put(url("/v2/service_instances/i2")).send_json(&body).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status(), "[Draining] status");
+        assert!(response.headers().contains_key("Retry-After"), "[Draining] Retry-After");
+        let response = client.get(url("/v2/catalog")).send().await.unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "[Draining] catalog status");
+
+        assert!(!drain.wait(Duration::from_millis(50)).await, "[Running] wait");
+        drop(gate);
+        assert!(drain.wait(Duration::from_secs(5)).await, "[Completed] wait");
+    };
+    let (response, ()) = futures::join!(provision, shutdown);
+    assert_eq!(StatusCode::CREATED, response.unwrap().status(), "[Provision] status");
+    assert_eq!(0, drain.in_flight(), "in_flight");
+    assert_eq!(1, backend.calls(), "backend.calls");
+}
+
+#[actix_rt::test]
+async fn shutdown_operation_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let body = json!({ "service_id": "mysql", "plan_id": "mysql_free" });
+
+    // Operations started before shutdown, recorded into a persistent store
+    {
+        let mut broker = common::broker(TestBackend::asynchronous());
+        *broker.store_mut() = Arc::new(osb::store::providers::store::file_json(dir.path()).unwrap());
+        let mut app = test::init_service(
+            App::new()
+                .service(osb::new_broker_scope("", common::catalog(), broker.clone()))
+        ).await;
+        for instance in &["i1", "i2"] {
+            let req = test::TestRequest::put().uri(&format!("/v2/service_instances/{}?accepts_incomplete=true", instance)).set_json(&body).to_request();
+            let (status, _) = common::call(&mut app, req).await;
+            assert_eq!(StatusCode::ACCEPTED, status, "[Provision {}] status", instance);
+        }
+        let req = test::TestRequest::get().uri("/v2/service_instances/i2/last_operation").to_request();
+        let (_, response) = common::call(&mut app, req).await;
+        assert_eq!(json!("succeeded"), response["state"], "[Provision i2] state");
+        let req = test::TestRequest::delete().uri("/v2/service_instances/i2?service_id=mysql&plan_id=mysql_free&accepts_incomplete=true").to_request();
+        let (status, _) = common::call(&mut app, req).await;
+        assert_eq!(StatusCode::ACCEPTED, status, "[Deprovision] status");
+    }
+
+    // Resumed on next startup
+    let mut broker = common::broker(TestBackend::asynchronous());
+    *broker.store_mut() = Arc::new(osb::store::providers::store::file_json(dir.path()).unwrap());
+    let recovery = OperationRecovery::new(broker.clone());
+    let report = recovery.run().await.unwrap();
+    assert_eq!(2, report.completed().len(), "completed: {}", report);
+    assert!(report.failures().is_empty(), "failures: {}", report);
+
+    let instance = broker.store().get_instance(&"i1".parse().unwrap()).unwrap().expect("[Provision] instance");
+    assert_eq!(Some(LastOperationState::Succeeded), instance.operation().map(|operation| operation.state()), "[Provision] state");
+    assert!(broker.store().get_instance(&"i2".parse().unwrap()).unwrap().is_none(), "[Deprovision] removed");
+    assert!(osb::shutdown::running_operations(&broker).unwrap().is_empty(), "running operations");
+    assert!(recovery.run().await.unwrap().is_empty(), "[Rerun] report");
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "tls")]
+
+use openservicebroker as osb;
+use osb::server::ServerConfig;
+
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use actix_web::App;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn start(config: ServerConfig) -> (actix_server::Server, SocketAddr) {
+    let catalog = config.check().unwrap();
+    let tls = osb::tls::server_config(config.tls().unwrap()).unwrap();
+    let broker = config.open_broker(&catalog).unwrap();
+    let config = Arc::new(config);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let factory = move || {
+        let config = config.clone();
+        let catalog = config.catalog_provider(&catalog);
+        App::new()
+            .wrap_fn(move |req, srv| config.authorize(req, srv))
+            .service(osb::new_broker_scope("", catalog, broker.clone()))
+    };
+    let server = osb::tls::listen(actix_server::Server::build().workers(1), listener, factory, tls).unwrap().run();
+    (server, addr)
+}
+
+/// Sends a request over HTTPS, returning response status and body
+async fn send(addr: SocketAddr, client_certificate: bool, authorization: Option<&str>, method: &str, path: &str, body: Option<Value>) -> std::io::Result<(u16, Value)> {
+    let mut config = rustls::ClientConfig::new();
+    config.root_store.add_pem_file(&mut BufReader::new(std::fs::File::open("tests/tls/ca.pem")?)).unwrap();
+    if client_certificate {
+        let certificates = rustls::internal::pemfile::certs(&mut BufReader::new(std::fs::File::open("tests/tls/client.pem")?)).unwrap();
+        let key = rustls::internal::pemfile::pkcs8_private_keys(&mut BufReader::new(std::fs::File::open("tests/tls/client-key.pem")?)).unwrap();
+        config.set_single_client_cert(certificates, key[0].clone());
+    }
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    let mut stream = connector.connect(webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream).await?;
+
+    let authorization = authorization.map(|value| format!("Authorization: {}\r\n", value)).unwrap_or_default();
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\nX-Broker-API-Version: 2.16\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                          method, path, authorization, body.len(), body);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or_default();
+    Ok((status, serde_json::from_str(body).unwrap_or(Value::Null)))
+}
+
+async fn get_catalog(addr: SocketAddr, client_certificate: bool, authorization: Option<&str>) -> std::io::Result<(u16, Value)> {
+    send(addr, client_certificate, authorization, "GET", "/v2/catalog", None).await
+}
+
+fn config(require_client_certificate: bool) -> ServerConfig {
+    serde_json::from_value(json!({
+        "credentials": { "username": "admin", "password": "secret" },
+        "store": { "type": "memory" },
+        "tls": {
+            "certificate": "tests/tls/server.pem",
+            "private_key": "tests/tls/server-key.pem",
+            "client_ca": "tests/tls/ca.pem",
+            "require_client_certificate": require_client_certificate,
+            "identities": [{ "subject": "CN=cloudfoundry,OU=Platforms,O=Example", "name": "cf", "services": ["mysql"] }],
+        },
+    })).unwrap()
+}
+
+#[actix_rt::test]
+async fn tls_client_identity() {
+    let (server, addr) = start(config(false));
+
+    let (status, _) = get_catalog(addr, false, None).await.unwrap();
+    assert_eq!(401, status, "[Anonymous] status");
+
+    let (status, catalog) = get_catalog(addr, false, Some("Basic YWRtaW46c2VjcmV0")).await.unwrap();
+    assert_eq!(200, status, "[Basic] status");
+    assert_eq!(2, catalog["services"].as_array().map_or(0, Vec::len), "[Basic] services");
+
+    let (status, catalog) = get_catalog(addr, true, None).await.unwrap();
+    assert_eq!(200, status, "[Certificate] status");
+    assert_eq!(json!(["mysql"]), json!(catalog["services"].as_array().unwrap().iter().map(|service| service["id"].clone()).collect::<Vec<_>>()), "[Certificate] services");
+
+    server.stop(true).await;
+}
+
+#[actix_rt::test]
+async fn tls_client_identity_services() {
+    let (server, addr) = start(config(false));
+    let provision = |service: &str, plan: &str| json!({ "service_id": service, "plan_id": plan });
+
+    let (status, response) = send(addr, true, None, "PUT", "/v2/service_instances/i1", Some(provision("pgsql", "pgsql_free"))).await.unwrap();
+    assert_eq!(400, status, "[Provision pgsql] status");
+    assert_eq!(json!("Service 'pgsql' is not available to 'cf'"), response["description"], "[Provision pgsql] description");
+    let (status, _) = send(addr, false, Some("Basic YWRtaW46c2VjcmV0"), "PUT", "/v2/service_instances/i1", Some(provision("pgsql", "pgsql_free"))).await.unwrap();
+    assert_eq!(201, status, "[Basic provision pgsql] status");
+
+    let (status, _) = send(addr, true, None, "PATCH", "/v2/service_instances/i1", Some(provision("pgsql", "pgsql_small"))).await.unwrap();
+    assert_eq!(400, status, "[Update pgsql] status");
+    let (status, _) = send(addr, true, None, "PUT", "/v2/service_instances/i1/service_bindings/b1", Some(provision("pgsql", "pgsql_free"))).await.unwrap();
+    assert_eq!(400, status, "[Bind pgsql] status");
+
+    let (status, _) = send(addr, true, None, "PUT", "/v2/service_instances/i2", Some(provision("mysql", "mysql_free"))).await.unwrap();
+    assert_eq!(201, status, "[Provision mysql] status");
+
+    server.stop(true).await;
+}
+
+#[actix_rt::test]
+async fn tls_required_client_certificate() {
+    let (server, addr) = start(config(true));
+
+    assert!(get_catalog(addr, false, Some("Basic YWRtaW46c2VjcmV0")).await.is_err(), "[Anonymous] handshake MUST fail");
+    let (status, _) = get_catalog(addr, true, None).await.unwrap();
+    assert_eq!(200, status, "[Certificate] status");
+
+    server.stop(true).await;
+}
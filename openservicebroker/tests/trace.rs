@@ -0,0 +1,89 @@
+mod common;
+
+use openservicebroker as osb;
+use common::TestBackend;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use actix_web::{test, App, http::StatusCode};
+use serde_json::{json, Value};
+
+/// Log output shared with the test
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn events(&self) -> Vec<Value> {
+        self.0.lock().unwrap()
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).expect("Log line MUST be JSON"))
+            .collect()
+    }
+}
+
+#[actix_rt::test]
+async fn trace_request_span() {
+    let output = Output::default();
+    let writer = output.clone();
+    let subscriber = tracing_subscriber::fmt()
+                                         .json()
+                                         .flatten_event(true)
+                                         .with_current_span(true)
+                                         .with_span_list(false)
+                                         .with_writer(move || writer.clone())
+                                         .finish();
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let backend = TestBackend::sync();
+    let mut app = test::init_service(
+        App::new()
+            .wrap_fn(osb::trace::instrument)
+            .service(osb::new_broker_scope("", common::catalog(), common::broker(backend.clone())))
+    ).await;
+    let identity = format!("cloudfoundry {}", base64::encode(r#"{ "user_id": "683ea748" }"#));
+
+    let req = test::TestRequest::put().uri("/v2/service_instances/i1/service_bindings/b1")
+                                      .header("X-Broker-API-Request-Identity", "e26cfe3b")
+                                      .header("X-Broker-API-Originating-Identity", identity.as_str())
+                                      .set_json(&json!({ "service_id": "mysql", "plan_id": "mysql_free" }))
+                                      .to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::NOT_FOUND, status, "[Bind] status");
+
+    backend.fail_next();
+    let req = test::TestRequest::put().uri("/v2/service_instances/i2")
+                                      .set_json(&json!({ "service_id": "mysql", "plan_id": "mysql_free" }))
+                                      .to_request();
+    let (status, _) = common::call(&mut app, req).await;
+    assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status, "[Provision] status");
+
+    let events = output.events();
+    let completed = events.iter().find(|event| event["message"] == "Request completed").expect("[Bind] event");
+    assert_eq!(json!(404), completed["status"], "[Bind] status field");
+    let span = &completed["span"];
+    assert_eq!(json!("request"), span["name"], "[Bind] span");
+    assert_eq!(json!("PUT"), span["method"], "[Bind] method");
+    assert_eq!(json!("e26cfe3b"), span["request_identity"], "[Bind] request_identity");
+    assert_eq!(json!("i1"), span["instance_id"], "[Bind] instance_id");
+    assert_eq!(json!("b1"), span["binding_id"], "[Bind] binding_id");
+    assert_eq!(json!("mysql"), span["service_id"], "[Bind] service_id");
+    assert_eq!(json!("mysql_free"), span["plan_id"], "[Bind] plan_id");
+    assert_eq!(json!("cloudfoundry"), span["platform"], "[Bind] platform");
+    assert_eq!(json!("683ea748"), span["user"], "[Bind] user");
+
+    let failure = events.iter().find(|event| event["level"] == "ERROR" && event["error"].is_string()).expect("[Provision] error event");
+    assert_eq!(json!("Backend failure"), failure["error"], "[Provision] error");
+    assert_eq!(json!("i2"), failure["span"]["instance_id"], "[Provision] instance_id");
+}